@@ -14,12 +14,31 @@ fn criterion_benchmark(c: &mut Criterion) {
         addresses: fixture.addresses().to_vec(),
         verbose: false,
         file_offsets: false,
+        module_base: false,
+        translate_offsets: false,
+        sections: Vec::new(),
         inline_frames: false,
         arch: None,
         uuid: None,
         format: OutputFormat::Json,
         input: None,
         debug_dirs: Vec::new(),
+        registers: Vec::new(),
+        image: None,
+        kernel_oops: false,
+        server_stdio: false,
+        module_dirs: Vec::new(),
+        kernel_symbol: None,
+        pac_mask: None,
+        va_bits: None,
+        return_addresses: false,
+        exact_pc_frames: Vec::new(),
+        slide: None,
+        full_path: false,
+        inline_delimiter: None,
+        dsym_dirs: Vec::new(),
+        locators: Vec::new(),
+        offline: false,
     };
 
     c.bench_function("symbolize_64_addresses", |b| {
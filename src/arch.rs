@@ -0,0 +1,360 @@
+// Architecture names. `object::Architecture` loses Mach-O subtypes (arm64e,
+// armv7k), byte order, and anything it does not model (arm64_32, PowerPC
+// Mach-O, LoongArch), so names are read from the file headers instead. Every
+// architecture has one canonical name, Apple's spelling where Apple has one;
+// `-a` filters are matched against it through a table of aliases.
+use object::read::elf::FileHeader;
+use object::read::macho::MachHeader;
+use object::{elf, macho, Architecture, Endianness, FileKind};
+
+/// The canonical architecture name of the Mach-O or ELF image in `data`, or
+/// `None` for other formats.
+pub(crate) fn file_arch_name(data: &[u8]) -> Option<String> {
+    match FileKind::parse(data).ok()? {
+        FileKind::MachO32 => {
+            let header = macho::MachHeader32::<Endianness>::parse(data, 0).ok()?;
+            let endian = header.endian().ok()?;
+            Some(macho_arch_name(
+                header.cputype(endian),
+                header.cpusubtype(endian),
+            ))
+        }
+        FileKind::MachO64 => {
+            let header = macho::MachHeader64::<Endianness>::parse(data, 0).ok()?;
+            let endian = header.endian().ok()?;
+            Some(macho_arch_name(
+                header.cputype(endian),
+                header.cpusubtype(endian),
+            ))
+        }
+        FileKind::Elf32 => {
+            let header = elf::FileHeader32::<Endianness>::parse(data).ok()?;
+            let endian = header.endian().ok()?;
+            Some(elf_arch_name(
+                header.e_machine(endian),
+                false,
+                header.is_little_endian(),
+            ))
+        }
+        FileKind::Elf64 => {
+            let header = elf::FileHeader64::<Endianness>::parse(data).ok()?;
+            let endian = header.endian().ok()?;
+            Some(elf_arch_name(
+                header.e_machine(endian),
+                true,
+                header.is_little_endian(),
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// The architecture in a dyld shared cache's magic (`dyld_v1  arm64e`).
+pub(crate) fn dyld_cache_arch_name(data: &[u8]) -> Option<String> {
+    let magic = std::str::from_utf8(data.get(..16)?).ok()?;
+    let arch = magic
+        .strip_prefix("dyld_v1")?
+        .trim_matches(|c: char| c == ' ' || c == '\0');
+    (!arch.is_empty()).then(|| arch.to_string())
+}
+
+/// The name of an `object::Architecture`, for objects whose header is not at
+/// hand.
+pub(crate) fn architecture_name(architecture: Architecture, little_endian: bool) -> String {
+    let name = match (architecture, little_endian) {
+        (Architecture::Aarch64, true) => "arm64",
+        (Architecture::Aarch64, false) => "aarch64_be",
+        (Architecture::Arm, true) => "arm",
+        (Architecture::Arm, false) => "armeb",
+        (Architecture::Avr, _) => "avr",
+        (Architecture::Bpf, true) => "bpf",
+        (Architecture::Bpf, false) => "bpfeb",
+        (Architecture::I386, _) => "i386",
+        (Architecture::X86_64, _) => "x86_64",
+        (Architecture::X86_64_X32, _) => "x32",
+        (Architecture::Hexagon, _) => "hexagon",
+        (Architecture::Mips, true) => "mipsel",
+        (Architecture::Mips, false) => "mips",
+        (Architecture::Mips64, true) => "mips64el",
+        (Architecture::Mips64, false) => "mips64",
+        (Architecture::Msp430, _) => "msp430",
+        (Architecture::PowerPc, true) => "ppcle",
+        (Architecture::PowerPc, false) => "ppc",
+        (Architecture::PowerPc64, true) => "ppc64le",
+        (Architecture::PowerPc64, false) => "ppc64",
+        (Architecture::Riscv32, _) => "riscv32",
+        (Architecture::Riscv64, _) => "riscv64",
+        (Architecture::S390x, _) => "s390x",
+        (Architecture::Sparc64, _) => "sparc64",
+        (Architecture::Wasm32, _) => "wasm32",
+        _ => "unknown",
+    };
+    name.to_string()
+}
+
+pub(crate) fn macho_arch_name(cputype: u32, cpusubtype: u32) -> String {
+    let cpusubtype = cpusubtype & !macho::CPU_SUBTYPE_MASK;
+    let name = match cputype {
+        macho::CPU_TYPE_ARM64 => match cpusubtype {
+            macho::CPU_SUBTYPE_ARM64E => "arm64e",
+            _ => "arm64",
+        },
+        macho::CPU_TYPE_ARM64_32 => "arm64_32",
+        macho::CPU_TYPE_ARM => match cpusubtype {
+            macho::CPU_SUBTYPE_ARM_V4T => "armv4t",
+            macho::CPU_SUBTYPE_ARM_V5TEJ => "armv5",
+            macho::CPU_SUBTYPE_ARM_V6 => "armv6",
+            macho::CPU_SUBTYPE_ARM_V6M => "armv6m",
+            macho::CPU_SUBTYPE_ARM_V7 => "armv7",
+            macho::CPU_SUBTYPE_ARM_V7F => "armv7f",
+            macho::CPU_SUBTYPE_ARM_V7S => "armv7s",
+            macho::CPU_SUBTYPE_ARM_V7K => "armv7k",
+            macho::CPU_SUBTYPE_ARM_V7M => "armv7m",
+            macho::CPU_SUBTYPE_ARM_V7EM => "armv7em",
+            macho::CPU_SUBTYPE_ARM_V8 => "armv8",
+            _ => "arm",
+        },
+        macho::CPU_TYPE_X86_64 => match cpusubtype {
+            macho::CPU_SUBTYPE_X86_64_H => "x86_64h",
+            _ => "x86_64",
+        },
+        macho::CPU_TYPE_X86 => "i386",
+        macho::CPU_TYPE_POWERPC => match cpusubtype {
+            macho::CPU_SUBTYPE_POWERPC_750 => "ppc750",
+            macho::CPU_SUBTYPE_POWERPC_7400 => "ppc7400",
+            macho::CPU_SUBTYPE_POWERPC_7450 => "ppc7450",
+            macho::CPU_SUBTYPE_POWERPC_970 => "ppc970",
+            _ => "ppc",
+        },
+        macho::CPU_TYPE_POWERPC64 => "ppc64",
+        macho::CPU_TYPE_MIPS => "mips",
+        macho::CPU_TYPE_SPARC => "sparc",
+        macho::CPU_TYPE_MC680X0 => "m68k",
+        _ => return format!("cputype{cputype}_subtype{cpusubtype}"),
+    };
+    name.to_string()
+}
+
+// `object` does not know LoongArch (258) or 32-bit SPARC and s390, so the
+// machine number is mapped here directly.
+const EM_LOONGARCH: u16 = 258;
+
+pub(crate) fn elf_arch_name(machine: u16, is_64: bool, little_endian: bool) -> String {
+    let name = match (machine, is_64, little_endian) {
+        (elf::EM_AARCH64, _, true) => "arm64",
+        (elf::EM_AARCH64, _, false) => "aarch64_be",
+        (elf::EM_ARM, _, true) => "arm",
+        (elf::EM_ARM, _, false) => "armeb",
+        (elf::EM_386, _, _) => "i386",
+        (elf::EM_X86_64, true, _) => "x86_64",
+        (elf::EM_X86_64, false, _) => "x32",
+        (elf::EM_PPC, _, true) => "ppcle",
+        (elf::EM_PPC, _, false) => "ppc",
+        (elf::EM_PPC64, _, true) => "ppc64le",
+        (elf::EM_PPC64, _, false) => "ppc64",
+        (elf::EM_MIPS, false, true) => "mipsel",
+        (elf::EM_MIPS, false, false) => "mips",
+        (elf::EM_MIPS, true, true) => "mips64el",
+        (elf::EM_MIPS, true, false) => "mips64",
+        (elf::EM_RISCV, false, _) => "riscv32",
+        (elf::EM_RISCV, true, _) => "riscv64",
+        (EM_LOONGARCH, false, _) => "loongarch32",
+        (EM_LOONGARCH, true, _) => "loongarch64",
+        (elf::EM_S390, false, _) => "s390",
+        (elf::EM_S390, true, _) => "s390x",
+        (elf::EM_SPARC, _, _) => "sparc",
+        (elf::EM_SPARCV9, _, _) => "sparc64",
+        (elf::EM_HEXAGON, _, _) => "hexagon",
+        (elf::EM_MSP430, _, _) => "msp430",
+        (elf::EM_AVR, _, _) => "avr",
+        (elf::EM_BPF, _, true) => "bpf",
+        (elf::EM_BPF, _, false) => "bpfeb",
+        (elf::EM_68K, _, _) => "m68k",
+        _ => return format!("elf_machine{machine}"),
+    };
+    name.to_string()
+}
+
+/// Whether the architecture called `actual` satisfies an `-a` filter. Aliases
+/// (`aarch64`, `amd64`, `powerpc64le`) always match. A `lenient` match also
+/// lets a filter and an architecture that differ only in their subtype match
+/// (`arm64` and `arm64e`, `armv7` and `armv7k`); byte order never differs.
+pub(crate) fn arch_matches_filter(actual: &str, filter: &str, lenient: bool) -> bool {
+    let actual = canonical_arch(actual);
+    let filter = canonical_arch(filter);
+    actual == filter || (lenient && arch_family(&actual) == arch_family(&filter))
+}
+
+/// The address of an instruction inside the call that `return_address`
+/// returns from. Fixed-width instruction sets step back one instruction; MIPS
+/// also skips the branch delay slot. Elsewhere one byte back is enough, since
+/// only the instruction containing the address matters.
+pub(crate) fn previous_instruction(arch_name: &str, return_address: u64) -> u64 {
+    let canonical = canonical_arch(arch_name);
+    let (align, step) = match arch_family(&canonical) {
+        "arm64" | "arm6432" | "aarch64be" | "loongarch64" | "loongarch32" => (4, 4),
+        "ppc" | "ppcle" | "ppc64" | "ppc64le" => (4, 4),
+        "mips" | "mipsel" | "mips64" | "mips64el" => (4, 8),
+        // Thumb and compressed RISC-V instructions are 2 bytes; stepping back
+        // 2 stays inside a 4-byte call too. s390x instructions are 2 to 6
+        // bytes long, always ending on an even address.
+        "arm" | "armeb" | "riscv32" | "riscv64" | "s390" | "s390x" => (2, 2),
+        _ => (1, 1),
+    };
+    (return_address & !(align - 1)).saturating_sub(step)
+}
+
+/// The registers that hold the second argument of a call, which is where
+/// `objc_msgSend(self, _cmd, ...)` receives the selector. arm64_32 passes it
+/// in the low half of `x1`, which crash reports show as either name.
+pub(crate) fn selector_registers(arch_name: &str) -> &'static [&'static str] {
+    match arch_family(&canonical_arch(arch_name)) {
+        "arm64" => &["x1"],
+        "arm6432" => &["x1", "w1"],
+        "x8664" => &["rsi"],
+        "arm" => &["r1"],
+        _ => &[],
+    }
+}
+
+// Names are compared without case or punctuation, so `x86_64`, `X86-64` and
+// `x8664` are the same.
+fn normalize_arch(value: &str) -> String {
+    value
+        .chars()
+        .filter(|character| character.is_ascii_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect::<String>()
+}
+
+fn canonical_arch(value: &str) -> String {
+    let normalized = normalize_arch(value);
+    let canonical = match normalized.as_str() {
+        "aarch64" | "arm64le" => "arm64",
+        "aarch64be" | "arm64be" => "aarch64be",
+        "arm6432" | "aarch6432" => "arm6432",
+        "armel" | "armle" => "arm",
+        "armbe" => "armeb",
+        "amd64" | "x64" | "x8664le" => "x8664",
+        "x86" | "i486" | "i586" | "i686" | "ia32" => "i386",
+        "x8664x32" | "x32" => "x32",
+        "powerpc" | "ppc32" | "powerpc32" => "ppc",
+        "powerpcle" | "ppc32le" => "ppcle",
+        "powerpc64" | "ppc64be" => "ppc64",
+        "powerpc64le" => "ppc64le",
+        "mipsle" | "mips32el" | "mips32le" => "mipsel",
+        "mips32" | "mipsbe" => "mips",
+        "mips64le" => "mips64el",
+        "mips64be" => "mips64",
+        "rv32" => "riscv32",
+        "rv64" => "riscv64",
+        "loong64" | "la64" => "loongarch64",
+        "loong32" | "la32" => "loongarch32",
+        "systemz" | "s390x64" => "s390x",
+        "sparcv9" => "sparc64",
+        "m68000" | "mc68000" | "mc680x0" => "m68k",
+        "ebpf" | "bpfel" => "bpf",
+        other => other,
+    };
+    canonical.to_string()
+}
+
+// The architecture without its subtype: `arm64e` is an `arm64`, `armv7k` an
+// `arm`, `ppc970` a `ppc`.
+fn arch_family(canonical: &str) -> &str {
+    match canonical {
+        "arm64e" => "arm64",
+        "x8664h" => "x8664",
+        name if name.starts_with("armv") => "arm",
+        name if name.starts_with("ppc") && name[3..].chars().all(|c| c.is_ascii_digit()) => {
+            match &name[3..] {
+                "64" => "ppc64",
+                _ => "ppc",
+            }
+        }
+        name => name,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_macho_subtypes_and_missing_architectures() {
+        assert_eq!(macho_arch_name(macho::CPU_TYPE_ARM64_32, 1), "arm64_32");
+        assert_eq!(
+            macho_arch_name(
+                macho::CPU_TYPE_ARM64,
+                macho::CPU_SUBTYPE_ARM64E | macho::CPU_SUBTYPE_PTRAUTH_ABI
+            ),
+            "arm64e"
+        );
+        assert_eq!(
+            macho_arch_name(macho::CPU_TYPE_ARM, macho::CPU_SUBTYPE_ARM_V7K),
+            "armv7k"
+        );
+        assert_eq!(macho_arch_name(macho::CPU_TYPE_POWERPC64, 0), "ppc64");
+        assert_eq!(macho_arch_name(99, 3), "cputype99_subtype3");
+    }
+
+    #[test]
+    fn names_elf_machines_with_byte_order() {
+        assert_eq!(elf_arch_name(elf::EM_PPC64, true, true), "ppc64le");
+        assert_eq!(elf_arch_name(elf::EM_PPC64, true, false), "ppc64");
+        assert_eq!(elf_arch_name(elf::EM_MIPS, false, true), "mipsel");
+        assert_eq!(elf_arch_name(elf::EM_RISCV, true, true), "riscv64");
+        assert_eq!(elf_arch_name(EM_LOONGARCH, true, true), "loongarch64");
+        assert_eq!(elf_arch_name(elf::EM_S390, true, false), "s390x");
+        assert_eq!(elf_arch_name(elf::EM_AARCH64, true, false), "aarch64_be");
+    }
+
+    #[test]
+    fn matches_filters_through_aliases_and_families() {
+        assert!(arch_matches_filter("arm64", "aarch64", false));
+        assert!(arch_matches_filter("x86_64", "AMD64", false));
+        assert!(arch_matches_filter("ppc64le", "powerpc64le", false));
+        assert!(arch_matches_filter("loongarch64", "loong64", false));
+        assert!(arch_matches_filter("arm64_32", "arm64_32", false));
+        assert!(!arch_matches_filter("arm64_32", "arm64", true));
+        assert!(!arch_matches_filter("ppc64le", "ppc64", true));
+        assert!(!arch_matches_filter("mipsel", "mips", true));
+
+        assert!(!arch_matches_filter("arm64e", "arm64", false));
+        assert!(arch_matches_filter("arm64e", "arm64", true));
+        assert!(arch_matches_filter("armv7k", "armv7", true));
+        assert!(arch_matches_filter("x86_64h", "x86_64", true));
+    }
+
+    #[test]
+    fn steps_back_to_the_call_instruction() {
+        assert_eq!(previous_instruction("x86_64", 0x1005), 0x1004);
+        assert_eq!(previous_instruction("arm64e", 0x1004), 0x1000);
+        assert_eq!(previous_instruction("armv7", 0x1003), 0x1000);
+        assert_eq!(previous_instruction("mipsel", 0x1008), 0x1000);
+        assert_eq!(previous_instruction("riscv64", 0x1004), 0x1002);
+        assert_eq!(previous_instruction("x86_64", 0), 0);
+    }
+
+    #[test]
+    fn names_the_selector_register() {
+        assert_eq!(selector_registers("arm64e"), ["x1"]);
+        assert_eq!(selector_registers("arm64_32"), ["x1", "w1"]);
+        assert_eq!(selector_registers("x86_64h"), ["rsi"]);
+        assert_eq!(selector_registers("armv7k"), ["r1"]);
+        assert!(selector_registers("i386").is_empty());
+    }
+
+    #[test]
+    fn reads_dyld_cache_magic() {
+        assert_eq!(
+            dyld_cache_arch_name(b"dyld_v1  arm64e\0rest").as_deref(),
+            Some("arm64e")
+        );
+        assert_eq!(
+            dyld_cache_arch_name(b"dyld_v1arm64_32\0").as_deref(),
+            Some("arm64_32")
+        );
+        assert_eq!(dyld_cache_arch_name(b"\x7fELF"), None);
+    }
+}
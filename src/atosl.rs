@@ -1,17 +1,25 @@
+use crate::arch;
+use crate::debug_map::{self, DebugMap, DebugMapObjects};
 use crate::demangle;
+use crate::dyld_cache;
+use crate::kernel;
+use crate::locate::{self, DirectoryLocator, ModuleIdentity, SymbolLocator};
+use crate::relocate::{relocated_section_data, SectionLayout};
+use crate::stdio;
+use crate::stubs::{self, Stub};
+use crate::symbols::SymbolTable;
 use anyhow::{anyhow, Context as _, Result};
 use gimli::{EndianSlice, RunTimeEndian};
-use object::macho;
 use object::read::macho::{FatArch, FatHeader};
-use object::{Object, ObjectSection, ObjectSegment, SymbolMap, SymbolMapName};
+use object::{Object, ObjectSection, ObjectSegment, SymbolMapName};
 use serde::Serialize;
 use std::borrow;
-use std::env;
 use std::fs;
 use std::io::{self, BufRead, BufReader};
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-type DwarfContext<'data> = addr2line::Context<EndianSlice<'data, RunTimeEndian>>;
+pub(crate) type DwarfContext<'data> = addr2line::Context<EndianSlice<'data, RunTimeEndian>>;
 
 #[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize)]
 #[serde(rename_all = "snake_case")]
@@ -29,6 +37,13 @@ pub enum OutputFormat {
 pub enum ResolverKind {
     Dwarf,
     SymbolTable,
+    /// The ELF dynamic symbol table (`.dynsym`), used when `.symtab` is stripped.
+    DynamicSymbolTable,
+    /// A Mach-O symbol stub or ELF PLT entry, named after its callee.
+    Stub,
+    /// The DWARF line table placed the address, but no subprogram DIE covers
+    /// it (assembly, compiler thunks), so the name comes from the symbol table.
+    DwarfLineSymbolTable,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -38,6 +53,18 @@ pub struct SymbolizeOptions {
     pub addresses: Vec<u64>,
     pub verbose: bool,
     pub file_offsets: bool,
+    /// For ELF objects, `load_address` is the module's load base (where its
+    /// lowest `PT_LOAD` segment is mapped) rather than the address of `.text`.
+    pub module_base: bool,
+    /// Treat `address - load_address` as an offset into the file and map it to
+    /// a virtual address through the ELF program headers or Mach-O segments.
+    pub translate_offsets: bool,
+    /// Load addresses of a relocatable object's sections, as `(name, address)`
+    /// pairs such as `(".text", 0xffffffffc0a00000)`. They are matched against
+    /// `address - load_address`, so absolute section addresses go with a
+    /// `load_address` of 0. Without any, addresses are offsets into the text
+    /// section.
+    pub sections: Vec<(String, u64)>,
     /// Expand inlined functions into the full call stack in text output (like
     /// `atos -i`). When false, only the outermost frame is printed. JSON output
     /// always carries the full inline chain under `inlined_by` regardless.
@@ -51,6 +78,59 @@ pub struct SymbolizeOptions {
     /// Extra roots to search for separate ELF debug files (`.gnu_debuglink` and
     /// `.build-id` layouts).
     pub debug_dirs: Vec<PathBuf>,
+    /// Register values from the crashing thread, as `(name, value)` pairs such
+    /// as `("x1", 0x1a2b3c)`. The selector argument register (`x1`, `rsi` or
+    /// `r1`) is used to name the `objc_msgSend` selector of the first frame.
+    pub registers: Vec<(String, u64)>,
+    /// Image to symbolize inside a dyld shared cache, by install name or file
+    /// name (`UIKitCore`). `uuid` selects the image by UUID instead.
+    pub image: Option<String>,
+    /// Read a Linux kernel oops, KASAN report or other splat from `input` (or
+    /// stdin) and decode its frames against `object_path` (vmlinux) and the
+    /// modules found under `module_dirs`.
+    pub kernel_oops: bool,
+    /// Answer requests read line by line from stdin, llvm-symbolizer style,
+    /// keeping the objects they name loaded; `object_path` is the object of
+    /// requests that name none.
+    pub server_stdio: bool,
+    /// Directories searched recursively for `<module>.ko` files.
+    pub module_dirs: Vec<PathBuf>,
+    /// A vmlinux symbol and its runtime address (`_text` from
+    /// `/proc/kallsyms`), used to derive the KASLR offset for raw addresses.
+    pub kernel_symbol: Option<(String, u64)>,
+    /// Pointer-authentication bits to clear from every address before lookup.
+    /// Overrides the mask derived from `va_bits` or the architecture.
+    pub pac_mask: Option<u64>,
+    /// Number of virtual-address bits; higher bits are cleared before lookup.
+    /// Without either option, arm64e images get 39 bits for a nonzero load
+    /// address below 2^39 and 47 above it, and keep every bit without one.
+    /// Code addresses on 32-bit ARM also lose the Thumb bit.
+    pub va_bits: Option<u32>,
+    /// Every address except the exact PCs is a return address from a caller
+    /// frame, and is looked up at the instruction before it (like lldb and
+    /// llvm-symbolizer). Frames still report the address as given.
+    pub return_addresses: bool,
+    /// Indices into `addresses` of exact PCs (the crashing frame), which are
+    /// looked up as given. Input addresses mark them with a `pc:` prefix.
+    pub exact_pc_frames: Vec<usize>,
+    /// The image's slide (`atos -s`): addresses are static addresses plus the
+    /// slide. Replaces `load_address` when set.
+    pub slide: Option<u64>,
+    /// Print source files with their full path in text output (`atos
+    /// -fullPath`). By default only the file name is printed, as atos does.
+    pub full_path: bool,
+    /// Join the inline frames of one address with this string instead of a
+    /// newline (`atos -d`).
+    pub inline_delimiter: Option<String>,
+    /// Extra roots searched recursively for the `.dSYM` bundle of a Mach-O
+    /// binary that carries no DWARF.
+    pub dsym_dirs: Vec<PathBuf>,
+    /// Extra symbol locators, asked after the built-in ones for the symbols of
+    /// a binary without DWARF or of the `uuid` searched for in a directory.
+    pub locators: Vec<Arc<dyn SymbolLocator>>,
+    /// Never ask the debuginfod servers in `DEBUGINFOD_URLS`; only local files
+    /// and caches are used.
+    pub offline: bool,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize)]
@@ -63,6 +143,9 @@ pub struct SelectedSlice {
 pub struct SourceLocation {
     pub file: String,
     pub line: u64,
+    /// 1-based, when the line table records one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<u64>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize)]
@@ -74,6 +157,10 @@ pub struct InlineFrame {
 #[derive(Clone, Debug, Eq, PartialEq, Serialize)]
 pub struct SymbolizedFrame {
     pub requested_address: u64,
+    /// `requested_address` with its PAC or Thumb bits cleared, when that
+    /// changed it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub canonical_address: Option<u64>,
     pub lookup_address: u64,
     pub symbol: String,
     pub object_name: String,
@@ -82,6 +169,10 @@ pub struct SymbolizedFrame {
     pub location: Option<SourceLocation>,
     #[serde(skip_serializing_if = "Vec::is_empty")]
     pub inlined_by: Vec<InlineFrame>,
+    /// The `objc_msgSend` selector recovered from register state; only set on
+    /// the first frame, which is the one the registers belong to.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub selector: Option<String>,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Serialize)]
@@ -90,7 +181,13 @@ pub enum SymbolizeOutcome {
     Resolved(SymbolizedFrame),
     Unresolved {
         requested_address: u64,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        canonical_address: Option<u64>,
         error: String,
+        /// As on [`SymbolizedFrame`]: a crash in `objc_msgSend` on a stripped
+        /// or foreign address still names the message being sent.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        selector: Option<String>,
     },
 }
 
@@ -104,6 +201,7 @@ pub struct SymbolizeReport {
 
 struct FatSlice<'data> {
     object: object::File<'data, &'data [u8]>,
+    data: &'data [u8],
     selected_slice: SelectedSlice,
 }
 
@@ -111,18 +209,27 @@ struct ResolvedObject<'data> {
     object: object::File<'data, &'data [u8]>,
     object_name: String,
     selected_slice: Option<SelectedSlice>,
+    /// Canonical architecture name (`arm64e`, `armv7k`, `riscv64`).
+    arch_name: String,
+    /// Symbols kept outside the object itself (dyld shared cache locals).
+    extra_symbols: Vec<SymbolMapName<'data>>,
+    /// The object's own bytes, when it is a standalone image rather than part
+    /// of a dyld shared cache.
+    raw_data: Option<&'data [u8]>,
 }
 
 pub fn run(options: SymbolizeOptions) -> Result<i32> {
+    if options.kernel_oops {
+        return kernel::run(&options);
+    }
+    if options.server_stdio {
+        return stdio::run(&options);
+    }
+
     // Addresses given on the command line use the batch path.
     if !options.addresses.is_empty() {
         let report = symbolize_path(&options)?;
-        emit_report(
-            &report,
-            options.format,
-            options.verbose,
-            options.inline_frames,
-        );
+        emit_report(&report, &options);
         return Ok(0);
     }
 
@@ -140,12 +247,15 @@ pub fn symbolize_path(options: &SymbolizeOptions) -> Result<SymbolizeReport> {
             .addresses
             .iter()
             .copied()
-            .map(|requested_address| {
-                symbolizer.symbolize(
+            .enumerate()
+            .map(|(index, requested_address)| {
+                let outcome = symbolizer.symbolize(
                     options.load_address,
                     requested_address,
                     options.file_offsets,
-                )
+                    options.exact_pc_frames.contains(&index),
+                );
+                symbolizer.attach_selector(outcome, index == 0)
             })
             .collect();
 
@@ -165,29 +275,22 @@ fn run_streaming(options: &SymbolizeOptions) -> Result<i32> {
             if options.format == OutputFormat::Text && options.verbose {
                 emit_text_header(&object_path, selected_slice.as_ref());
             }
+            let mut first = true;
             for_each_input_address(options.input.as_deref(), |parsed| {
                 let outcome = symbolizer.symbolize_parsed(options, parsed);
-                emit_streaming_outcome(
-                    &outcome,
-                    options.format,
-                    options.verbose,
-                    options.inline_frames,
-                );
+                let outcome = symbolizer.attach_selector(outcome, first);
+                first = false;
+                emit_streaming_outcome(&outcome, options);
             })?;
             Ok(0)
         },
     )?
 }
 
-fn emit_streaming_outcome(
-    outcome: &SymbolizeOutcome,
-    format: OutputFormat,
-    verbose: bool,
-    inline_frames: bool,
-) {
-    match format {
+fn emit_streaming_outcome(outcome: &SymbolizeOutcome, options: &SymbolizeOptions) {
+    match options.format {
         OutputFormat::JsonLines => println!("{}", serde_json::to_string(outcome).unwrap()),
-        _ => emit_text_outcome(outcome, verbose, inline_frames),
+        _ => emit_text_outcome(outcome, options),
     }
 }
 
@@ -197,7 +300,8 @@ fn run_collected_input(options: &SymbolizeOptions) -> Result<i32> {
         |symbolizer, object_path, selected_slice| -> Result<SymbolizeReport> {
             let mut frames = Vec::new();
             for_each_input_address(options.input.as_deref(), |parsed| {
-                frames.push(symbolizer.symbolize_parsed(options, parsed));
+                let outcome = symbolizer.symbolize_parsed(options, parsed);
+                frames.push(symbolizer.attach_selector(outcome, frames.is_empty()));
             })?;
             Ok(SymbolizeReport {
                 object_path,
@@ -207,68 +311,247 @@ fn run_collected_input(options: &SymbolizeOptions) -> Result<i32> {
             })
         },
     )??;
-    emit_report(
-        &report,
-        options.format,
-        options.verbose,
-        options.inline_frames,
-    );
+    emit_report(&report, options);
     Ok(0)
 }
 
-struct Symbolizer<'a> {
-    object_name: &'a str,
+pub(crate) struct ObjectSymbolizer<'a> {
+    pub(crate) object_name: &'a str,
     context: Option<&'a DwarfContext<'a>>,
-    symbol_map: &'a SymbolMap<SymbolMapName<'a>>,
+    debug_map: Option<&'a DebugMap<'a>>,
+    symbol_table: &'a SymbolTable<'a>,
     text_vmaddr: u64,
+    /// Set when addresses go through segment file offsets or section
+    /// placements instead of a single text base.
+    address_map: Option<&'a AddressMap>,
+    stubs: &'a [Stub],
+    selector: Option<String>,
+    /// Bits kept from every requested address; clears PAC bits.
+    address_mask: AddressMask,
+    /// Code addresses have bit 0 set for Thumb code, which is cleared before
+    /// looking them up.
+    thumb: bool,
+    /// Look up return addresses at the instruction before them.
+    return_addresses: bool,
+    slide: Option<u64>,
+    pub(crate) arch_name: &'a str,
+}
+
+/// A global variable or other symbol found for a data address.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct DataSymbol {
+    pub(crate) name: String,
+    pub(crate) start: u64,
+    /// Zero when the symbol's extent is unknown.
+    pub(crate) size: u64,
+}
+
+/// Maps `address - load_address` onto lookup addresses range by range.
+#[derive(Debug)]
+struct AddressMap {
+    kind: AddressMapKind,
+    ranges: Vec<AddressRange>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AddressMapKind {
+    /// Loadable segments by file offset (`--translate-offsets`).
+    FileOffsets,
+    /// Sections of a relocatable object by their load address (`--section`).
+    Sections,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct AddressRange {
+    start: u64,
+    size: u64,
+    address: u64,
 }
 
-impl Symbolizer<'_> {
-    fn symbolize(
+impl AddressMap {
+    fn translate(&self, load_address: u64, address: u64) -> Result<u64> {
+        let offset = address.checked_sub(load_address).ok_or_else(|| {
+            anyhow!("address {address:#x} is smaller than load address {load_address:#x}")
+        })?;
+        self.ranges
+            .iter()
+            .find(|range| offset >= range.start && offset - range.start < range.size)
+            .map(|range| range.address + (offset - range.start))
+            .ok_or_else(|| match self.kind {
+                AddressMapKind::FileOffsets => {
+                    anyhow!("file offset {offset:#x} is not inside any loadable segment")
+                }
+                AddressMapKind::Sections => anyhow!(
+                    "address {offset:#x} is not inside any mapped section (pass --section NAME=ADDRESS)"
+                ),
+            })
+    }
+}
+
+impl ObjectSymbolizer<'_> {
+    pub(crate) fn symbolize(
         &self,
         load_address: u64,
         requested_address: u64,
         file_offsets: bool,
+        exact_pc: bool,
+    ) -> SymbolizeOutcome {
+        let mut address = requested_address & self.address_mask.for_load_address(load_address);
+        if self.thumb {
+            address &= !1;
+        }
+        let canonical_address = (address != requested_address).then_some(address);
+        // A return address points past the call; the call itself is what the
+        // caller frame was executing.
+        if self.return_addresses && !exact_pc {
+            address = arch::previous_instruction(self.arch_name, address);
+        }
+        let outcome = match self.search_address(load_address, address, file_offsets) {
+            Ok(search_address) => symbolize_address(
+                self.object_name,
+                self.context,
+                self.debug_map,
+                self.symbol_table,
+                self.stubs,
+                requested_address,
+                search_address,
+            ),
+            Err(err) => SymbolizeOutcome::Unresolved {
+                requested_address,
+                canonical_address: None,
+                error: err.to_string(),
+                selector: None,
+            },
+        };
+        match outcome {
+            SymbolizeOutcome::Resolved(frame) => SymbolizeOutcome::Resolved(SymbolizedFrame {
+                canonical_address,
+                ..frame
+            }),
+            SymbolizeOutcome::Unresolved {
+                requested_address,
+                error,
+                selector,
+                ..
+            } => SymbolizeOutcome::Unresolved {
+                requested_address,
+                canonical_address,
+                error,
+                selector,
+            },
+        }
+    }
+
+    // The address in the object's own address space that `address` was
+    // loaded at.
+    fn search_address(&self, load_address: u64, address: u64, file_offsets: bool) -> Result<u64> {
+        match (self.address_map, self.slide) {
+            (Some(address_map), slide) => {
+                address_map.translate(slide.unwrap_or(load_address), address)
+            }
+            (None, Some(slide)) => address
+                .checked_sub(slide)
+                .ok_or_else(|| anyhow!("address {address:#x} is smaller than slide {slide:#x}")),
+            (None, None) => {
+                calculate_search_address(load_address, address, self.text_vmaddr, file_offsets)
+            }
+        }
+    }
+
+    /// The symbol-table entry covering a data address, with its start
+    /// translated back to the address space `requested_address` is in.
+    pub(crate) fn symbolize_data(
+        &self,
+        load_address: u64,
+        requested_address: u64,
+    ) -> Result<DataSymbol, String> {
+        let address = requested_address & self.address_mask.for_load_address(load_address);
+        let search_address = self
+            .search_address(load_address, address, false)
+            .map_err(|err| err.to_string())?;
+        let symbol = self.symbol_table.lookup(search_address)?;
+        Ok(DataSymbol {
+            name: symbol.display_name(),
+            start: address.wrapping_sub(search_address - symbol.address),
+            size: match symbol.end {
+                u64::MAX => 0,
+                end => end - symbol.address,
+            },
+        })
+    }
+
+    /// Symbolizes an address that is already in the object's own address
+    /// space, bypassing the load address.
+    pub(crate) fn symbolize_lookup(
+        &self,
+        requested_address: u64,
+        lookup_address: u64,
     ) -> SymbolizeOutcome {
         symbolize_address(
             self.object_name,
             self.context,
-            self.symbol_map,
-            load_address,
+            self.debug_map,
+            self.symbol_table,
+            self.stubs,
             requested_address,
-            self.text_vmaddr,
-            file_offsets,
+            lookup_address,
         )
     }
 
+    /// The lookup address of the symbol called `name`, preferring the one
+    /// whose extent is `size` bytes when several share the name.
+    pub(crate) fn function_address(&self, name: &str, size: Option<u64>) -> Option<u64> {
+        self.symbol_table
+            .find_by_name(name, size)
+            .map(|symbol| symbol.address)
+    }
+
     fn symbolize_parsed(
         &self,
         options: &SymbolizeOptions,
-        parsed: Result<u64, String>,
+        parsed: Result<InputAddress, String>,
     ) -> SymbolizeOutcome {
         match parsed {
-            Ok(address) => self.symbolize(options.load_address, address, options.file_offsets),
+            Ok(input) => self.symbolize(
+                options.load_address,
+                input.address,
+                options.file_offsets,
+                input.exact_pc,
+            ),
             Err(error) => SymbolizeOutcome::Unresolved {
                 requested_address: 0,
+                canonical_address: None,
                 error,
+                selector: None,
             },
         }
     }
+
+    // Register state describes the crashing frame only, so the selector is
+    // attached to the first address and nothing else, whether or not that
+    // address resolved.
+    fn attach_selector(&self, mut outcome: SymbolizeOutcome, first: bool) -> SymbolizeOutcome {
+        if first {
+            match &mut outcome {
+                SymbolizeOutcome::Resolved(frame) => frame.selector = self.selector.clone(),
+                SymbolizeOutcome::Unresolved { selector, .. } => {
+                    selector.clone_from(&self.selector)
+                }
+            }
+        }
+        outcome
+    }
 }
 
 // Loads the object and DWARF context once, then hands a reusable symbolizer to
 // `body`. Keeping the borrowed state inside one stack frame avoids a
 // self-referential struct (the context borrows the sections, which borrow the
 // mmap).
-fn with_symbolizer<T>(
+pub(crate) fn with_symbolizer<T>(
     options: &SymbolizeOptions,
-    body: impl FnOnce(&Symbolizer<'_>, String, Option<SelectedSlice>) -> T,
+    body: impl FnOnce(&ObjectSymbolizer<'_>, String, Option<SelectedSlice>) -> T,
 ) -> Result<T> {
-    let object_path = resolve_object_path(
-        &options.object_path,
-        options.uuid.as_deref(),
-        &options.debug_dirs,
-    )?;
+    let object_path = resolve_object_path(options)?;
     if options.verbose && object_path != options.object_path {
         eprintln!("resolved_object: {}", object_path.display());
     }
@@ -277,28 +560,93 @@ fn with_symbolizer<T>(
     let mmap = unsafe { memmap2::Mmap::map(&file) }
         .with_context(|| format!("failed to memory-map: {}", object_path.display()))?;
 
+    // A dyld shared cache is spread over subcache files that have to stay
+    // mapped for as long as the image parsed out of it.
+    let subcache_maps = match object::FileKind::parse(&*mmap) {
+        Ok(object::FileKind::DyldCache) => dyld_cache::open_subcaches(&object_path, &mmap)?,
+        _ => Vec::new(),
+    };
+    let subcaches = subcache_maps.iter().map(|map| &map[..]).collect::<Vec<_>>();
+
     let parsed_uuid_filter = match options.uuid.as_deref() {
         Some(value) => parse_uuid_filter(value)?,
         None => None,
     };
-    let resolved = resolve_object_from_data(
-        &mmap,
-        &object_path,
-        options.arch.as_deref(),
-        parsed_uuid_filter,
-        options.verbose,
-    )?;
+    let filters = ObjectFilters {
+        arch: options.arch.as_deref(),
+        uuid: parsed_uuid_filter,
+        image: options.image.as_deref(),
+    };
+    let resolved =
+        resolve_object_from_data(&mmap, &subcaches, &object_path, &filters, options.verbose)?;
+
+    // A dSYM or `.debug` companion keeps only the headers of the code
+    // sections and none of their file contents, so stubs and file offsets are
+    // read from the binary that was named instead.
+    let image_map = match object_path != options.object_path && options.object_path.is_file() {
+        true => {
+            let file = fs::File::open(&options.object_path).with_context(|| {
+                format!(
+                    "failed to open object file: {}",
+                    options.object_path.display()
+                )
+            })?;
+            let map = unsafe { memmap2::Mmap::map(&file) }.with_context(|| {
+                format!("failed to memory-map: {}", options.object_path.display())
+            })?;
+            Some(map)
+        }
+        false => None,
+    };
+    let image = match &image_map {
+        Some(map) => Some(resolve_object_from_data(
+            map,
+            &[],
+            &options.object_path,
+            &filters,
+            false,
+        )?),
+        None => None,
+    };
+    let image = image.as_ref().unwrap_or(&resolved);
+    let image_data = image_map.as_deref().unwrap_or(&mmap);
 
     let endian = if resolved.object.is_little_endian() {
         RunTimeEndian::Little
     } else {
         RunTimeEndian::Big
     };
-    let text_vmaddr = find_text_vmaddr(&resolved.object)?;
-    let symbol_map = resolved.object.symbol_map();
+    let text_vmaddr = select_text_vmaddr(&resolved.object, options)?;
+
+    // Relocatable objects are looked up in a layout that gives every section
+    // its own addresses, with their relocations applied.
+    let layout = match resolved.object.kind() {
+        object::ObjectKind::Relocatable => Some(SectionLayout::for_object(
+            &resolved.object,
+            &resolved.arch_name,
+        )),
+        _ if !options.sections.is_empty() => {
+            return Err(anyhow!(
+                "--section only applies to relocatable objects (ELF ET_REL, Mach-O MH_OBJECT)"
+            ));
+        }
+        _ => None,
+    };
+    let address_map = match &layout {
+        Some(layout) => Some(section_address_map(&resolved.object, layout, options)?),
+        None if options.translate_offsets => Some(file_offset_address_map(image, image_data)?),
+        None => None,
+    };
+
+    let symbol_table = SymbolTable::build(
+        &resolved.object,
+        resolved.raw_data,
+        &resolved.extra_symbols,
+        layout.as_ref(),
+    );
 
     let dwarf_sections = if is_object_dwarf(&resolved.object) {
-        Some(load_dwarf_sections(&resolved.object)?)
+        Some(load_dwarf_sections(&resolved.object, layout.as_ref())?)
     } else {
         None
     };
@@ -310,11 +658,48 @@ fn with_symbolizer<T>(
         None => None,
     };
 
-    let symbolizer = Symbolizer {
+    // A Mach-O image linked without running dsymutil has no DWARF of its own,
+    // but its debug map still leads to the objects that do.
+    let debug_map_entries = match context {
+        Some(_) => Vec::new(),
+        None => debug_map::read_debug_map(&resolved.object, resolved.raw_data),
+    };
+    let debug_map_files = debug_map::map_object_files(&debug_map_entries, options.verbose);
+    let debug_map_objects =
+        DebugMapObjects::new(debug_map_entries, &debug_map_files, options.verbose);
+    let debug_map = (!debug_map_objects.is_empty()).then(|| DebugMap::new(&debug_map_objects));
+
+    let address_mask = select_address_mask(&resolved, options);
+    let thumb = resolved.object.architecture() == object::Architecture::Arm;
+    if options.verbose && thumb {
+        eprintln!("address_mask: Thumb bit cleared from code addresses");
+    }
+    let stubs = stubs::collect_stubs(&image.object, image.raw_data);
+    if options.verbose
+        && image.object.format() == object::BinaryFormat::Elf
+        && !stubs::decodes_plt(image.object.architecture())
+    {
+        eprintln!("stubs: PLT entries are not decoded for {}", image.arch_name);
+    }
+    let selector = resolve_selector(&resolved, options, text_vmaddr);
+    if options.verbose && !options.registers.is_empty() {
+        eprintln!("selector: {}", selector.as_deref().unwrap_or("-"));
+    }
+
+    let symbolizer = ObjectSymbolizer {
         object_name: &resolved.object_name,
         context: context.as_ref(),
-        symbol_map: &symbol_map,
+        debug_map: debug_map.as_ref(),
+        symbol_table: &symbol_table,
         text_vmaddr,
+        address_map: address_map.as_ref(),
+        stubs: &stubs,
+        selector,
+        address_mask,
+        thumb,
+        return_addresses: options.return_addresses,
+        slide: options.slide,
+        arch_name: &resolved.arch_name,
     };
 
     Ok(body(
@@ -324,9 +709,69 @@ fn with_symbolizer<T>(
     ))
 }
 
+/// The bits of a requested address that are part of the address the image
+/// was linked at; arm64e return addresses carry a pointer-authentication code
+/// above them.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum AddressMask {
+    /// Keep these bits: from `--pac-mask` or `--va-bits`, or all of them.
+    Fixed(u64),
+    /// iOS userspace uses 39 address bits and macOS 47; an image loaded
+    /// above 2^39 can only be the latter. Without a load address there is
+    /// nothing to tell them apart, so nothing is cleared.
+    Arm64e,
+}
+
+impl AddressMask {
+    fn for_load_address(self, load_address: u64) -> u64 {
+        match self {
+            Self::Fixed(mask) => mask,
+            Self::Arm64e if load_address == 0 => u64::MAX,
+            Self::Arm64e if load_address < 1 << 39 => va_mask(39),
+            Self::Arm64e => va_mask(47),
+        }
+    }
+}
+
+fn va_mask(bits: u32) -> u64 {
+    match bits {
+        0..=63 => (1u64 << bits) - 1,
+        _ => u64::MAX,
+    }
+}
+
+fn select_address_mask(resolved: &ResolvedObject<'_>, options: &SymbolizeOptions) -> AddressMask {
+    let (mask, reason) = match (options.pac_mask, options.va_bits) {
+        (Some(pac_mask), _) => (AddressMask::Fixed(!pac_mask), "--pac-mask".to_string()),
+        (None, Some(bits)) => (
+            AddressMask::Fixed(va_mask(bits)),
+            format!("{bits}-bit virtual addresses"),
+        ),
+        (None, None) if resolved.arch_name == "arm64e" => (
+            AddressMask::Arm64e,
+            "arm64e, from the load address".to_string(),
+        ),
+        (None, None) => (AddressMask::Fixed(u64::MAX), String::new()),
+    };
+    let bits = mask.for_load_address(options.load_address);
+    if options.verbose && bits != u64::MAX {
+        eprintln!("address_mask: {bits:#x} ({reason})");
+    }
+    mask
+}
+
+/// An address read from `--input` or stdin.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct InputAddress {
+    pub(crate) address: u64,
+    /// Written as `pc:0x…`: the exact PC of the crashing frame rather than a
+    /// return address.
+    pub(crate) exact_pc: bool,
+}
+
 fn for_each_input_address(
     input: Option<&Path>,
-    mut handle: impl FnMut(Result<u64, String>),
+    mut handle: impl FnMut(Result<InputAddress, String>),
 ) -> Result<()> {
     match input {
         Some(path) => {
@@ -343,7 +788,7 @@ fn for_each_input_address(
 
 fn read_addresses(
     reader: impl BufRead,
-    handle: &mut impl FnMut(Result<u64, String>),
+    handle: &mut impl FnMut(Result<InputAddress, String>),
 ) -> Result<()> {
     for line in reader.lines() {
         let line = line.context("failed to read address input")?;
@@ -354,23 +799,31 @@ fn read_addresses(
     Ok(())
 }
 
-fn parse_address_token(token: &str) -> Result<u64, String> {
-    let parsed = match token
+pub(crate) fn parse_address_token(token: &str) -> Result<InputAddress, String> {
+    let (value, exact_pc) = match token.strip_prefix("pc:") {
+        Some(value) => (value, true),
+        None => (token, false),
+    };
+    let parsed = match value
         .strip_prefix("0x")
-        .or_else(|| token.strip_prefix("0X"))
+        .or_else(|| value.strip_prefix("0X"))
     {
         Some(hex) => u64::from_str_radix(hex, 16),
-        None => token.parse::<u64>(),
+        None => value.parse::<u64>(),
     };
-    parsed.map_err(|err| format!("invalid address '{token}': {err}"))
+    parsed
+        .map(|address| InputAddress { address, exact_pc })
+        .map_err(|err| format!("invalid address '{token}': {err}"))
 }
 
 // Accepts a Mach-O/ELF file, a `.dSYM` bundle directory, or (with `uuid`) a
 // directory of binaries/dSYMs to search, and returns the path to the binary
 // that actually carries the symbols.
-fn resolve_object_path(path: &Path, uuid: Option<&str>, debug_dirs: &[PathBuf]) -> Result<PathBuf> {
+fn resolve_object_path(options: &SymbolizeOptions) -> Result<PathBuf> {
+    let path = options.object_path.as_path();
+    let uuid = options.uuid.as_deref();
     if path.is_file() {
-        return Ok(resolve_debug_companion(path, debug_dirs));
+        return Ok(resolve_debug_companion(path, options));
     }
 
     if path.is_dir() {
@@ -379,7 +832,7 @@ fn resolve_object_path(path: &Path, uuid: Option<&str>, debug_dirs: &[PathBuf])
             return select_dwarf_payload(&dwarf_dir, path);
         }
         if let Some(uuid) = uuid {
-            return find_object_by_id(path, uuid);
+            return find_object_by_id(path, uuid, options);
         }
         return Err(anyhow!(
             "{} is a directory; pass --uuid to select a matching binary/dSYM, or point at a file",
@@ -390,247 +843,63 @@ fn resolve_object_path(path: &Path, uuid: Option<&str>, debug_dirs: &[PathBuf])
     Err(anyhow!("object path does not exist: {}", path.display()))
 }
 
-// Walks `dir` for a binary or dSYM payload whose Mach-O UUID or ELF build-id
-// matches `uuid` (compared as hex, ignoring case and separators).
-fn find_object_by_id(dir: &Path, uuid: &str) -> Result<PathBuf> {
-    let wanted = normalize_hex_id(uuid);
-    if wanted.is_empty() {
-        return Err(anyhow!("invalid --uuid value: '{uuid}'"));
-    }
-
-    let mut candidates = Vec::new();
-    collect_candidate_files(dir, &mut candidates)?;
-    candidates.sort();
+// Searches `dir` (then debuginfod and any configured locators) for a binary or dSYM
+// payload whose Mach-O UUID or ELF build-id matches `uuid`.
+fn find_object_by_id(dir: &Path, uuid: &str, options: &SymbolizeOptions) -> Result<PathBuf> {
+    let module = ModuleIdentity::from_id(uuid)?;
+    let mut locators: Vec<Arc<dyn SymbolLocator>> = vec![Arc::new(DirectoryLocator {
+        root: dir.to_path_buf(),
+    })];
+    locators.extend(locate::debuginfod_locator(options.offline));
+    locators.extend(options.locators.iter().cloned());
 
-    for candidate in &candidates {
-        if let Ok(data) = fs::read(candidate) {
-            if collect_module_ids(&data).contains(&wanted) {
-                return Ok(candidate.clone());
-            }
-        }
-    }
-
-    Err(anyhow!(
-        "no binary or dSYM under {} matched uuid {}",
-        dir.display(),
-        uuid
-    ))
+    locate::find_symbols(&locators, &module, options.verbose).ok_or_else(|| {
+        anyhow!(
+            "no binary or dSYM under {} matched uuid {}",
+            dir.display(),
+            uuid
+        )
+    })
 }
 
-// A stripped ELF can point at its DWARF in a separate file via `.gnu_debuglink`
-// or its build-id. When the given file carries no DWARF, follow those hints to a
-// companion debug file that exists; otherwise keep the original path.
-fn resolve_debug_companion(path: &Path, debug_dirs: &[PathBuf]) -> PathBuf {
-    let keep = || path.to_path_buf();
-
+// A stripped Mach-O keeps its DWARF in a `.dSYM` bundle, and a stripped ELF can
+// point at it in a separate file via `.gnu_debuglink` or its build-id. When the
+// given file carries no DWARF, ask the locators for that companion; otherwise
+// keep the original path.
+fn resolve_debug_companion(path: &Path, options: &SymbolizeOptions) -> PathBuf {
     let Ok(handle) = fs::File::open(path) else {
-        return keep();
+        return path.to_path_buf();
     };
     // mmap so checking large binaries only touches headers, not the whole file.
     let Ok(mmap) = (unsafe { memmap2::Mmap::map(&handle) }) else {
-        return keep();
-    };
-    let Ok(file) = object::File::parse(&*mmap) else {
-        return keep();
+        return path.to_path_buf();
     };
-    if is_object_dwarf(&file) {
-        return keep();
-    }
-
-    if let Some((name, crc)) = read_debuglink(&file) {
-        let dir = path.parent().unwrap_or_else(|| Path::new("."));
-        for candidate in debuglink_candidates(dir, &name, debug_dirs) {
-            // The CRC guards against using a debug file that no longer matches
-            // the binary, matching gdb's behavior.
-            if candidate.is_file() && file_crc32(&candidate) == Some(crc) {
-                return candidate;
-            }
-        }
-    }
-
-    if let Ok(Some(build_id)) = file.build_id() {
-        // The build-id path itself identifies the matching debug file.
-        for candidate in build_id_candidates(build_id, debug_dirs) {
-            if candidate.is_file() {
-                return candidate;
-            }
-        }
-    }
-
-    keep()
-}
-
-fn read_debuglink<'data>(file: &object::File<'data, &'data [u8]>) -> Option<(String, u32)> {
-    let section = file.section_by_name(".gnu_debuglink")?;
-    let data = section.data().ok()?;
-    if data.len() < 4 {
-        return None;
-    }
-    let end = data
-        .iter()
-        .position(|byte| *byte == 0)
-        .unwrap_or(data.len());
-    let name = std::str::from_utf8(&data[..end]).ok()?;
-    if name.is_empty() {
-        return None;
-    }
-    // The 4-byte CRC trails the NUL-padded name, in the file's byte order.
-    let crc_bytes: [u8; 4] = data[data.len() - 4..].try_into().ok()?;
-    let crc = if file.is_little_endian() {
-        u32::from_le_bytes(crc_bytes)
-    } else {
-        u32::from_be_bytes(crc_bytes)
-    };
-    Some((name.to_string(), crc))
-}
-
-fn debuglink_candidates(binary_dir: &Path, name: &str, debug_dirs: &[PathBuf]) -> Vec<PathBuf> {
-    let mut candidates = vec![binary_dir.join(name), binary_dir.join(".debug").join(name)];
-
-    let mirrored = binary_dir
-        .canonicalize()
-        .ok()
-        .map(|abs| abs.strip_prefix("/").unwrap_or(&abs).to_path_buf());
-
-    let roots = std::iter::once(PathBuf::from("/usr/lib/debug")).chain(debug_dirs.iter().cloned());
-    for root in roots {
-        candidates.push(root.join(name));
-        if let Some(mirror) = &mirrored {
-            candidates.push(root.join(mirror).join(name));
-        }
+    let uuid_filter = options
+        .uuid
+        .as_deref()
+        .and_then(|uuid| parse_uuid_filter(uuid).ok().flatten());
+    let modules = locate::module_identities(path, &mmap, options.arch.as_deref(), uuid_filter);
+    if modules.is_empty() {
+        return path.to_path_buf();
     }
 
-    candidates
-}
-
-fn build_id_candidates(build_id: &[u8], debug_dirs: &[PathBuf]) -> Vec<PathBuf> {
-    let subpath = build_id_debug_subpath(build_id);
-    let mut candidates = vec![PathBuf::from("/usr/lib/debug/.build-id").join(&subpath)];
-
-    for dir in debug_dirs {
-        candidates.push(dir.join(".build-id").join(&subpath));
-    }
-
-    // debuginfod stores fetched debug files at <cache>/<build-id hex>/debuginfo.
-    let hex = format_hex(build_id);
-    for cache in debuginfod_cache_dirs() {
-        candidates.push(cache.join(&hex).join("debuginfo"));
-    }
-
-    candidates
-}
-
-fn debuginfod_cache_dirs() -> Vec<PathBuf> {
-    if let Ok(path) = env::var("DEBUGINFOD_CACHE_PATH") {
-        return vec![PathBuf::from(path)];
-    }
-    if let Ok(xdg) = env::var("XDG_CACHE_HOME") {
-        return vec![PathBuf::from(xdg).join("debuginfod_client")];
-    }
-    if let Ok(home) = env::var("HOME") {
-        return vec![PathBuf::from(home).join(".cache").join("debuginfod_client")];
-    }
-    Vec::new()
-}
-
-fn build_id_debug_subpath(build_id: &[u8]) -> PathBuf {
-    let hex = format_hex(build_id);
-    match hex.split_at_checked(2) {
-        Some((prefix, rest)) => PathBuf::from(prefix).join(format!("{rest}.debug")),
-        None => PathBuf::from(format!("{hex}.debug")),
-    }
-}
-
-fn file_crc32(path: &Path) -> Option<u32> {
-    let handle = fs::File::open(path).ok()?;
-    let mmap = unsafe { memmap2::Mmap::map(&handle) }.ok()?;
-    Some(crc32(&mmap))
-}
-
-// Standard CRC-32 (reflected, polynomial 0xedb88320), as used by gnu_debuglink.
-fn crc32(data: &[u8]) -> u32 {
-    let mut crc: u32 = 0xffff_ffff;
-    for &byte in data {
-        crc ^= u32::from(byte);
-        for _ in 0..8 {
-            let mask = (crc & 1).wrapping_neg();
-            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
-        }
-    }
-    !crc
-}
-
-fn collect_candidate_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
-    let entries = fs::read_dir(dir)
-        .with_context(|| format!("failed to read directory: {}", dir.display()))?;
-    for entry in entries {
-        let entry = entry?;
-        let file_type = entry.file_type()?;
-        if file_type.is_symlink() {
-            continue;
-        }
-        let path = entry.path();
-        if file_type.is_dir() {
-            collect_candidate_files(&path, out)?;
-        } else if file_type.is_file() {
-            out.push(path);
-        }
-    }
-    Ok(())
-}
-
-fn collect_module_ids(data: &[u8]) -> Vec<String> {
-    match object::FileKind::parse(data) {
-        Ok(object::FileKind::MachOFat32) => FatHeader::parse_arch32(data)
-            .map(|arches| fat_slice_ids(arches, data))
-            .unwrap_or_default(),
-        Ok(object::FileKind::MachOFat64) => FatHeader::parse_arch64(data)
-            .map(|arches| fat_slice_ids(arches, data))
-            .unwrap_or_default(),
-        Ok(_) => slice_ids(data),
-        Err(_) => Vec::new(),
-    }
-}
-
-fn fat_slice_ids<A: FatArch>(arches: &[A], data: &[u8]) -> Vec<String> {
-    arches
+    let locators = locate::companion_locators(
+        &options.debug_dirs,
+        &options.dsym_dirs,
+        options.offline,
+        &options.locators,
+    );
+    modules
         .iter()
-        .filter_map(|arch| arch.data(data).ok())
-        .flat_map(slice_ids)
-        .collect()
-}
-
-fn slice_ids(data: &[u8]) -> Vec<String> {
-    let Ok(file) = object::File::parse(data) else {
-        return Vec::new();
-    };
-    let mut ids = Vec::new();
-    if let Ok(Some(uuid)) = file.mach_uuid() {
-        ids.push(format_hex(&uuid));
-    }
-    if let Ok(Some(build_id)) = file.build_id() {
-        ids.push(format_hex(build_id));
-    }
-    ids
-}
-
-fn format_hex(bytes: &[u8]) -> String {
-    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
-}
-
-fn normalize_hex_id(value: &str) -> String {
-    value
-        .chars()
-        .filter(|c| c.is_ascii_hexdigit())
-        .flat_map(|c| c.to_lowercase())
-        .collect()
+        .find_map(|module| locate::find_symbols(&locators, module, options.verbose))
+        .unwrap_or_else(|| path.to_path_buf())
 }
 
 // A 16-byte value is a Mach-O slice UUID and is used to pick a fat slice and to
 // validate non-fat files. Other hex lengths are build-ids used only to select a
 // file from a directory, so they impose no slice filter here.
 fn parse_uuid_filter(value: &str) -> Result<Option<[u8; 16]>> {
-    let hex = normalize_hex_id(value);
+    let hex = locate::normalize_hex_id(value);
     if hex.is_empty() || value.chars().any(|c| c != '-' && !c.is_ascii_hexdigit()) {
         return Err(anyhow!("invalid uuid format: '{value}'"));
     }
@@ -679,9 +948,9 @@ fn select_dwarf_payload(dwarf_dir: &Path, bundle: &Path) -> Result<PathBuf> {
     }
 }
 
-fn emit_report(report: &SymbolizeReport, format: OutputFormat, verbose: bool, inline_frames: bool) {
-    match format {
-        OutputFormat::Text => emit_text_report(report, verbose, inline_frames),
+fn emit_report(report: &SymbolizeReport, options: &SymbolizeOptions) {
+    match options.format {
+        OutputFormat::Text => emit_text_report(report, options),
         OutputFormat::Json => println!("{}", serde_json::to_string(report).unwrap()),
         OutputFormat::JsonPretty => println!("{}", serde_json::to_string_pretty(report).unwrap()),
         OutputFormat::JsonLines => {
@@ -692,12 +961,12 @@ fn emit_report(report: &SymbolizeReport, format: OutputFormat, verbose: bool, in
     }
 }
 
-fn emit_text_report(report: &SymbolizeReport, verbose: bool, inline_frames: bool) {
-    if verbose {
+fn emit_text_report(report: &SymbolizeReport, options: &SymbolizeOptions) {
+    if options.verbose {
         emit_text_header(&report.object_path, report.selected_slice.as_ref());
     }
     for frame in &report.frames {
-        emit_text_outcome(frame, verbose, inline_frames);
+        emit_text_outcome(frame, options);
     }
 }
 
@@ -712,10 +981,11 @@ fn emit_text_header(object_path: &str, selected_slice: Option<&SelectedSlice>) {
     }
 }
 
-fn emit_text_outcome(outcome: &SymbolizeOutcome, verbose: bool, inline_frames: bool) {
+fn emit_text_outcome(outcome: &SymbolizeOutcome, options: &SymbolizeOptions) {
+    let full_path = options.full_path;
     match outcome {
         SymbolizeOutcome::Resolved(frame) => {
-            if verbose {
+            if options.verbose {
                 eprintln!(
                     "frame: requested=0x{requested:016x} lookup=0x{lookup:016x} resolver={resolver:?} status=resolved",
                     requested = frame.requested_address,
@@ -723,43 +993,66 @@ fn emit_text_outcome(outcome: &SymbolizeOutcome, verbose: bool, inline_frames: b
                     resolver = frame.resolver,
                 );
             }
-            if inline_frames {
-                // Full inline call stack, innermost first (like `atos -i`).
-                println!("{}", format_text_frame(frame));
-                for inline in &frame.inlined_by {
-                    println!("{}", format_inline_frame(inline, &frame.object_name));
-                }
-            } else {
-                // Only the outermost frame: the real (non-inlined) function that
-                // physically contains the address. It is the last entry in the
-                // inline chain, or the primary frame when nothing was inlined.
-                match frame.inlined_by.last() {
-                    Some(outermost) => {
-                        println!("{}", format_inline_frame(outermost, &frame.object_name))
+            let mut lines =
+                if options.inline_frames {
+                    // Full inline call stack, innermost first (like `atos -i`).
+                    std::iter::once(format_text_frame(frame, full_path))
+                        .chain(frame.inlined_by.iter().map(|inline| {
+                            format_inline_frame(inline, &frame.object_name, full_path)
+                        }))
+                        .collect::<Vec<_>>()
+                } else {
+                    // Only the outermost frame: the real (non-inlined) function that
+                    // physically contains the address. It is the last entry in the
+                    // inline chain, or the primary frame when nothing was inlined.
+                    match frame.inlined_by.last() {
+                        Some(outermost) => vec![format_inline_frame(
+                            outermost,
+                            &frame.object_name,
+                            full_path,
+                        )],
+                        None => vec![format_text_frame(frame, full_path)],
+                    }
+                };
+            if let Some(selector) = &frame.selector {
+                lines[0].push_str(&format!(" selector: {selector:?}"));
+            }
+            match &options.inline_delimiter {
+                Some(delimiter) => println!("{}", lines.join(delimiter)),
+                None => {
+                    for line in lines {
+                        println!("{line}");
                     }
-                    None => println!("{}", format_text_frame(frame)),
                 }
             }
         }
         SymbolizeOutcome::Unresolved {
             requested_address,
             error,
+            selector,
+            ..
         } => {
-            if verbose {
+            if options.verbose {
                 eprintln!(
                     "frame: requested=0x{requested_address:016x} status=unresolved error={error}"
                 );
             }
-            println!("N/A - {error}");
+            match selector {
+                Some(selector) => println!("N/A - {error} selector: {selector:?}"),
+                None => println!("N/A - {error}"),
+            }
         }
     }
 }
 
-fn format_text_frame(frame: &SymbolizedFrame) -> String {
+fn format_text_frame(frame: &SymbolizedFrame, full_path: bool) -> String {
     match &frame.location {
         Some(location) => format!(
             "{} (in {}) ({}:{})",
-            frame.symbol, frame.object_name, location.file, location.line
+            frame.symbol,
+            frame.object_name,
+            display_file(&location.file, full_path),
+            location.line
         ),
         None => format!(
             "{} (in {}) + {}",
@@ -768,69 +1061,98 @@ fn format_text_frame(frame: &SymbolizedFrame) -> String {
     }
 }
 
-fn format_inline_frame(frame: &InlineFrame, object_name: &str) -> String {
+fn format_inline_frame(frame: &InlineFrame, object_name: &str, full_path: bool) -> String {
     match &frame.location {
         Some(location) => format!(
             "{} (in {}) ({}:{})",
-            frame.symbol, object_name, location.file, location.line
+            frame.symbol,
+            object_name,
+            display_file(&location.file, full_path),
+            location.line
         ),
         None => format!("{} (in {})", frame.symbol, object_name),
     }
 }
 
-#[allow(clippy::too_many_arguments)]
+// atos prints only the file name unless asked for `-fullPath`.
+fn display_file(file: &str, full_path: bool) -> &str {
+    match full_path {
+        true => file,
+        false => file.rsplit(['/', '\\']).next().unwrap_or(file),
+    }
+}
+
 fn symbolize_address<'data>(
     object_name: &str,
     context: Option<&DwarfContext<'data>>,
-    symbol_map: &SymbolMap<SymbolMapName<'data>>,
-    load_address: u64,
+    debug_map: Option<&DebugMap<'data>>,
+    symbol_table: &SymbolTable<'data>,
+    stubs: &[Stub],
     requested_address: u64,
-    text_vmaddr: u64,
-    file_offsets: bool,
+    search_address: u64,
 ) -> SymbolizeOutcome {
-    let search_address = match calculate_search_address(
-        load_address,
-        requested_address,
-        text_vmaddr,
-        file_offsets,
-    ) {
-        Ok(search_address) => search_address,
-        Err(err) => {
-            return SymbolizeOutcome::Unresolved {
-                requested_address,
-                error: err.to_string(),
-            };
-        }
+    // With a debug map, the DWARF lives in the object the address was linked
+    // from and is queried at the matching object address.
+    let dwarf = match context {
+        Some(context) => Some((context, search_address)),
+        None => debug_map.and_then(|debug_map| debug_map.locate(search_address)),
     };
-
-    if let Some(context) = context {
+    if let Some((context, dwarf_address)) = dwarf {
         if let Ok(Some(frame)) = dwarf_symbolize_address(
             context,
-            symbol_map,
+            symbol_table,
             object_name,
             requested_address,
             search_address,
+            dwarf_address,
         ) {
             return SymbolizeOutcome::Resolved(frame);
         }
     }
 
-    match symbol_symbolize_address(symbol_map, object_name, requested_address, search_address) {
+    // Stubs sit in their own sections, so the nearest preceding symbol is
+    // unrelated to them; name them after their callee instead.
+    if let Some(stub) = stubs::find_stub(stubs, search_address) {
+        return SymbolizeOutcome::Resolved(SymbolizedFrame {
+            requested_address,
+            canonical_address: None,
+            lookup_address: search_address,
+            symbol: stub.name.clone(),
+            object_name: object_name.to_string(),
+            offset: search_address - stub.address,
+            resolver: ResolverKind::Stub,
+            location: None,
+            inlined_by: Vec::new(),
+            selector: None,
+        });
+    }
+
+    match symbol_symbolize_address(symbol_table, object_name, requested_address, search_address) {
         Ok(frame) => SymbolizeOutcome::Resolved(frame),
-        Err(err) => SymbolizeOutcome::Unresolved {
+        Err(error) => SymbolizeOutcome::Unresolved {
             requested_address,
-            error: err.to_string(),
+            canonical_address: None,
+            error,
+            selector: None,
         },
     }
 }
 
+struct ObjectFilters<'a> {
+    arch: Option<&'a str>,
+    uuid: Option<[u8; 16]>,
+    image: Option<&'a str>,
+}
+
 fn resolve_object_from_data<'data>(
     data: &'data [u8],
+    subcaches: &[&'data [u8]],
     object_path: &Path,
-    arch_filter: Option<&str>,
-    uuid_filter: Option<[u8; 16]>,
+    filters: &ObjectFilters<'_>,
     verbose: bool,
 ) -> Result<ResolvedObject<'data>> {
+    let arch_filter = filters.arch;
+    let uuid_filter = filters.uuid;
     let kind = object::FileKind::parse(data)?;
     let object_name = object_path
         .file_name()
@@ -842,6 +1164,12 @@ fn resolve_object_from_data<'data>(
         })?
         .to_string_lossy()
         .to_string();
+    if filters.image.is_some() && kind != object::FileKind::DyldCache {
+        return Err(anyhow!(
+            "--image selects an image inside a dyld shared cache, but {} is not one",
+            object_path.display()
+        ));
+    }
 
     match kind {
         object::FileKind::MachOFat32 => {
@@ -850,7 +1178,10 @@ fn resolve_object_from_data<'data>(
             Ok(ResolvedObject {
                 object: selected.object,
                 object_name,
+                arch_name: selected.selected_slice.arch.clone(),
                 selected_slice: Some(selected.selected_slice),
+                extra_symbols: Vec::new(),
+                raw_data: Some(selected.data),
             })
         }
         object::FileKind::MachOFat64 => {
@@ -859,20 +1190,59 @@ fn resolve_object_from_data<'data>(
             Ok(ResolvedObject {
                 object: selected.object,
                 object_name,
+                arch_name: selected.selected_slice.arch.clone(),
                 selected_slice: Some(selected.selected_slice),
+                extra_symbols: Vec::new(),
+                raw_data: Some(selected.data),
+            })
+        }
+        object::FileKind::DyldCache => {
+            let image =
+                dyld_cache::select_image(data, subcaches, filters.image, uuid_filter, verbose)?;
+            let arch_name = arch::dyld_cache_arch_name(data).unwrap_or_else(|| {
+                arch::architecture_name(
+                    image.object.architecture(),
+                    image.object.is_little_endian(),
+                )
+            });
+            validate_non_fat_filters(&image.object, &arch_name, arch_filter, None)?;
+            let selected_slice = image.object.mach_uuid()?.map(|uuid| SelectedSlice {
+                arch: arch_name.clone(),
+                uuid: Some(format_uuid(uuid)),
+            });
+            // Frames name the framework, not the cache file that carried it.
+            let object_name = image
+                .install_name
+                .rsplit('/')
+                .next()
+                .unwrap_or(image.install_name)
+                .to_string();
+            Ok(ResolvedObject {
+                object: image.object,
+                object_name,
+                selected_slice,
+                arch_name,
+                extra_symbols: image.local_symbols,
+                raw_data: None,
             })
         }
         _ => {
             let file = object::File::parse(data)?;
-            validate_non_fat_filters(&file, arch_filter, uuid_filter)?;
+            let arch_name = arch::file_arch_name(data).unwrap_or_else(|| {
+                arch::architecture_name(file.architecture(), file.is_little_endian())
+            });
+            validate_non_fat_filters(&file, &arch_name, arch_filter, uuid_filter)?;
             let selected_slice = file.mach_uuid()?.map(|uuid| SelectedSlice {
-                arch: format!("{:?}", file.architecture()).to_lowercase(),
+                arch: arch_name.clone(),
                 uuid: Some(format_uuid(uuid)),
             });
             Ok(ResolvedObject {
                 object: file,
                 object_name,
                 selected_slice,
+                arch_name,
+                extra_symbols: Vec::new(),
+                raw_data: Some(data),
             })
         }
     }
@@ -880,6 +1250,7 @@ fn resolve_object_from_data<'data>(
 
 fn validate_non_fat_filters<'data>(
     file: &object::File<'data, &'data [u8]>,
+    arch_name: &str,
     arch_filter: Option<&str>,
     uuid_filter: Option<[u8; 16]>,
 ) -> Result<()> {
@@ -897,12 +1268,11 @@ fn validate_non_fat_filters<'data>(
     }
 
     if let Some(arch_filter) = arch_filter {
-        let architecture = file.architecture();
-        if !architecture_matches_filter(architecture, arch_filter) {
+        // A thin image has nothing to choose between, so `arm64` accepts an
+        // arm64e image as well.
+        if !arch::arch_matches_filter(arch_name, arch_filter, true) {
             return Err(anyhow!(
-                "architecture mismatch: requested '{}', actual '{:?}'",
-                arch_filter,
-                architecture
+                "architecture mismatch: requested '{arch_filter}', actual '{arch_name}'"
             ));
         }
     }
@@ -919,11 +1289,13 @@ fn select_fat_slice<'data, A: FatArch>(
 ) -> Result<FatSlice<'data>> {
     let mut slices = Vec::with_capacity(arches.len());
     for arch in arches {
-        let arch_name = format_macho_arch_name(arch.cputype(), arch.cpusubtype());
-        let object = object::File::parse(arch.data(data)?)?;
+        let arch_name = arch::macho_arch_name(arch.cputype(), arch.cpusubtype());
+        let slice_data = arch.data(data)?;
+        let object = object::File::parse(slice_data)?;
         let uuid = object.mach_uuid()?.map(format_uuid);
         slices.push(FatSlice {
             object,
+            data: slice_data,
             selected_slice: SelectedSlice {
                 arch: arch_name,
                 uuid,
@@ -956,11 +1328,20 @@ fn select_fat_slice<'data, A: FatArch>(
     }
 
     let available_slices = format_available_slices(&slices);
+    // `-a arm64` picks the arm64 slice next to an arm64e one, but still finds
+    // an arm64e slice when it is the only one of its family.
+    let lenient = arch_filter.is_some_and(|filter| {
+        !slices
+            .iter()
+            .any(|slice| arch::arch_matches_filter(&slice.selected_slice.arch, filter, false))
+    });
     let mut matches = slices
         .into_iter()
         .filter(|slice| {
             let arch_ok = arch_filter
-                .map(|filter| macho_arch_matches_filter(&slice.selected_slice.arch, filter))
+                .map(|filter| {
+                    arch::arch_matches_filter(&slice.selected_slice.arch, filter, lenient)
+                })
                 .unwrap_or(true);
             let uuid_ok = uuid_filter
                 .map(|uuid| {
@@ -1021,7 +1402,7 @@ fn parse_uuid_string(value: &str) -> Result<[u8; 16]> {
     Ok(out)
 }
 
-fn format_uuid(uuid: [u8; 16]) -> String {
+pub(crate) fn format_uuid(uuid: [u8; 16]) -> String {
     let hex = uuid
         .iter()
         .map(|byte| format!("{byte:02X}"))
@@ -1036,68 +1417,7 @@ fn format_uuid(uuid: [u8; 16]) -> String {
     )
 }
 
-fn normalize_arch(value: &str) -> String {
-    value
-        .chars()
-        .filter(|character| character.is_ascii_alphanumeric())
-        .flat_map(char::to_lowercase)
-        .collect::<String>()
-}
-
-fn architecture_matches_filter(architecture: object::Architecture, arch_filter: &str) -> bool {
-    let filter = normalize_arch(arch_filter);
-    match filter.as_str() {
-        "arm" | "armv7" | "armv7s" | "armv7k" => architecture == object::Architecture::Arm,
-        "arm64" | "arm64e" | "aarch64" => architecture == object::Architecture::Aarch64,
-        "x8664" | "x64" | "amd64" | "x8664h" => architecture == object::Architecture::X86_64,
-        "x86" | "i386" => architecture == object::Architecture::I386,
-        _ => false,
-    }
-}
-
-fn macho_arch_matches_filter(arch_name: &str, arch_filter: &str) -> bool {
-    let actual = normalize_arch(arch_name);
-    let filter = normalize_arch(arch_filter);
-
-    if actual == filter {
-        return true;
-    }
-
-    matches!(
-        (actual.as_str(), filter.as_str()),
-        ("arm64", "aarch64")
-            | ("aarch64", "arm64")
-            | ("x8664", "amd64")
-            | ("x8664", "x64")
-            | ("i386", "x86")
-    )
-}
-
-fn format_macho_arch_name(cputype: u32, cpusubtype: u32) -> String {
-    let cpusubtype = cpusubtype & !macho::CPU_SUBTYPE_MASK;
-    match cputype {
-        macho::CPU_TYPE_ARM64 => match cpusubtype {
-            macho::CPU_SUBTYPE_ARM64E => "arm64e".to_string(),
-            _ => "arm64".to_string(),
-        },
-        macho::CPU_TYPE_ARM => match cpusubtype {
-            macho::CPU_SUBTYPE_ARM_V7 => "armv7".to_string(),
-            macho::CPU_SUBTYPE_ARM_V7F => "armv7f".to_string(),
-            macho::CPU_SUBTYPE_ARM_V7S => "armv7s".to_string(),
-            macho::CPU_SUBTYPE_ARM_V7K => "armv7k".to_string(),
-            macho::CPU_SUBTYPE_ARM_V8 => "armv8".to_string(),
-            _ => "arm".to_string(),
-        },
-        macho::CPU_TYPE_X86_64 => match cpusubtype {
-            macho::CPU_SUBTYPE_X86_64_H => "x86_64h".to_string(),
-            _ => "x86_64".to_string(),
-        },
-        macho::CPU_TYPE_X86 => "i386".to_string(),
-        _ => format!("cputype{cputype}_subtype{cpusubtype}"),
-    }
-}
-
-fn is_object_dwarf<'data>(object: &object::File<'data, &'data [u8]>) -> bool {
+pub(crate) fn is_object_dwarf<'data>(object: &object::File<'data, &'data [u8]>) -> bool {
     object.section_by_name("__debug_line").is_some()
         || object.section_by_name(".debug_line").is_some()
 }
@@ -1121,6 +1441,141 @@ fn find_text_vmaddr<'data>(object: &object::File<'data, &'data [u8]>) -> Result<
     Ok(0)
 }
 
+// ELF has no __TEXT segment. By default the lookup re-bases onto `.text`, which
+// existing callers rely on; with `module_base` it re-bases onto the lowest
+// `PT_LOAD` segment, matching load bases reported by the dynamic loader.
+fn select_text_vmaddr<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    options: &SymbolizeOptions,
+) -> Result<u64> {
+    let text_vmaddr = find_text_vmaddr(object)?;
+    if object.format() != object::BinaryFormat::Elf
+        || object.kind() == object::ObjectKind::Relocatable
+    {
+        return Ok(text_vmaddr);
+    }
+
+    if options.translate_offsets {
+        if options.verbose {
+            eprintln!("address_base: none (file offsets are translated through PT_LOAD segments)");
+        }
+        return Ok(text_vmaddr);
+    }
+    if options.file_offsets {
+        if options.verbose {
+            eprintln!("address_base: none (--file-offsets looks up address - load address)");
+        }
+        return Ok(text_vmaddr);
+    }
+    if !options.module_base {
+        if options.verbose {
+            eprintln!(
+                "address_base: .text {text_vmaddr:#x} (pass --module-base if the load address is the module base)"
+            );
+        }
+        return Ok(text_vmaddr);
+    }
+
+    let base = elf_load_base(object)
+        .ok_or_else(|| anyhow!("--module-base needs an ELF object with PT_LOAD segments"))?;
+    if options.verbose {
+        eprintln!(
+            "address_base: lowest PT_LOAD p_vaddr {base:#x} (load address is the module base)"
+        );
+    }
+    Ok(base)
+}
+
+// The loader maps segments at page granularity, so the module base corresponds
+// to the lowest `PT_LOAD` p_vaddr rounded down to its alignment.
+fn elf_load_base<'data>(object: &object::File<'data, &'data [u8]>) -> Option<u64> {
+    object
+        .segments()
+        .map(|segment| {
+            let align = segment.align();
+            if align.is_power_of_two() {
+                segment.address() & !(align - 1)
+            } else {
+                segment.address()
+            }
+        })
+        .min()
+}
+
+// Segment file offsets are relative to the selected slice, while offsets from a
+// universal binary are relative to the file, so the slice's position is added.
+fn file_offset_address_map(resolved: &ResolvedObject<'_>, file: &[u8]) -> Result<AddressMap> {
+    let data = resolved.raw_data.ok_or_else(|| {
+        anyhow!("file offsets cannot be translated for an image inside a dyld shared cache")
+    })?;
+    let slice_offset = (data.as_ptr() as usize).saturating_sub(file.as_ptr() as usize) as u64;
+    let ranges = resolved
+        .object
+        .segments()
+        .filter_map(|segment| {
+            let (file_offset, file_size) = segment.file_range();
+            (file_size > 0).then_some(AddressRange {
+                start: slice_offset + file_offset,
+                size: file_size,
+                address: segment.address(),
+            })
+        })
+        .collect();
+    Ok(AddressMap {
+        kind: AddressMapKind::FileOffsets,
+        ranges,
+    })
+}
+
+// Each `--section NAME=ADDRESS` places one section of a relocatable object, as
+// `/sys/module/<name>/sections/*` reports them. Without any, addresses are
+// offsets into the text section.
+fn section_address_map<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    layout: &SectionLayout,
+    options: &SymbolizeOptions,
+) -> Result<AddressMap> {
+    let default_text = [(
+        if object.format() == object::BinaryFormat::MachO {
+            "__text"
+        } else {
+            ".text"
+        }
+        .to_string(),
+        0,
+    )];
+    let placements = if options.sections.is_empty() {
+        &default_text[..]
+    } else {
+        &options.sections[..]
+    };
+
+    let mut ranges = Vec::with_capacity(placements.len());
+    for (name, start) in placements {
+        let section = object
+            .section_by_name(name)
+            .ok_or_else(|| anyhow!("relocatable object has no section named {name}"))?;
+        let address = layout
+            .section_address(object, section.index())
+            .unwrap_or_default();
+        if options.verbose {
+            eprintln!(
+                "section: {name} at {start:#x} (size {:#x}, lookup base {address:#x})",
+                section.size()
+            );
+        }
+        ranges.push(AddressRange {
+            start: *start,
+            size: section.size(),
+            address,
+        });
+    }
+    Ok(AddressMap {
+        kind: AddressMapKind::Sections,
+        ranges,
+    })
+}
+
 fn calculate_search_address(
     load_address: u64,
     address: u64,
@@ -1152,68 +1607,83 @@ pass the runtime address as reported in the crash report"
 }
 
 fn symbol_symbolize_address(
-    symbol_map: &SymbolMap<SymbolMapName<'_>>,
+    symbol_table: &SymbolTable<'_>,
     object_name: &str,
     requested_address: u64,
     search_address: u64,
-) -> Result<SymbolizedFrame> {
-    let found_symbol = symbol_map
-        .get(search_address)
-        .ok_or_else(|| anyhow!("failed to search symbol table"))?;
-    let offset = search_address.saturating_sub(found_symbol.address());
+) -> Result<SymbolizedFrame, String> {
+    let found_symbol = symbol_table.lookup(search_address)?;
+    let offset = search_address.saturating_sub(found_symbol.address);
 
     Ok(SymbolizedFrame {
         requested_address,
+        canonical_address: None,
         lookup_address: search_address,
-        symbol: demangle::demangle_symbol(found_symbol.name()),
+        symbol: found_symbol.display_name(),
         object_name: object_name.to_string(),
         offset,
-        resolver: ResolverKind::SymbolTable,
+        resolver: if symbol_table.is_dynamic() {
+            ResolverKind::DynamicSymbolTable
+        } else {
+            ResolverKind::SymbolTable
+        },
         location: None,
         inlined_by: Vec::new(),
+        selector: None,
     })
 }
 
-fn load_dwarf_sections<'data>(
+pub(crate) fn load_dwarf_sections<'data>(
     object: &object::File<'data, &'data [u8]>,
+    layout: Option<&SectionLayout>,
 ) -> Result<gimli::DwarfSections<borrow::Cow<'data, [u8]>>> {
-    let sections = gimli::DwarfSections::load(
-        |section_id| -> Result<borrow::Cow<'data, [u8]>, gimli::Error> {
-            let macho_name = section_id
-                .name()
-                .strip_prefix('.')
-                .map(|name| format!("__{name}"));
-
-            match object.section_by_name(section_id.name()).or_else(|| {
-                macho_name
-                    .as_deref()
-                    .and_then(|name| object.section_by_name(name))
-            }) {
-                Some(section) => Ok(section
+    let sections = gimli::DwarfSections::load(|section_id| -> Result<borrow::Cow<'data, [u8]>> {
+        match object
+            .section_by_name(section_id.name())
+            .or_else(|| object.section_by_name(&macho_section_name(section_id.name())))
+        {
+            // Relocations that cannot be applied would leave wrong
+            // addresses behind, so they fail the load.
+            Some(section) => match layout {
+                Some(layout) => relocated_section_data(object, &section, layout)
+                    .with_context(|| format!("failed to relocate {}", section_id.name())),
+                None => Ok(section
                     .uncompressed_data()
                     .unwrap_or(borrow::Cow::Borrowed(&[][..]))),
-                None => Ok(borrow::Cow::Borrowed(&[][..])),
-            }
-        },
-    )?;
+            },
+            None => Ok(borrow::Cow::Borrowed(&[][..])),
+        }
+    })?;
     Ok(sections)
 }
 
+// Mach-O section names are limited to 16 bytes (`__debug_str_offs`).
+fn macho_section_name(name: &str) -> String {
+    let mut name = format!("__{}", name.trim_start_matches('.'));
+    name.truncate(16);
+    name
+}
+
 // `atos` reports inlined call sites as a stack, innermost first. addr2line
 // yields frames in the same order, so the first frame becomes the primary
 // result and the remaining ones are the callers that inlined it.
 fn dwarf_symbolize_address<'data>(
     context: &DwarfContext<'data>,
-    symbol_map: &SymbolMap<SymbolMapName<'data>>,
+    symbol_table: &SymbolTable<'data>,
     object_name: &str,
     requested_address: u64,
     search_address: u64,
+    dwarf_address: u64,
 ) -> Result<Option<SymbolizedFrame>> {
-    let mut iter = context.find_frames(search_address).skip_all_loads()?;
+    let mut iter = context.find_frames(dwarf_address).skip_all_loads()?;
     let mut frames: Vec<(String, Option<SourceLocation>)> = Vec::new();
+    let mut line_only = None;
 
     while let Some(frame) = iter.next()? {
         let Some(function) = frame.function.as_ref() else {
+            if line_only.is_none() {
+                line_only = frame.location.as_ref().and_then(location_from_addr2line);
+            }
             continue;
         };
         let Ok(raw_name) = function.raw_name() else {
@@ -1225,10 +1695,26 @@ fn dwarf_symbolize_address<'data>(
     }
 
     if frames.is_empty() {
-        return Ok(None);
+        // Keep the line-table location and borrow the name from the symbol table.
+        let (Some(location), Ok(found_symbol)) = (line_only, symbol_table.lookup(search_address))
+        else {
+            return Ok(None);
+        };
+        return Ok(Some(SymbolizedFrame {
+            requested_address,
+            canonical_address: None,
+            lookup_address: search_address,
+            symbol: found_symbol.display_name(),
+            object_name: object_name.to_string(),
+            offset: search_address - found_symbol.address,
+            resolver: ResolverKind::DwarfLineSymbolTable,
+            location: Some(location),
+            inlined_by: Vec::new(),
+            selector: None,
+        }));
     }
 
-    let offset = function_offset(symbol_map, search_address);
+    let offset = function_offset(symbol_table, search_address);
     let (symbol, location) = frames.remove(0);
     let inlined_by = frames
         .into_iter()
@@ -1237,6 +1723,7 @@ fn dwarf_symbolize_address<'data>(
 
     Ok(Some(SymbolizedFrame {
         requested_address,
+        canonical_address: None,
         lookup_address: search_address,
         symbol,
         object_name: object_name.to_string(),
@@ -1244,6 +1731,7 @@ fn dwarf_symbolize_address<'data>(
         resolver: ResolverKind::Dwarf,
         location,
         inlined_by,
+        selector: None,
     }))
 }
 
@@ -1256,6 +1744,7 @@ fn location_from_addr2line(location: &addr2line::Location<'_>) -> Option<SourceL
     Some(SourceLocation {
         file: normalize_debug_path(file),
         line: u64::from(line),
+        column: location.column.filter(|&column| column != 0).map(u64::from),
     })
 }
 
@@ -1278,10 +1767,108 @@ fn normalize_debug_path(path: &str) -> String {
     }
 }
 
-fn function_offset(symbol_map: &SymbolMap<SymbolMapName<'_>>, search_address: u64) -> u64 {
-    symbol_map
-        .get(search_address)
-        .map(|symbol| search_address.saturating_sub(symbol.address()))
+// A SEL is a pointer to the uniqued C string in `__objc_methname`. The register
+// value is a runtime address, so it is slid back the same way frame addresses
+// are before looking it up.
+fn resolve_selector<'data>(
+    resolved: &ResolvedObject<'data>,
+    options: &SymbolizeOptions,
+    text_vmaddr: u64,
+) -> Option<String> {
+    let object = &resolved.object;
+    let names = arch::selector_registers(&resolved.arch_name);
+    let (_, value) = options
+        .registers
+        .iter()
+        .find(|(name, _)| names.iter().any(|wanted| name.eq_ignore_ascii_case(wanted)))?;
+    let address = calculate_search_address(
+        options.load_address,
+        *value,
+        text_vmaddr,
+        options.file_offsets,
+    )
+    .ok()?;
+
+    if let Some(name) = read_objc_methname(object, address) {
+        return Some(name);
+    }
+
+    // The register may instead hold the `__objc_selrefs` slot.
+    let slot = read_section_pointer(object, "__objc_selrefs", address)?;
+    selref_targets(slot, text_vmaddr).find_map(|candidate| read_objc_methname(object, candidate))
+}
+
+// A selref slot on disk holds the selector's vmaddr, or in an image linked
+// with chained fixups a rebase that dyld rewrites into it at launch. A rebase
+// keeps its target in the low bits, under the chain link and flags:
+//
+//   DYLD_CHAINED_PTR_64             target:36, the vmaddr
+//   DYLD_CHAINED_PTR_64_OFFSET      target:36, the offset from the __TEXT base
+//   DYLD_CHAINED_PTR_ARM64E         target:43, the vmaddr
+//   DYLD_CHAINED_PTR_ARM64E_USERLAND target:43, the offset from the __TEXT base
+//
+// Which format applies is recorded in LC_DYLD_CHAINED_FIXUPS, which `object`
+// does not parse, so every reading is offered; only the right one lands in
+// `__objc_methname`.
+fn selref_targets(slot: u64, text_vmaddr: u64) -> impl Iterator<Item = u64> {
+    let targets = [36, 43].map(|bits| slot & ((1 << bits) - 1));
+    std::iter::once(slot).chain(
+        targets
+            .into_iter()
+            .flat_map(move |target| [target, target.wrapping_add(text_vmaddr)]),
+    )
+}
+
+fn section_data_at<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    name: &str,
+    address: u64,
+) -> Option<&'data [u8]> {
+    let section = object.section_by_name(name)?;
+    let offset = address.checked_sub(section.address())?;
+    if offset >= section.size() {
+        return None;
+    }
+    section.data().ok()?.get(usize::try_from(offset).ok()?..)
+}
+
+fn read_objc_methname<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    address: u64,
+) -> Option<String> {
+    let data = section_data_at(object, "__objc_methname", address)?;
+    let end = data.iter().position(|byte| *byte == 0)?;
+    let name = std::str::from_utf8(&data[..end]).ok()?;
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+fn read_section_pointer<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    name: &str,
+    address: u64,
+) -> Option<u64> {
+    let data = section_data_at(object, name, address)?;
+    if object.is_64() {
+        let bytes: [u8; 8] = data.get(..8)?.try_into().ok()?;
+        Some(if object.is_little_endian() {
+            u64::from_le_bytes(bytes)
+        } else {
+            u64::from_be_bytes(bytes)
+        })
+    } else {
+        let bytes: [u8; 4] = data.get(..4)?.try_into().ok()?;
+        Some(u64::from(if object.is_little_endian() {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }))
+    }
+}
+
+fn function_offset(symbol_table: &SymbolTable<'_>, search_address: u64) -> u64 {
+    symbol_table
+        .lookup(search_address)
+        .map(|symbol| search_address.saturating_sub(symbol.address))
         .unwrap_or(0)
 }
 
@@ -1297,6 +1884,43 @@ mod tests {
         assert_eq!(format_uuid(a), "29118F18-9DFC-36A8-9028-A19B13996D5E");
     }
 
+    #[test]
+    fn reads_selref_slots_as_pointers_or_chained_rebases() {
+        let text = 0x1_0000_0000;
+        let targets = |slot| selref_targets(slot, text).collect::<Vec<_>>();
+
+        // Already bound, as in a memory dump or an image without chained fixups.
+        assert!(targets(0x1_0000_4f20).contains(&0x1_0000_4f20));
+        // DYLD_CHAINED_PTR_64_OFFSET: next = 2, target = offset 0x4f20.
+        assert!(targets(2 << 51 | 0x4f20).contains(&0x1_0000_4f20));
+        // DYLD_CHAINED_PTR_64: next = 1, high8 = 0, target = vmaddr.
+        assert!(targets(1 << 51 | 0x1_0000_4f20).contains(&0x1_0000_4f20));
+        // DYLD_CHAINED_PTR_ARM64E: a vmaddr wider than 36 bits.
+        assert!(targets(3 << 51 | 0x18_0000_4f20).contains(&0x18_0000_4f20));
+    }
+
+    #[test]
+    fn infers_arm64e_address_bits_only_from_a_load_address() {
+        let signed = 0x002b_1c01_0000_12a4;
+        let mask = AddressMask::Arm64e;
+        assert_eq!(signed & mask.for_load_address(0x1_0000_0000), 0x1_0000_12a4);
+        assert_eq!(
+            0x2b1c_8001_0000_12a4 & mask.for_load_address(1 << 40),
+            0x0001_0000_12a4
+        );
+        assert_eq!(mask.for_load_address(0), u64::MAX);
+        assert_eq!(
+            AddressMask::Fixed(va_mask(40)).for_load_address(0),
+            va_mask(40)
+        );
+    }
+
+    #[test]
+    fn truncates_macho_section_names() {
+        assert_eq!(macho_section_name(".debug_info"), "__debug_info");
+        assert_eq!(macho_section_name(".debug_str_offsets"), "__debug_str_offs");
+    }
+
     #[test]
     fn parse_uuid_rejects_invalid() {
         assert!(parse_uuid_string("not-a-uuid").is_err());
@@ -1304,10 +1928,50 @@ mod tests {
     }
 
     #[test]
-    fn macho_arch_aliases_match() {
-        assert!(macho_arch_matches_filter("arm64", "aarch64"));
-        assert!(macho_arch_matches_filter("x86_64", "amd64"));
-        assert!(macho_arch_matches_filter("i386", "x86"));
+    fn address_map_translates_range_by_range() {
+        let address_map = AddressMap {
+            kind: AddressMapKind::FileOffsets,
+            ranges: vec![
+                AddressRange {
+                    start: 0,
+                    size: 0x1000,
+                    address: 0x40_0000,
+                },
+                AddressRange {
+                    start: 0x1000,
+                    size: 0x800,
+                    address: 0x40_1000,
+                },
+            ],
+        };
+        assert_eq!(address_map.translate(0, 0x1234).unwrap(), 0x40_1234);
+        assert_eq!(
+            address_map.translate(0x7000_0000, 0x7000_0010).unwrap(),
+            0x40_0010
+        );
+        let error = address_map.translate(0, 0x1800).unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("not inside any loadable segment"));
+    }
+
+    #[test]
+    fn parse_address_token_marks_exact_pc() {
+        assert_eq!(
+            parse_address_token("pc:0x1f00"),
+            Ok(InputAddress {
+                address: 0x1f00,
+                exact_pc: true
+            })
+        );
+        assert_eq!(
+            parse_address_token("42"),
+            Ok(InputAddress {
+                address: 42,
+                exact_pc: false
+            })
+        );
+        assert!(parse_address_token("pc:").is_err());
     }
 
     #[test]
@@ -1332,6 +1996,7 @@ mod tests {
     fn format_text_frame_with_location() {
         let frame = SymbolizedFrame {
             requested_address: 1,
+            canonical_address: None,
             lookup_address: 1,
             symbol: "demo".to_string(),
             object_name: "fixture".to_string(),
@@ -1340,14 +2005,20 @@ mod tests {
             location: Some(SourceLocation {
                 file: "src/main.rs".to_string(),
                 line: 7,
+                column: None,
             }),
             inlined_by: Vec::new(),
+            selector: None,
         };
 
         assert_eq!(
-            format_text_frame(&frame),
+            format_text_frame(&frame, true),
             "demo (in fixture) (src/main.rs:7)"
         );
+        assert_eq!(
+            format_text_frame(&frame, false),
+            "demo (in fixture) (main.rs:7)"
+        );
     }
 
     #[test]
@@ -1374,10 +2045,11 @@ mod tests {
             location: Some(SourceLocation {
                 file: "src/lib.rs".to_string(),
                 line: 3,
+                column: None,
             }),
         };
         assert_eq!(
-            format_inline_frame(&with_location, "fixture"),
+            format_inline_frame(&with_location, "fixture", true),
             "leaf (in fixture) (src/lib.rs:3)"
         );
 
@@ -1386,7 +2058,7 @@ mod tests {
             location: None,
         };
         assert_eq!(
-            format_inline_frame(&without_location, "fixture"),
+            format_inline_frame(&without_location, "fixture", false),
             "leaf (in fixture)"
         );
     }
@@ -0,0 +1,540 @@
+// Symbolication without a dSYM. A Mach-O image linked from objects built with
+// `-g` keeps a debug map in its symbol table: STABS entries naming each object
+// file (`N_OSO`) and where that object's functions (`N_FUN`) and data
+// (`N_STSYM`, `N_GSYM`) ended up. The DWARF itself stays in the objects, so a
+// lookup is translated into the object's own address space and resolved
+// against its (relocated) DWARF, which is what lldb does.
+use crate::atosl::{is_object_dwarf, load_dwarf_sections, DwarfContext};
+use crate::relocate::SectionLayout;
+use anyhow::{anyhow, Result};
+use gimli::{EndianSlice, RunTimeEndian};
+use object::macho;
+use object::read::archive::ArchiveFile;
+use object::read::macho::{MachHeader, Nlist};
+use object::{Endianness, Object, ObjectSection, ObjectSymbol, SectionIndex};
+use std::borrow::Cow;
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// One `N_OSO` entry of the debug map and the symbols linked from it.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct DebugMapObject<'data> {
+    /// The object path, or `archive.a(member.o)` for an archive member.
+    pub path: &'data str,
+    /// Modification time recorded by the linker; 0 when it was zeroed.
+    pub mtime: u64,
+    pub symbols: Vec<DebugMapSymbol<'data>>,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct DebugMapSymbol<'data> {
+    pub name: &'data str,
+    /// Address in the linked image.
+    pub address: u64,
+    /// Only functions record their size; data extends to the next symbol.
+    pub size: Option<u64>,
+}
+
+/// An object's DWARF sections with relocations applied, plus the ranges of the
+/// linked image that came from it, sorted by linked address.
+pub(crate) struct LoadedObject<'data> {
+    endian: RunTimeEndian,
+    sections: gimli::DwarfSections<Cow<'data, [u8]>>,
+    ranges: Vec<MappedRange>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+struct MappedRange {
+    address: u64,
+    end: u64,
+    object_address: u64,
+}
+
+/// The objects of a debug map, each parsed and relocated the first time a
+/// lookup needs it and kept for later lookups. A failure is kept too, so a
+/// missing or rebuilt object is reported once.
+pub(crate) struct DebugMapObjects<'data> {
+    entries: Vec<DebugMapObject<'data>>,
+    files: &'data HashMap<String, memmap2::Mmap>,
+    loaded: Vec<OnceCell<Option<LoadedObject<'data>>>>,
+    verbose: bool,
+}
+
+impl<'data> DebugMapObjects<'data> {
+    pub(crate) fn new(
+        entries: Vec<DebugMapObject<'data>>,
+        files: &'data HashMap<String, memmap2::Mmap>,
+        verbose: bool,
+    ) -> Self {
+        let loaded = entries.iter().map(|_| OnceCell::new()).collect();
+        Self {
+            entries,
+            files,
+            loaded,
+            verbose,
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn get(&self, index: usize) -> Option<&LoadedObject<'data>> {
+        self.loaded[index]
+            .get_or_init(|| {
+                let entry = &self.entries[index];
+                match load_object(entry, self.files) {
+                    Ok(Some(object)) => {
+                        if self.verbose {
+                            eprintln!("debug_map: loaded {}", entry.path);
+                        }
+                        Some(object)
+                    }
+                    Ok(None) => None,
+                    Err(err) => {
+                        if self.verbose {
+                            eprintln!("debug_map: skipping {}: {err:#}", entry.path);
+                        }
+                        None
+                    }
+                }
+            })
+            .as_ref()
+    }
+}
+
+pub(crate) struct DebugMap<'a> {
+    objects: &'a DebugMapObjects<'a>,
+    /// `(address, end, index into objects)`, sorted by address: the part of
+    /// the linked image each object covers according to the debug map alone,
+    /// which decides the object to load for a lookup.
+    spans: Vec<(u64, u64, usize)>,
+    contexts: Vec<OnceCell<Option<DwarfContext<'a>>>>,
+}
+
+impl<'a> DebugMap<'a> {
+    pub(crate) fn new(objects: &'a DebugMapObjects<'a>) -> Self {
+        let mut starts = objects
+            .entries
+            .iter()
+            .flat_map(|entry| entry.symbols.iter().map(|symbol| symbol.address))
+            .collect::<Vec<_>>();
+        starts.sort_unstable();
+
+        // Data symbols record no size; until their object is loaded, they are
+        // taken to reach the next symbol of the debug map.
+        let mut spans = Vec::new();
+        for (index, entry) in objects.entries.iter().enumerate() {
+            for symbol in &entry.symbols {
+                let end = match symbol.size {
+                    Some(size) => symbol.address + size,
+                    None => {
+                        let next = starts.partition_point(|&start| start <= symbol.address);
+                        starts.get(next).copied().unwrap_or(u64::MAX)
+                    }
+                };
+                spans.push((symbol.address, end, index));
+            }
+        }
+        spans.sort_unstable_by_key(|&(address, _, _)| address);
+
+        Self {
+            objects,
+            spans,
+            contexts: objects.entries.iter().map(|_| OnceCell::new()).collect(),
+        }
+    }
+
+    /// Returns the DWARF context of the object `address` was linked from and
+    /// the matching address inside that object.
+    pub(crate) fn locate(&self, address: u64) -> Option<(&DwarfContext<'a>, u64)> {
+        let &(_, _, index) = find_range(&self.spans, address, |&(start, end, _)| (start, end))?;
+        let object = self.objects.get(index)?;
+        let range = find_range(&object.ranges, address, |range| (range.address, range.end))?;
+        let context = self.contexts[index]
+            .get_or_init(|| {
+                let dwarf = object
+                    .sections
+                    .borrow(|section| EndianSlice::new(section.as_ref(), object.endian));
+                match DwarfContext::from_dwarf(dwarf) {
+                    Ok(context) => Some(context),
+                    Err(err) => {
+                        if self.objects.verbose {
+                            eprintln!(
+                                "debug_map: skipping {}: failed to build DWARF context: {err}",
+                                self.objects.entries[index].path
+                            );
+                        }
+                        None
+                    }
+                }
+            })
+            .as_ref()?;
+        Some((context, range.object_address + (address - range.address)))
+    }
+}
+
+// Finds the entry of `ranges`, sorted by start, whose `[start, end)` holds
+// `address`.
+fn find_range<T>(ranges: &[T], address: u64, bounds: impl Fn(&T) -> (u64, u64)) -> Option<&T> {
+    let index = ranges.partition_point(|range| bounds(range).0 <= address);
+    let range = &ranges[index.checked_sub(1)?];
+    (address < bounds(range).1).then_some(range)
+}
+
+/// Reads the debug map of a Mach-O image. Returns nothing for other formats,
+/// or when the image carries no `N_OSO` entries.
+pub(crate) fn read_debug_map<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    raw_data: Option<&'data [u8]>,
+) -> Vec<DebugMapObject<'data>> {
+    let (object::BinaryFormat::MachO, Some(data)) = (object.format(), raw_data) else {
+        return Vec::new();
+    };
+    if object.is_64() {
+        macho_stabs::<macho::MachHeader64<Endianness>>(data)
+    } else {
+        macho_stabs::<macho::MachHeader32<Endianness>>(data)
+    }
+    .unwrap_or_default()
+}
+
+fn macho_stabs<'data, Mach: MachHeader<Endian = Endianness>>(
+    data: &'data [u8],
+) -> Option<Vec<DebugMapObject<'data>>> {
+    let header = Mach::parse(data, 0).ok()?;
+    let endian = header.endian().ok()?;
+    let mut commands = header.load_commands(endian, data, 0).ok()?;
+    let mut symtab = None;
+    while let Ok(Some(command)) = commands.next() {
+        if let Ok(Some(command)) = command.symtab() {
+            symtab = Some(command);
+        }
+    }
+    let symbols = symtab?.symbols::<Mach, _>(endian, data).ok()?;
+
+    let mut stabs = Vec::new();
+    let mut globals = HashMap::new();
+    for nlist in symbols.iter() {
+        let Some(name) = nlist
+            .name(endian, symbols.strings())
+            .ok()
+            .and_then(|name| std::str::from_utf8(name).ok())
+        else {
+            continue;
+        };
+        let value = nlist.n_value(endian).into();
+        if nlist.is_stab() {
+            stabs.push((nlist.n_type(), name, value));
+        } else if nlist.n_type() & macho::N_TYPE == macho::N_SECT {
+            globals.insert(name, value);
+        }
+    }
+    Some(collect_objects(stabs, &globals))
+}
+
+// Groups stabs by the `N_OSO` that precedes them. A function is an `N_FUN`
+// carrying its name and start address followed by an unnamed `N_FUN` carrying
+// its size; `N_GSYM` records no address, so it comes from the image's own
+// symbol of the same name.
+fn collect_objects<'data>(
+    stabs: Vec<(u8, &'data str, u64)>,
+    globals: &HashMap<&'data str, u64>,
+) -> Vec<DebugMapObject<'data>> {
+    let mut objects: Vec<DebugMapObject<'data>> = Vec::new();
+    let mut open = false;
+    let mut function = None;
+    for (kind, name, value) in stabs {
+        if kind == macho::N_OSO {
+            objects.push(DebugMapObject {
+                path: name,
+                mtime: value,
+                symbols: Vec::new(),
+            });
+            open = true;
+            continue;
+        }
+        if kind == macho::N_SO && name.is_empty() {
+            open = false;
+            continue;
+        }
+        let Some(current) = objects.last_mut().filter(|_| open) else {
+            continue;
+        };
+        match kind {
+            macho::N_FUN if !name.is_empty() => function = Some((name, value)),
+            macho::N_FUN => {
+                if let Some((name, address)) = function.take() {
+                    current.symbols.push(DebugMapSymbol {
+                        name,
+                        address,
+                        size: Some(value),
+                    });
+                }
+            }
+            macho::N_STSYM => current.symbols.push(DebugMapSymbol {
+                name,
+                address: value,
+                size: None,
+            }),
+            macho::N_GSYM => {
+                if let Some(&address) = globals.get(name) {
+                    current.symbols.push(DebugMapSymbol {
+                        name,
+                        address,
+                        size: None,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+    objects.retain(|object| !object.symbols.is_empty());
+    objects
+}
+
+/// Maps every file the debug map refers to, keyed by path. An archive is
+/// mapped once for all of its members. Missing files are reported in verbose
+/// mode and otherwise skipped: their functions fall back to the symbol table.
+pub(crate) fn map_object_files(
+    objects: &[DebugMapObject<'_>],
+    verbose: bool,
+) -> HashMap<String, memmap2::Mmap> {
+    let mut files = HashMap::new();
+    for object in objects {
+        let (path, _) = split_archive_member(object.path);
+        if files.contains_key(path) {
+            continue;
+        }
+        let mapped = fs::File::open(path).and_then(|file| unsafe { memmap2::Mmap::map(&file) });
+        match mapped {
+            Ok(mmap) => {
+                files.insert(path.to_string(), mmap);
+            }
+            Err(err) => {
+                if verbose {
+                    eprintln!("debug_map: skipping {path}: {err}");
+                }
+            }
+        }
+    }
+    files
+}
+
+/// Parses a mapped object, applies its relocations to the DWARF sections and
+/// pairs its symbols with the linked addresses from the debug map.
+fn load_object<'data>(
+    entry: &DebugMapObject<'data>,
+    files: &'data HashMap<String, memmap2::Mmap>,
+) -> Result<Option<LoadedObject<'data>>> {
+    let (path, member) = split_archive_member(entry.path);
+    let Some(file) = files.get(path) else {
+        return Ok(None);
+    };
+    let data = match member {
+        Some(member) => archive_member(file, member, entry.mtime)?,
+        None => {
+            check_mtime(path, entry.mtime)?;
+            &file[..]
+        }
+    };
+
+    let object = object::File::parse(data)?;
+    if !is_object_dwarf(&object) {
+        return Ok(None);
+    }
+    let endian = if object.is_little_endian() {
+        RunTimeEndian::Little
+    } else {
+        RunTimeEndian::Big
+    };
+
+    let mut addresses = HashMap::new();
+    let mut starts = Vec::new();
+    for symbol in object.symbols() {
+        let Some(section) = symbol.section_index() else {
+            continue;
+        };
+        if let Ok(name) = symbol.name() {
+            addresses.insert(name, symbol.address());
+        }
+        starts.push((section, symbol.address()));
+    }
+    starts.sort_unstable_by_key(|&(_, address)| address);
+
+    let mut ranges = Vec::new();
+    for symbol in &entry.symbols {
+        let Some(&object_address) = addresses.get(symbol.name) else {
+            continue;
+        };
+        let size = symbol
+            .size
+            .or_else(|| data_extent(&object, &starts, object_address))
+            .unwrap_or(0);
+        if size > 0 {
+            ranges.push(MappedRange {
+                address: symbol.address,
+                end: symbol.address + size,
+                object_address,
+            });
+        }
+    }
+
+    let sections = load_dwarf_sections(&object, Some(&SectionLayout::native()))?;
+
+    ranges.sort_unstable_by_key(|range| range.address);
+
+    Ok(Some(LoadedObject {
+        endian,
+        sections,
+        ranges,
+    }))
+}
+
+// Splits `libfoo.a(bar.o)` into the archive path and the member name.
+fn split_archive_member(path: &str) -> (&str, Option<&str>) {
+    match path
+        .strip_suffix(')')
+        .and_then(|rest| rest.rsplit_once('('))
+    {
+        Some((archive, member)) if !archive.is_empty() => (archive, Some(member)),
+        _ => (path, None),
+    }
+}
+
+fn archive_member<'data>(data: &'data [u8], name: &str, mtime: u64) -> Result<&'data [u8]> {
+    let archive = ArchiveFile::parse(data)?;
+    for member in archive.members() {
+        let member = member?;
+        if member.name() != name.as_bytes() {
+            continue;
+        }
+        if let Some(date) = member.date() {
+            if mtime != 0 && date != 0 && date != mtime {
+                return Err(anyhow!("archive member {name} changed since it was linked"));
+            }
+        }
+        return Ok(member.data(data)?);
+    }
+    Err(anyhow!("archive has no member {name}"))
+}
+
+// An object rebuilt after linking no longer matches the debug map. Linkers
+// zero the timestamp for reproducible builds, so 0 means "unknown".
+fn check_mtime(path: &str, mtime: u64) -> Result<()> {
+    if mtime == 0 {
+        return Ok(());
+    }
+    let modified = fs::metadata(Path::new(path))?
+        .modified()?
+        .duration_since(UNIX_EPOCH)?
+        .as_secs();
+    if modified != mtime {
+        return Err(anyhow!("object file changed since it was linked"));
+    }
+    Ok(())
+}
+
+// Data symbols carry no size in the debug map; they extend to the next symbol
+// of the same section, or to the end of that section.
+fn data_extent<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    starts: &[(SectionIndex, u64)],
+    address: u64,
+) -> Option<u64> {
+    let (section_index, _) = *starts.iter().find(|&&(_, start)| start == address)?;
+    let next = starts
+        .iter()
+        .filter(|&&(section, start)| section == section_index && start > address)
+        .map(|&(_, start)| start)
+        .next();
+    let end = match next {
+        Some(next) => next,
+        None => {
+            let section = object.section_by_index(section_index).ok()?;
+            section.address() + section.size()
+        }
+    };
+    end.checked_sub(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_stabs_by_object() {
+        let globals = HashMap::from([("_counter", 0x1_0000_8000)]);
+        let stabs = vec![
+            (macho::N_SO, "/src/", 0),
+            (macho::N_SO, "main.c", 0),
+            (macho::N_OSO, "/build/main.o", 1_700_000_000),
+            (macho::N_BNSYM, "", 0x1_0000_3f00),
+            (macho::N_FUN, "_main", 0x1_0000_3f00),
+            (macho::N_FUN, "", 0x40),
+            (macho::N_ENSYM, "", 0x1_0000_3f00),
+            (macho::N_STSYM, "_table", 0x1_0000_8010),
+            (macho::N_GSYM, "_counter", 0),
+            (macho::N_SO, "", 0),
+            (macho::N_FUN, "_orphan", 0x1_0000_3f80),
+            (macho::N_FUN, "", 0x10),
+            (macho::N_OSO, "/build/libutil.a(util.o)", 0),
+            (macho::N_FUN, "_util", 0x1_0000_3f40),
+            (macho::N_FUN, "", 0x20),
+            (macho::N_SO, "", 0),
+        ];
+
+        let objects = collect_objects(stabs, &globals);
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].path, "/build/main.o");
+        assert_eq!(objects[0].mtime, 1_700_000_000);
+        assert_eq!(
+            objects[0].symbols,
+            vec![
+                DebugMapSymbol {
+                    name: "_main",
+                    address: 0x1_0000_3f00,
+                    size: Some(0x40),
+                },
+                DebugMapSymbol {
+                    name: "_table",
+                    address: 0x1_0000_8010,
+                    size: None,
+                },
+                DebugMapSymbol {
+                    name: "_counter",
+                    address: 0x1_0000_8000,
+                    size: None,
+                },
+            ]
+        );
+        assert_eq!(objects[1].path, "/build/libutil.a(util.o)");
+        assert_eq!(objects[1].symbols[0].name, "_util");
+    }
+
+    #[test]
+    fn splits_archive_member_paths() {
+        assert_eq!(
+            split_archive_member("/build/libutil.a(util.o)"),
+            ("/build/libutil.a", Some("util.o"))
+        );
+        assert_eq!(
+            split_archive_member("/build/main.o"),
+            ("/build/main.o", None)
+        );
+        assert_eq!(split_archive_member("(odd)"), ("(odd)", None));
+    }
+
+    #[test]
+    fn finds_linked_range() {
+        let ranges = vec![(0x1000, 0x1040, 0), (0x1040, 0x1060, 1)];
+        let bounds = |&(start, end, _): &(u64, u64, usize)| (start, end);
+        assert_eq!(find_range(&ranges, 0x1010, bounds), Some(&ranges[0]));
+        assert_eq!(find_range(&ranges, 0x1040, bounds), Some(&ranges[1]));
+        assert_eq!(find_range(&ranges, 0x1060, bounds), None);
+        assert_eq!(find_range(&ranges, 0xfff, bounds), None);
+    }
+}
@@ -0,0 +1,556 @@
+//! The debuginfod protocol.
+//!
+//! [`DebuginfodClient`] fetches debug files, executables and sources by ELF
+//! build-id from the servers listed in `DEBUGINFOD_URLS`, and stores them in
+//! the cache layout the elfutils client uses (`<cache>/<build-id>/debuginfo`),
+//! so later lookups, by atosl or by gdb, find them without the network.
+//!
+//! [`DebuginfodServer`] answers the same requests from local directories, so a
+//! build machine can share its outputs.
+use crate::atosl::load_dwarf_sections;
+use crate::http;
+use crate::index;
+use crate::locate::{self, ScannedModule};
+use anyhow::{anyhow, bail, Context as _, Result};
+use object::read::macho::{FatArch, FatHeader};
+use object::{Object, ObjectKind, ObjectSection};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for a server to connect or send more data, as
+/// `DEBUGINFOD_TIMEOUT` does when unset.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(90);
+
+/// What to fetch for a build-id.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Artifact {
+    /// The separate debug file.
+    Debuginfo,
+    /// The executable or shared library.
+    Executable,
+    /// A source file, by the absolute path recorded in the DWARF.
+    Source(String),
+}
+
+impl Artifact {
+    fn url_path(&self) -> String {
+        match self {
+            Artifact::Debuginfo => "debuginfo".to_string(),
+            Artifact::Executable => "executable".to_string(),
+            Artifact::Source(path) => format!("source/{}", path.trim_start_matches('/')),
+        }
+    }
+
+    // elfutils flattens the source path into one file name, `/` becoming `#`.
+    fn cache_name(&self) -> String {
+        match self {
+            Artifact::Source(path) => format!("source{}", path.replace('/', "#")),
+            _ => self.url_path(),
+        }
+    }
+}
+
+/// Fetches artifacts from debuginfod servers into a local cache.
+#[derive(Clone, Debug)]
+pub struct DebuginfodClient {
+    pub urls: Vec<String>,
+    pub cache_dir: PathBuf,
+    pub timeout: Duration,
+}
+
+impl DebuginfodClient {
+    /// The client configured like elfutils': the space-separated
+    /// `DEBUGINFOD_URLS`, the cache from `DEBUGINFOD_CACHE_PATH` (or the XDG
+    /// cache), and `DEBUGINFOD_TIMEOUT` in seconds. `None` without servers.
+    pub fn from_env() -> Option<Self> {
+        let urls = env::var("DEBUGINFOD_URLS")
+            .unwrap_or_default()
+            .split_whitespace()
+            .map(str::to_string)
+            .collect::<Vec<_>>();
+        if urls.is_empty() {
+            return None;
+        }
+        let timeout = env::var("DEBUGINFOD_TIMEOUT")
+            .ok()
+            .and_then(|value| value.trim().parse().ok())
+            .map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+        Some(Self {
+            urls,
+            cache_dir: locate::debuginfod_cache_dirs().into_iter().next()?,
+            timeout,
+        })
+    }
+
+    /// The cached `artifact` of `build_id`, fetched from the first server that
+    /// has it when it is not cached yet. `Ok(None)` when no server has it;
+    /// an error when none has it and some could not be asked.
+    ///
+    /// A downloaded debug file or executable must carry `build_id`; one that
+    /// does not is discarded.
+    pub fn fetch(&self, build_id: &[u8], artifact: &Artifact) -> Result<Option<PathBuf>> {
+        let hex = build_id
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<String>();
+        let cached = self.cache_dir.join(&hex).join(artifact.cache_name());
+        if cached.is_file() {
+            return Ok(Some(cached));
+        }
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(self.timeout)
+            .timeout_read(self.timeout)
+            .build();
+        let mut failure = None;
+        for server in &self.urls {
+            let url = format!(
+                "{}/buildid/{hex}/{}",
+                server.trim_end_matches('/'),
+                artifact.url_path()
+            );
+            match download(&agent, &url, &cached, &hex, artifact) {
+                Ok(true) => return Ok(Some(cached)),
+                Ok(false) => {}
+                Err(err) => failure = Some(err.context(url)),
+            }
+        }
+        match failure {
+            Some(err) => Err(err),
+            None => Ok(None),
+        }
+    }
+}
+
+// Downloads `url` to `target`; `false` when the server does not have it.
+fn download(
+    agent: &ureq::Agent,
+    url: &str,
+    target: &Path,
+    hex: &str,
+    artifact: &Artifact,
+) -> Result<bool> {
+    let response = match agent.get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::Status(404, _)) => return Ok(false),
+        Err(err) => return Err(anyhow!(err)),
+    };
+
+    let dir = target.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(dir)
+        .with_context(|| format!("failed to create cache directory {}", dir.display()))?;
+    // Each download gets a file of its own, so two fetches of the same id
+    // never write into one another, and it is dropped unless it checks out.
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("failed to create a file in {}", dir.display()))?;
+    io::copy(&mut response.into_reader(), &mut file)?;
+    if !matches!(artifact, Artifact::Source(_))
+        && !locate::scan_file(file.path())
+            .iter()
+            .any(|module| module.id == hex)
+    {
+        bail!("downloaded file does not carry build-id {hex}");
+    }
+    file.persist(target)
+        .with_context(|| format!("failed to write {}", target.display()))?;
+    Ok(true)
+}
+
+/// How often a request for an unknown id may rescan the served directories.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Serves the binaries, debug files and dSYM payloads under a set of
+/// directories over the debuginfod protocol, by ELF build-id or Mach-O UUID.
+#[derive(Debug)]
+pub struct DebuginfodServer {
+    roots: Vec<PathBuf>,
+    modules: Mutex<ServedModules>,
+    verbose: bool,
+}
+
+#[derive(Debug)]
+struct ServedModules {
+    by_id: HashMap<String, Vec<(PathBuf, bool)>>,
+    scanned_at: Instant,
+    /// Set while one request rescans, so that others neither wait for it nor
+    /// start their own.
+    scanning: bool,
+}
+
+/// The answer to one request: a status, and the file to send with a 200.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Reply {
+    pub status: u16,
+    pub file: Option<PathBuf>,
+}
+
+impl Reply {
+    fn status(status: u16) -> Self {
+        Self { status, file: None }
+    }
+}
+
+impl DebuginfodServer {
+    /// Scans `roots`; a root indexed with `atosl index build` is read from its
+    /// index.
+    pub fn new(roots: Vec<PathBuf>, verbose: bool) -> Result<Self> {
+        let by_id = scan_roots(&roots)?;
+        Ok(Self {
+            roots,
+            modules: Mutex::new(ServedModules {
+                by_id,
+                scanned_at: Instant::now(),
+                scanning: false,
+            }),
+            verbose,
+        })
+    }
+
+    /// How many module ids are served.
+    pub fn module_count(&self) -> usize {
+        self.modules.lock().expect("modules lock").by_id.len()
+    }
+
+    /// Answers a request for `url`: `/buildid/<id>/debuginfo`,
+    /// `/buildid/<id>/executable` or `/buildid/<id>/source/<path>`.
+    pub fn reply(&self, url: &str) -> Reply {
+        let path = url.split(['?', '#']).next().unwrap_or_default();
+        let Some(rest) = path.strip_prefix("/buildid/") else {
+            return Reply::status(404);
+        };
+        let (id, artifact) = rest.split_once('/').unwrap_or((rest, ""));
+        let id = locate::normalize_hex_id(id);
+        let artifact = match artifact {
+            "debuginfo" => Artifact::Debuginfo,
+            "executable" => Artifact::Executable,
+            _ => match artifact.strip_prefix("source/") {
+                Some(source) => Artifact::Source(format!("/{}", percent_decode(source))),
+                None => return Reply::status(400),
+            },
+        };
+        if id.is_empty() {
+            return Reply::status(400);
+        }
+
+        let mut files = self.files(&id);
+        if files.is_empty() {
+            self.rescan();
+            files = self.files(&id);
+        }
+        let found = match &artifact {
+            Artifact::Debuginfo => files
+                .iter()
+                .find(|(_, dwarf)| *dwarf)
+                .map(|(path, _)| path.clone()),
+            Artifact::Executable => files
+                .iter()
+                .map(|(path, _)| path)
+                .find(|path| is_executable(path, &id))
+                .cloned(),
+            // Only sources the module's DWARF names, never arbitrary files.
+            Artifact::Source(source) => files
+                .iter()
+                .filter(|(_, dwarf)| *dwarf)
+                .any(|(path, _)| {
+                    dwarf_source_files(path, &id)
+                        .iter()
+                        .any(|file| file == Path::new(source))
+                })
+                .then(|| PathBuf::from(source))
+                .filter(|source| source.is_file()),
+        };
+        match found {
+            Some(file) => Reply {
+                status: 200,
+                file: Some(file),
+            },
+            None => Reply::status(404),
+        }
+    }
+
+    /// Answers HTTP requests on `listener` until it fails, on a few threads.
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        let workers = thread::available_parallelism()
+            .map_or(1, usize::from)
+            .min(8);
+        http::serve_with_workers(listener, workers, |request| self.answer(request))
+    }
+
+    fn answer(&self, request: tiny_http::Request) -> io::Result<()> {
+        let reply = self.reply(request.url());
+        if self.verbose {
+            eprintln!("{} {} {}", reply.status, request.method(), request.url());
+        }
+        let file = reply
+            .file
+            .as_ref()
+            .and_then(|path| fs::File::open(path).ok());
+        match (file, &reply.file) {
+            (Some(file), Some(path)) => {
+                let mut response = tiny_http::Response::from_file(file);
+                let value = header_value(path);
+                if let Ok(header) =
+                    tiny_http::Header::from_bytes(&b"X-DEBUGINFOD-FILE"[..], value.as_bytes())
+                {
+                    response.add_header(header);
+                }
+                request.respond(response)
+            }
+            _ => request.respond(tiny_http::Response::empty(match reply.status {
+                200 => 404,
+                status => status,
+            })),
+        }
+    }
+
+    fn files(&self, id: &str) -> Vec<(PathBuf, bool)> {
+        let modules = self.modules.lock().expect("modules lock");
+        modules.by_id.get(id).cloned().unwrap_or_default()
+    }
+
+    // The walk runs without the lock, so requests for known ids are answered
+    // from the previous scan until it is swapped in.
+    fn rescan(&self) {
+        {
+            let mut modules = self.modules.lock().expect("modules lock");
+            if modules.scanning || modules.scanned_at.elapsed() < RESCAN_INTERVAL {
+                return;
+            }
+            modules.scanning = true;
+        }
+        let scanned = scan_roots(&self.roots);
+        let mut modules = self.modules.lock().expect("modules lock");
+        modules.scanning = false;
+        modules.scanned_at = Instant::now();
+        match scanned {
+            Ok(by_id) => modules.by_id = by_id,
+            Err(err) => eprintln!("rescan failed: {err:#}"),
+        }
+    }
+}
+
+// Header values are printable ASCII, so the rest of a path is percent-encoded,
+// as elfutils does.
+fn header_value(path: &Path) -> String {
+    let mut value = String::new();
+    for &byte in path.to_string_lossy().as_bytes() {
+        match byte {
+            b'%' => value.push_str("%25"),
+            b' '..=b'~' => value.push(char::from(byte)),
+            _ => value.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    value
+}
+
+// Module id → `(file, carries DWARF)`, DWARF-bearing files first.
+fn scan_roots(roots: &[PathBuf]) -> Result<HashMap<String, Vec<(PathBuf, bool)>>> {
+    let mut by_id = HashMap::<String, Vec<(PathBuf, bool)>>::new();
+    for root in roots {
+        let scanned = match index::indexed_modules(root)? {
+            Some(scanned) => scanned,
+            None => {
+                let mut files = Vec::new();
+                locate::collect_candidate_files(root, &mut files)?;
+                files.sort();
+                let modules = locate::parallel_map(&files, |file| locate::scan_file(file));
+                files.into_iter().zip(modules).collect()
+            }
+        };
+        for (path, modules) in scanned {
+            for ScannedModule { id, dwarf, .. } in modules {
+                by_id.entry(id).or_default().push((path.clone(), dwarf));
+            }
+        }
+    }
+    for files in by_id.values_mut() {
+        files.sort_by_key(|(_, dwarf)| !dwarf);
+    }
+    Ok(by_id)
+}
+
+// The slice of `data` whose UUID or build-id is `id`.
+fn slice_with_id<'data>(data: &'data [u8], id: &str) -> Option<&'data [u8]> {
+    let matches = |slice: &&[u8]| {
+        locate::collect_modules(slice)
+            .iter()
+            .any(|module| module.id == id)
+    };
+    match object::FileKind::parse(data).ok()? {
+        object::FileKind::MachOFat32 => FatHeader::parse_arch32(data)
+            .ok()?
+            .iter()
+            .filter_map(|arch| arch.data(data).ok())
+            .find(matches),
+        object::FileKind::MachOFat64 => FatHeader::parse_arch64(data)
+            .ok()?
+            .iter()
+            .filter_map(|arch| arch.data(data).ok())
+            .find(matches),
+        _ => Some(data).filter(matches),
+    }
+}
+
+// An executable or library with its code, not a debug-only file: a dSYM is no
+// Mach-O kind object knows, and `objcopy --only-keep-debug` leaves `.text`
+// without file contents.
+fn is_executable(path: &Path, id: &str) -> bool {
+    let Ok(handle) = fs::File::open(path) else {
+        return false;
+    };
+    let Ok(mmap) = (unsafe { memmap2::Mmap::map(&handle) }) else {
+        return false;
+    };
+    let Some(file) = slice_with_id(&mmap, id).and_then(|slice| object::File::parse(slice).ok())
+    else {
+        return false;
+    };
+    matches!(
+        file.kind(),
+        ObjectKind::Executable | ObjectKind::Dynamic | ObjectKind::Relocatable
+    ) && file
+        .section_by_name(".text")
+        .or_else(|| file.section_by_name("__text"))
+        .is_none_or(|text| text.file_range().is_some())
+}
+
+// The source files named by the line programs of the module `id` in `path`.
+fn dwarf_source_files(path: &Path, id: &str) -> Vec<PathBuf> {
+    let Ok(handle) = fs::File::open(path) else {
+        return Vec::new();
+    };
+    let Ok(mmap) = (unsafe { memmap2::Mmap::map(&handle) }) else {
+        return Vec::new();
+    };
+    let Some(file) = slice_with_id(&mmap, id).and_then(|slice| object::File::parse(slice).ok())
+    else {
+        return Vec::new();
+    };
+    let endian = if file.is_little_endian() {
+        gimli::RunTimeEndian::Little
+    } else {
+        gimli::RunTimeEndian::Big
+    };
+    let Ok(sections) = load_dwarf_sections(&file, None) else {
+        return Vec::new();
+    };
+    let dwarf = sections.borrow(|section| gimli::EndianSlice::new(section, endian));
+
+    let mut sources = Vec::new();
+    let mut units = dwarf.units();
+    while let Ok(Some(header)) = units.next() {
+        let Ok(unit) = dwarf.unit(header) else {
+            continue;
+        };
+        let Some(program) = &unit.line_program else {
+            continue;
+        };
+        let header = program.header();
+        let comp_dir = unit
+            .comp_dir
+            .map(|dir| PathBuf::from(dir.to_string_lossy().into_owned()))
+            .unwrap_or_default();
+        let string = |value| {
+            dwarf
+                .attr_string(&unit, value)
+                .ok()
+                .map(|text| text.to_string_lossy().into_owned())
+        };
+        for entry in header.file_names() {
+            let Some(name) = string(entry.path_name()) else {
+                continue;
+            };
+            let dir = entry
+                .directory(header)
+                .and_then(string)
+                .map(PathBuf::from)
+                .unwrap_or_default();
+            sources.push(comp_dir.join(dir).join(name));
+        }
+    }
+    sources
+}
+
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = (bytes[index] == b'%')
+            .then(|| text.get(index + 1..index + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn artifacts_map_to_debuginfod_urls_and_cache_names() {
+        let source = Artifact::Source("/usr/src/app/main.c".to_string());
+        assert_eq!(source.url_path(), "source/usr/src/app/main.c");
+        assert_eq!(source.cache_name(), "source#usr#src#app#main.c");
+        assert_eq!(Artifact::Debuginfo.url_path(), "debuginfo");
+        assert_eq!(Artifact::Executable.cache_name(), "executable");
+    }
+
+    #[test]
+    fn rescans_once_at_a_time() {
+        let root = tempfile::tempdir().unwrap();
+        let server = DebuginfodServer::new(vec![root.path().to_path_buf()], false).unwrap();
+        let stale = Instant::now() - RESCAN_INTERVAL * 2;
+        let scanned_at = || server.modules.lock().unwrap().scanned_at;
+
+        // Another request is already scanning: this one leaves it to that.
+        {
+            let mut modules = server.modules.lock().unwrap();
+            modules.scanned_at = stale;
+            modules.scanning = true;
+        }
+        server.rescan();
+        assert_eq!(scanned_at(), stale);
+
+        server.modules.lock().unwrap().scanning = false;
+        server.rescan();
+        assert!(scanned_at() > stale);
+        assert!(!server.modules.lock().unwrap().scanning);
+    }
+
+    #[test]
+    fn server_rejects_malformed_requests() {
+        let root = tempfile::tempdir().unwrap();
+        let server = DebuginfodServer::new(vec![root.path().to_path_buf()], false).unwrap();
+        assert_eq!(server.reply("/buildid/abcd/debuginfo").status, 404);
+        assert_eq!(server.reply("/buildid/abcd/symbols").status, 400);
+        assert_eq!(server.reply("/metrics").status, 404);
+        assert_eq!(percent_decode("my%20app/a%2Bb.c%"), "my app/a+b.c%");
+        assert_eq!(
+            header_value(Path::new("/srv/débug/100%/app")),
+            "/srv/d%C3%A9bug/100%25/app"
+        );
+        assert_eq!(
+            percent_decode(&header_value(Path::new("/srv/débug"))),
+            "/srv/débug"
+        );
+    }
+}
@@ -0,0 +1,202 @@
+// A stripped Mach-O binary keeps only a sparse symbol table; its DWARF lives in
+// a `.dSYM` bundle produced at build time. These helpers find the bundle for a
+// slice by its `LC_UUID`, the way lldb and Xcode do.
+use crate::arch;
+use object::read::macho::{FatArch, FatHeader};
+use object::Object;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// `(arch, uuid)` of every Mach-O slice without `__debug_line`.
+pub(crate) fn stripped_slice_uuids(data: &[u8]) -> Vec<(String, [u8; 16])> {
+    match object::FileKind::parse(data) {
+        Ok(object::FileKind::MachOFat32) => FatHeader::parse_arch32(data)
+            .map(|arches| fat_stripped_slice_uuids(arches, data))
+            .unwrap_or_default(),
+        Ok(object::FileKind::MachOFat64) => FatHeader::parse_arch64(data)
+            .map(|arches| fat_stripped_slice_uuids(arches, data))
+            .unwrap_or_default(),
+        Ok(object::FileKind::MachO32 | object::FileKind::MachO64) => {
+            stripped_slice_uuid(data).into_iter().collect()
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn fat_stripped_slice_uuids<A: FatArch>(arches: &[A], data: &[u8]) -> Vec<(String, [u8; 16])> {
+    arches
+        .iter()
+        .filter_map(|arch| arch.data(data).ok())
+        .filter_map(stripped_slice_uuid)
+        .collect()
+}
+
+fn stripped_slice_uuid(data: &[u8]) -> Option<(String, [u8; 16])> {
+    let file = object::File::parse(data).ok()?;
+    if file.section_by_name("__debug_line").is_some() {
+        return None;
+    }
+    let uuid = file.mach_uuid().ok()??;
+    Some((arch::file_arch_name(data)?, uuid))
+}
+
+// Bundles in search order: `<Binary>.dSYM` beside the binary; the bundles
+// inside and beside each enclosing `.app` (Xcode's build products); the
+// `dSYMs` folder of an enclosing `.xcarchive`; then every bundle under the
+// configured roots.
+pub(crate) fn candidate_bundles(path: Option<&Path>, dsym_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut bundles = Vec::new();
+    if let Some((dir, name)) = path.and_then(|path| Some((path.parent()?, path.file_name()?))) {
+        let mut bundle_name = name.to_os_string();
+        bundle_name.push(".dSYM");
+        bundles.push(dir.join(bundle_name));
+    }
+    for ancestor in path.into_iter().flat_map(|path| path.ancestors().skip(1)) {
+        match ancestor
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("app") => {
+                bundles.extend(bundles_in(ancestor, false));
+                if let Some(parent) = ancestor.parent() {
+                    bundles.extend(bundles_in(parent, false));
+                }
+            }
+            Some("xcarchive") => bundles.extend(bundles_in(&ancestor.join("dSYMs"), true)),
+            _ => {}
+        }
+    }
+    for dir in dsym_dirs {
+        bundles.extend(bundles_in(dir, true));
+    }
+    let mut seen = std::collections::HashSet::new();
+    bundles.retain(|bundle| seen.insert(bundle.clone()));
+    bundles
+}
+
+fn bundles_in(dir: &Path, recursive: bool) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut dirs = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_dir()))
+        .map(|entry| entry.path())
+        .collect::<Vec<_>>();
+    dirs.sort();
+
+    let mut bundles = Vec::new();
+    for dir in dirs {
+        if dir.extension().is_some_and(|extension| extension == "dSYM") {
+            bundles.push(dir);
+        } else if recursive {
+            bundles.extend(bundles_in(&dir, true));
+        }
+    }
+    bundles
+}
+
+pub(crate) fn matching_payload(bundle: &Path, wanted: &[[u8; 16]]) -> Option<PathBuf> {
+    bundle_payloads(bundle).into_iter().find(|payload| {
+        payload_uuids(payload)
+            .iter()
+            .any(|uuid| wanted.contains(uuid))
+    })
+}
+
+/// The payload of `bundle` that carries `uuid`, or its only payload when the
+/// UUID is not known.
+pub(crate) fn bundle_payload(bundle: &Path, uuid: Option<[u8; 16]>) -> Option<PathBuf> {
+    match uuid {
+        Some(uuid) => matching_payload(bundle, &[uuid]),
+        None => {
+            let mut payloads = bundle_payloads(bundle);
+            (payloads.len() == 1).then(|| payloads.remove(0))
+        }
+    }
+}
+
+/// Whether `path` lies inside a `.dSYM` bundle.
+pub(crate) fn in_bundle(path: &Path) -> bool {
+    path.ancestors().any(|ancestor| {
+        ancestor
+            .extension()
+            .is_some_and(|extension| extension == "dSYM")
+    })
+}
+
+fn bundle_payloads(bundle: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(bundle.join("Contents/Resources/DWARF")) else {
+        return Vec::new();
+    };
+    let mut payloads = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect::<Vec<_>>();
+    payloads.sort();
+    payloads
+}
+
+fn payload_uuids(path: &Path) -> Vec<[u8; 16]> {
+    let Ok(handle) = fs::File::open(path) else {
+        return Vec::new();
+    };
+    // mmap so only the headers of a large payload are read.
+    let Ok(mmap) = (unsafe { memmap2::Mmap::map(&handle) }) else {
+        return Vec::new();
+    };
+    let uuid = |data: &[u8]| object::File::parse(data).ok()?.mach_uuid().ok()?;
+    match object::FileKind::parse(&*mmap) {
+        Ok(object::FileKind::MachOFat32) => FatHeader::parse_arch32(&*mmap)
+            .map(|arches| {
+                arches
+                    .iter()
+                    .filter_map(|arch| uuid(arch.data(&*mmap).ok()?))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        Ok(object::FileKind::MachOFat64) => FatHeader::parse_arch64(&*mmap)
+            .map(|arches| {
+                arches
+                    .iter()
+                    .filter_map(|arch| uuid(arch.data(&*mmap).ok()?))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        _ => uuid(&mmap).into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn candidate_bundles_cover_app_and_xcarchive_layouts() {
+        let root = tempfile::tempdir().unwrap();
+        let archive = root.path().join("MyApp.xcarchive");
+        let app = archive.join("Products/Applications/MyApp.app");
+        let binary = app.join("MyApp");
+        for dir in [
+            app.join("MyApp.dSYM"),
+            archive.join("Products/Applications/MyApp.app.dSYM"),
+            archive.join("dSYMs/MyApp.app.dSYM"),
+            archive.join("dSYMs/Frameworks/Kit.framework.dSYM"),
+        ] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        let extra = root.path().join("symbols");
+        fs::create_dir_all(extra.join("nested/Other.dSYM")).unwrap();
+
+        assert_eq!(
+            candidate_bundles(Some(&binary), std::slice::from_ref(&extra)),
+            vec![
+                app.join("MyApp.dSYM"),
+                archive.join("Products/Applications/MyApp.app.dSYM"),
+                archive.join("dSYMs/Frameworks/Kit.framework.dSYM"),
+                archive.join("dSYMs/MyApp.app.dSYM"),
+                extra.join("nested/Other.dSYM"),
+            ]
+        );
+    }
+}
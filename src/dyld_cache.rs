@@ -0,0 +1,321 @@
+// Support for symbolicating images that only exist inside a dyld shared cache
+// (UIKit, Foundation, libsystem...). The cache is split across the main file,
+// numbered subcaches and, on device caches, a `.symbols` file that holds the
+// local symbols stripped from the images.
+use anyhow::{anyhow, Context as _, Result};
+use object::macho::{self, DyldCacheHeader};
+use object::read::macho::{DyldCache, Nlist};
+use object::{Endian, Endianness, Object, ObjectSegment, ReadRef, SymbolMapName};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Caches whose header reaches `symbols_subcache_uuid` (offset 0x190) store the
+// local symbol entries with 64-bit dylib offsets.
+const LOCAL_SYMBOLS_64_HEADER_SIZE: u32 = 0x190;
+
+pub(crate) struct CacheImage<'data> {
+    pub object: object::File<'data, &'data [u8]>,
+    pub install_name: &'data str,
+    /// Local symbols from the cache's symbols file, which the image's own
+    /// symbol table no longer carries.
+    pub local_symbols: Vec<SymbolMapName<'data>>,
+}
+
+/// Maps the subcaches that belong to the cache at `path`, in the order
+/// `DyldCache::parse` expects: `.1`/`.01`, `.2`/`.02`, ... then `.symbols`.
+pub(crate) fn open_subcaches(path: &Path, data: &[u8]) -> Result<Vec<memmap2::Mmap>> {
+    let header = DyldCacheHeader::<Endianness>::parse(data)?;
+    let (_, endian) = header.parse_magic()?;
+    let count = header.subcaches(endian, data)?.map_or(0, <[_]>::len);
+
+    let mut files = Vec::with_capacity(count + 1);
+    for index in 1..=count {
+        let candidates = subcache_candidates(path, index);
+        let found = candidates
+            .iter()
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| {
+                anyhow!(
+                    "dyld shared cache subcache {index} not found next to {} (looked for {})",
+                    path.display(),
+                    candidates
+                        .iter()
+                        .map(|candidate| candidate.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+        files.push(map_file(found)?);
+    }
+
+    if header.symbols_subcache_uuid(endian).is_some() {
+        let symbols = with_suffix(path, "symbols");
+        if !symbols.is_file() {
+            return Err(anyhow!(
+                "dyld shared cache symbols file not found: {}",
+                symbols.display()
+            ));
+        }
+        files.push(map_file(&symbols)?);
+    }
+
+    Ok(files)
+}
+
+/// Finds the image matching `install_name` (a full install name or its file
+/// name) or `uuid` and parses it out of the cache.
+pub(crate) fn select_image<'data>(
+    data: &'data [u8],
+    subcaches: &[&'data [u8]],
+    install_name: Option<&str>,
+    uuid: Option<[u8; 16]>,
+    verbose: bool,
+) -> Result<CacheImage<'data>> {
+    if install_name.is_none() && uuid.is_none() {
+        return Err(anyhow!(
+            "dyld shared cache contains many images; pass --image <install name> or --uuid to select one"
+        ));
+    }
+
+    let cache = DyldCache::<Endianness, &'data [u8]>::parse(data, subcaches)
+        .context("failed to parse dyld shared cache")?;
+
+    for image in cache.images() {
+        let Ok(path) = image.path() else {
+            continue;
+        };
+        if let Some(wanted) = install_name {
+            if !install_name_matches(path, wanted) {
+                continue;
+            }
+        }
+        let Ok(object) = image.parse_object() else {
+            continue;
+        };
+        if let Some(wanted) = uuid {
+            if object.mach_uuid().ok().flatten() != Some(wanted) {
+                continue;
+            }
+        }
+
+        if verbose {
+            eprintln!("cache_image: {path}");
+        }
+        let local_symbols = image_local_symbols(data, subcaches, &object)?;
+        return Ok(CacheImage {
+            object,
+            install_name: path,
+            local_symbols,
+        });
+    }
+
+    Err(anyhow!(
+        "no image in the dyld shared cache matched image={} uuid={}",
+        install_name.unwrap_or("-"),
+        uuid.map(crate::atosl::format_uuid)
+            .unwrap_or_else(|| "-".to_string())
+    ))
+}
+
+fn install_name_matches(path: &str, wanted: &str) -> bool {
+    path == wanted
+        || path
+            .rsplit_once('/')
+            .is_some_and(|(_, file_name)| file_name == wanted)
+}
+
+fn subcache_candidates(path: &Path, index: usize) -> [PathBuf; 2] {
+    [
+        with_suffix(path, &format!("{index:02}")),
+        with_suffix(path, &index.to_string()),
+    ]
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+fn map_file(path: &Path) -> Result<memmap2::Mmap> {
+    let file = fs::File::open(path)
+        .with_context(|| format!("failed to open dyld cache file: {}", path.display()))?;
+    unsafe { memmap2::Mmap::map(&file) }
+        .with_context(|| format!("failed to memory-map: {}", path.display()))
+}
+
+// Device caches move local symbols to the `.symbols` subcache (always passed
+// last); older caches keep them in the main file.
+fn image_local_symbols<'data>(
+    data: &'data [u8],
+    subcaches: &[&'data [u8]],
+    object: &object::File<'data, &'data [u8]>,
+) -> Result<Vec<SymbolMapName<'data>>> {
+    let header = DyldCacheHeader::<Endianness>::parse(data)?;
+    let (_, endian) = header.parse_magic()?;
+    let symbols_data = match (header.symbols_subcache_uuid(endian), subcaches.last()) {
+        (Some(_), Some(symbols)) => *symbols,
+        _ => data,
+    };
+
+    // Local symbol entries are keyed by the image's offset from the cache base.
+    let Some(base) = header.mappings(endian, data)?.first() else {
+        return Ok(Vec::new());
+    };
+    let Some(text) = object
+        .segments()
+        .find(|segment| segment.name().ok().flatten() == Some("__TEXT"))
+    else {
+        return Ok(Vec::new());
+    };
+    let dylib_offset = text.address().wrapping_sub(base.address.get(endian));
+
+    if object.is_64() {
+        local_symbols::<macho::Nlist64<Endianness>>(symbols_data, dylib_offset)
+    } else {
+        local_symbols::<macho::Nlist32<Endianness>>(symbols_data, dylib_offset)
+    }
+}
+
+// Reads `dyld_cache_local_symbols_info` and returns the defined, non-stab
+// symbols of the entry whose dylib offset is `dylib_offset`.
+fn local_symbols<'data, N: Nlist<Endian = Endianness>>(
+    data: &'data [u8],
+    dylib_offset: u64,
+) -> Result<Vec<SymbolMapName<'data>>> {
+    let header = DyldCacheHeader::<Endianness>::parse(data)?;
+    let (_, endian) = header.parse_magic()?;
+    let info = header.local_symbols_offset.get(endian);
+    if info == 0 || header.local_symbols_size.get(endian) == 0 {
+        return Ok(Vec::new());
+    }
+    let entries_64 = header.mapping_offset.get(endian) >= LOCAL_SYMBOLS_64_HEADER_SIZE;
+
+    let read_u32 = |offset: u64| -> Result<u32> {
+        let bytes = data
+            .read_bytes_at(offset, 4)
+            .map_err(|()| anyhow!("truncated dyld cache local symbols"))?;
+        Ok(endian.read_u32_bytes(bytes.try_into()?))
+    };
+    let read_u64 = |offset: u64| -> Result<u64> {
+        let bytes = data
+            .read_bytes_at(offset, 8)
+            .map_err(|()| anyhow!("truncated dyld cache local symbols"))?;
+        Ok(endian.read_u64_bytes(bytes.try_into()?))
+    };
+
+    let nlist_offset = info + u64::from(read_u32(info)?);
+    let nlist_count = read_u32(info + 4)? as usize;
+    let strings_offset = info + u64::from(read_u32(info + 8)?);
+    let strings_size = u64::from(read_u32(info + 12)?);
+    let entries_offset = info + u64::from(read_u32(info + 16)?);
+    let entries_count = u64::from(read_u32(info + 20)?);
+
+    let nlists = data
+        .read_slice_at::<N>(nlist_offset, nlist_count)
+        .map_err(|()| anyhow!("invalid dyld cache local symbol table"))?;
+    let entry_size = if entries_64 { 16 } else { 12 };
+
+    for index in 0..entries_count {
+        let entry = entries_offset + index * entry_size;
+        let (offset, rest) = if entries_64 {
+            (read_u64(entry)?, entry + 8)
+        } else {
+            (u64::from(read_u32(entry)?), entry + 4)
+        };
+        if offset != dylib_offset {
+            continue;
+        }
+        let start = read_u32(rest)? as usize;
+        let count = read_u32(rest + 4)? as usize;
+        let symbols = nlists
+            .get(start..start.saturating_add(count))
+            .ok_or_else(|| anyhow!("dyld cache local symbol entry out of range"))?;
+
+        return Ok(symbols
+            .iter()
+            .filter(|nlist| !nlist.is_stab() && nlist.n_type() & macho::N_TYPE == macho::N_SECT)
+            .filter_map(|nlist| {
+                let name = data
+                    .read_bytes_at_until(
+                        strings_offset + u64::from(nlist.n_strx(endian))
+                            ..strings_offset + strings_size,
+                        0,
+                    )
+                    .ok()?;
+                let name = std::str::from_utf8(name).ok()?;
+                Some(SymbolMapName::new(nlist.n_value(endian).into(), name))
+            })
+            .collect());
+    }
+
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put_u32(buffer: &mut [u8], offset: usize, value: u32) {
+        buffer[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn put_u64(buffer: &mut [u8], offset: usize, value: u64) {
+        buffer[offset..offset + 8].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn reads_local_symbols_for_one_dylib() {
+        let mut data = vec![0u8; 0x300];
+        data[..16].copy_from_slice(b"dyld_v1   arm64\0");
+        put_u32(&mut data, 0x10, 0x1c8); // mapping_offset: 64-bit entries
+        put_u64(&mut data, 0x48, 0x200); // local_symbols_offset
+        put_u64(&mut data, 0x50, 0x100); // local_symbols_size
+
+        // info: nlists at +0x20 (2), strings at +0x40, entries at +0x60 (1)
+        let info = 0x200;
+        put_u32(&mut data, info, 0x20);
+        put_u32(&mut data, info + 4, 2);
+        put_u32(&mut data, info + 8, 0x40);
+        put_u32(&mut data, info + 12, 0x20);
+        put_u32(&mut data, info + 16, 0x60);
+        put_u32(&mut data, info + 20, 1);
+        put_u64(&mut data, info + 0x60, 0x4000);
+        put_u32(&mut data, info + 0x60 + 8, 0);
+        put_u32(&mut data, info + 0x60 + 12, 2);
+
+        // A section symbol and a stab, which must be skipped.
+        let nlist = info + 0x20;
+        put_u32(&mut data, nlist, 1);
+        data[nlist + 4] = macho::N_SECT;
+        put_u64(&mut data, nlist + 8, 0x1_8000_4000);
+        put_u32(&mut data, nlist + 16, 1);
+        data[nlist + 16 + 4] = macho::N_FUN;
+        data[info + 0x40 + 1..info + 0x40 + 12].copy_from_slice(b"_local_fun\0");
+
+        let symbols = local_symbols::<macho::Nlist64<Endianness>>(&data, 0x4000).unwrap();
+        assert_eq!(symbols.len(), 1);
+        assert_eq!(symbols[0].address(), 0x1_8000_4000);
+        assert_eq!(symbols[0].name(), "_local_fun");
+
+        assert!(local_symbols::<macho::Nlist64<Endianness>>(&data, 0x8000)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn matches_install_name_or_file_name() {
+        let path = "/System/Library/PrivateFrameworks/UIKitCore.framework/UIKitCore";
+        assert!(install_name_matches(path, path));
+        assert!(install_name_matches(path, "UIKitCore"));
+        assert!(!install_name_matches(path, "UIKit"));
+    }
+
+    #[test]
+    fn subcache_candidates_cover_both_suffix_styles() {
+        let [padded, plain] = subcache_candidates(Path::new("dyld_shared_cache_arm64e"), 1);
+        assert_eq!(padded, PathBuf::from("dyld_shared_cache_arm64e.01"));
+        assert_eq!(plain, PathBuf::from("dyld_shared_cache_arm64e.1"));
+    }
+}
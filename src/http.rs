@@ -0,0 +1,43 @@
+// The accept loop shared by the HTTP servers (`atosl serve-debuginfod`, `atosl
+// serve`): a fixed number of worker threads, each answering one request at a
+// time.
+use anyhow::{anyhow, Context as _, Result};
+use std::io;
+use std::net::TcpListener;
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+
+/// Answers HTTP requests on `listener` with `handle` on `workers` threads,
+/// until accepting a request fails.
+///
+/// A request whose handler panics is dropped, which tiny_http answers with a
+/// 500; the worker goes on with the next request.
+pub(crate) fn serve_with_workers(
+    listener: TcpListener,
+    workers: usize,
+    handle: impl Fn(tiny_http::Request) -> io::Result<()> + Sync,
+) -> Result<()> {
+    let server = tiny_http::Server::from_listener(listener, None)
+        .map_err(|err| anyhow!("failed to start the server: {err}"))?;
+    let (server, handle) = (&server, &handle);
+    thread::scope(|scope| {
+        let workers = (0..workers.max(1))
+            .map(|_| {
+                scope.spawn(move || loop {
+                    let request = server.recv().context("failed to accept a request")?;
+                    // A client that hung up is not the server's problem.
+                    let _ = panic::catch_unwind(AssertUnwindSafe(|| handle(request)));
+                })
+            })
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|_| Err(anyhow!("server thread panicked")))
+            })
+            .collect::<Result<Vec<()>>>()
+    })?;
+    Ok(())
+}
@@ -0,0 +1,289 @@
+//! An on-disk index of the module ids under a directory.
+//!
+//! Finding a UUID or build-id in a directory of binaries and dSYMs means
+//! reading every file in it, which is slow for a large symbol archive.
+//! [`build_index`] records each file's ids, architectures, size and mtime in
+//! [`INDEX_FILE_NAME`] at the root; lookups under an indexed root (`-o <dir>
+//! --uuid`, `--dsym-dir`) read the index instead, re-reading only files whose
+//! size or mtime changed.
+use crate::locate::{self, ScannedModule};
+use anyhow::{Context as _, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Name of the index file written at the root of an indexed directory.
+pub const INDEX_FILE_NAME: &str = ".atosl-index.json";
+
+const INDEX_VERSION: u32 = 2;
+
+/// What [`build_index`] or [`update_index`] did.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct IndexSummary {
+    /// The index file written.
+    pub index_path: PathBuf,
+    /// Files now in the index.
+    pub files: usize,
+    /// Module ids now in the index.
+    pub modules: usize,
+    /// Files read because they were new or changed.
+    pub read: usize,
+    /// Entries dropped because their file is gone.
+    pub removed: usize,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct SymbolIndex {
+    version: u32,
+    files: Vec<IndexedFile>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct IndexedFile {
+    // Relative to the indexed root.
+    path: PathBuf,
+    size: u64,
+    mtime: (u64, u32),
+    modules: Vec<IndexedModule>,
+}
+
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+struct IndexedModule {
+    id: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    arch: Option<String>,
+    dwarf: bool,
+}
+
+/// Indexes every file under `root` from scratch and writes the index.
+pub fn build_index(root: &Path) -> Result<IndexSummary> {
+    let mut index = SymbolIndex::default();
+    let summary = refresh(root, &mut index)?;
+    save(root, &index)?;
+    Ok(summary)
+}
+
+/// Brings the index of `root` up to date, reading only new and changed files.
+/// Without an index this is [`build_index`].
+pub fn update_index(root: &Path) -> Result<IndexSummary> {
+    let mut index = load(root)?.unwrap_or_default();
+    let summary = refresh(root, &mut index)?;
+    save(root, &index)?;
+    Ok(summary)
+}
+
+/// The files under an indexed `root` that carry `id` (lowercase hex), those
+/// with DWARF first, or `None` when `root` has no index.
+///
+/// Entries whose file changed are re-read first. A miss is answered from the
+/// index as it stands: walking a large archive on every unknown id would cost
+/// what the index saves, so files added since are only found once
+/// [`update_index`] (`atosl index update`) has run.
+pub(crate) fn lookup(root: &Path, id: &str) -> Result<Option<Vec<PathBuf>>> {
+    let Some(mut index) = load(root)? else {
+        return Ok(None);
+    };
+
+    let mut changed = false;
+    for entry in index.files.iter_mut().filter(|entry| entry.carries(id)) {
+        if !entry.is_fresh(root) {
+            if let Some(reread) = read_entry(root, &entry.path) {
+                *entry = reread;
+            }
+            changed = true;
+        }
+    }
+    if changed {
+        // A read-only archive still answers lookups; it just stays stale.
+        let _ = save(root, &index);
+    }
+    Ok(Some(index.matches(root, id)))
+}
+
+/// A file and the modules in it.
+pub(crate) type FileModules = (PathBuf, Vec<ScannedModule>);
+
+/// Every file in the index of `root`, brought up to date, with its modules, or
+/// `None` when `root` has no index.
+pub(crate) fn indexed_modules(root: &Path) -> Result<Option<Vec<FileModules>>> {
+    let Some(mut index) = load(root)? else {
+        return Ok(None);
+    };
+    let summary = refresh(root, &mut index)?;
+    if summary.read > 0 || summary.removed > 0 {
+        let _ = save(root, &index);
+    }
+    Ok(Some(
+        index
+            .files
+            .into_iter()
+            .map(|entry| {
+                let modules = entry
+                    .modules
+                    .into_iter()
+                    .map(|module| ScannedModule {
+                        id: module.id,
+                        arch: module.arch,
+                        dwarf: module.dwarf,
+                    })
+                    .collect();
+                (root.join(entry.path), modules)
+            })
+            .collect(),
+    ))
+}
+
+impl SymbolIndex {
+    fn matches(&self, root: &Path, id: &str) -> Vec<PathBuf> {
+        let mut found = self
+            .files
+            .iter()
+            .filter(|entry| entry.is_fresh(root))
+            .filter_map(|entry| {
+                let module = entry.modules.iter().find(|module| module.id == id)?;
+                Some((root.join(&entry.path), module.dwarf))
+            })
+            .collect::<Vec<_>>();
+        found.sort_by_key(|(_, dwarf)| !dwarf);
+        found.into_iter().map(|(path, _)| path).collect()
+    }
+}
+
+impl IndexedFile {
+    fn carries(&self, id: &str) -> bool {
+        self.modules.iter().any(|module| module.id == id)
+    }
+
+    fn is_fresh(&self, root: &Path) -> bool {
+        fs::metadata(root.join(&self.path))
+            .ok()
+            .and_then(|metadata| stamp(&metadata))
+            == Some((self.size, self.mtime))
+    }
+}
+
+// Re-reads the files under `root` that are not in `index` or whose size or
+// mtime changed, and drops entries for files that are gone.
+fn refresh(root: &Path, index: &mut SymbolIndex) -> Result<IndexSummary> {
+    let mut files = Vec::new();
+    locate::collect_candidate_files(root, &mut files)?;
+    files.sort();
+
+    let mut previous = index
+        .files
+        .drain(..)
+        .map(|entry| (entry.path.clone(), entry))
+        .collect::<HashMap<_, _>>();
+    let mut summary = IndexSummary {
+        index_path: root.join(INDEX_FILE_NAME),
+        ..IndexSummary::default()
+    };
+    let mut stale = Vec::new();
+    for file in &files {
+        let Ok(relative) = file.strip_prefix(root) else {
+            continue;
+        };
+        if relative
+            .to_str()
+            .is_some_and(|name| name.starts_with(INDEX_FILE_NAME))
+        {
+            continue;
+        }
+        match previous.remove(relative) {
+            Some(entry) if entry.is_fresh(root) => index.files.push(entry),
+            _ => stale.push(relative),
+        }
+    }
+    let reread = locate::parallel_map(&stale, |relative| read_entry(root, relative));
+    summary.read = reread.iter().flatten().count();
+    index.files.extend(reread.into_iter().flatten());
+    index.files.sort_by(|a, b| a.path.cmp(&b.path));
+    index.version = INDEX_VERSION;
+    summary.removed = previous.len();
+    summary.files = index.files.len();
+    summary.modules = index.files.iter().map(|entry| entry.modules.len()).sum();
+    Ok(summary)
+}
+
+fn read_entry(root: &Path, relative: &Path) -> Option<IndexedFile> {
+    let path = root.join(relative);
+    let (size, mtime) = stamp(&fs::metadata(&path).ok()?)?;
+    let modules = locate::scan_file(&path)
+        .into_iter()
+        .map(|module| IndexedModule {
+            id: module.id,
+            arch: module.arch,
+            dwarf: module.dwarf,
+        })
+        .collect();
+    Some(IndexedFile {
+        path: relative.to_path_buf(),
+        size,
+        mtime,
+        modules,
+    })
+}
+
+fn stamp(metadata: &fs::Metadata) -> Option<(u64, (u64, u32))> {
+    let mtime = metadata.modified().ok()?.duration_since(UNIX_EPOCH).ok()?;
+    Some((metadata.len(), (mtime.as_secs(), mtime.subsec_nanos())))
+}
+
+// An index written by another version is treated as empty and rebuilt.
+fn load(root: &Path) -> Result<Option<SymbolIndex>> {
+    let path = root.join(INDEX_FILE_NAME);
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read index {}", path.display()))
+        }
+    };
+    let index = serde_json::from_str::<SymbolIndex>(&text)
+        .ok()
+        .filter(|index| index.version == INDEX_VERSION)
+        .unwrap_or_default();
+    Ok(Some(index))
+}
+
+fn save(root: &Path, index: &SymbolIndex) -> Result<()> {
+    let path = root.join(INDEX_FILE_NAME);
+    // Write beside the index and rename, so a concurrent lookup never sees a
+    // partial file.
+    let partial = root.join(format!("{INDEX_FILE_NAME}.partial"));
+    fs::write(&partial, serde_json::to_vec(index)?)
+        .with_context(|| format!("failed to write index {}", partial.display()))?;
+    fs::rename(&partial, &path).with_context(|| format!("failed to write index {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn update_rereads_only_new_and_changed_files() {
+        let root = tempfile::tempdir().unwrap();
+        fs::create_dir_all(root.path().join("nested")).unwrap();
+        fs::write(root.path().join("a"), "a").unwrap();
+        fs::write(root.path().join("nested/b"), "b").unwrap();
+
+        let built = build_index(root.path()).unwrap();
+        assert_eq!((built.files, built.read, built.removed), (2, 2, 0));
+
+        fs::write(root.path().join("nested/b"), "changed").unwrap();
+        fs::write(root.path().join("c"), "c").unwrap();
+        fs::remove_file(root.path().join("a")).unwrap();
+        let updated = update_index(root.path()).unwrap();
+        assert_eq!((updated.files, updated.read, updated.removed), (2, 2, 1));
+
+        let unchanged = update_index(root.path()).unwrap();
+        assert_eq!(
+            (unchanged.files, unchanged.read, unchanged.removed),
+            (2, 0, 0)
+        );
+        assert_eq!(lookup(root.path(), "00").unwrap(), Some(Vec::new()));
+        assert_eq!(lookup(&root.path().join("nested"), "00").unwrap(), None);
+    }
+}
@@ -0,0 +1,416 @@
+// Decoding of Linux kernel oopses, KASAN reports and other splats, in the
+// spirit of the kernel's `decode_stacktrace.sh`. Frames are printed as
+// `func+0x1a/0x90 [module]`, so each one is resolved by symbol name against
+// vmlinux or the module's `.ko`. Bare `[<address>]` frames are resolved in
+// vmlinux once the KASLR offset is known.
+use crate::atosl::{self, SymbolizeOptions, SymbolizeOutcome, SymbolizedFrame};
+use anyhow::{anyhow, Context as _, Result};
+use std::collections::{BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// A `symbol+0xoffset/0xsize` frame and the module that follows it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct SymbolFrame {
+    /// Byte range of `symbol+0xoffset/0xsize` in the line.
+    start: usize,
+    end: usize,
+    symbol: String,
+    offset: u64,
+    size: Option<u64>,
+    module: Option<String>,
+}
+
+#[derive(Debug, Default, Eq, PartialEq)]
+struct OopsLine {
+    frames: Vec<SymbolFrame>,
+    /// `[<ffffffff8108c2a3>]` addresses, as printed by older kernels.
+    raw_addresses: Vec<(usize, usize, u64)>,
+}
+
+/// A decodable span of an oops line: the n-th symbol frame or raw address.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Span {
+    Frame(usize, usize),
+    Raw(usize, usize),
+}
+
+/// One resolved frame: the innermost function first, then the functions it
+/// was inlined into, each with its source location.
+type Resolution = Vec<(String, String)>;
+
+pub(crate) fn run(options: &SymbolizeOptions) -> Result<i32> {
+    let text = read_report(options.input.as_deref())?;
+    let lines = text.lines().map(parse_line).collect::<Vec<_>>();
+
+    let mut resolved: HashMap<Span, Resolution> = HashMap::new();
+    resolve_vmlinux(options, &lines, &mut resolved)?;
+
+    let modules = lines
+        .iter()
+        .flat_map(|line| &line.frames)
+        .filter_map(|frame| frame.module.clone())
+        .collect::<BTreeSet<_>>();
+    let module_files = find_module_files(&options.module_dirs);
+    for module in modules {
+        let Some(path) = module_files.get(&normalize_module_name(&module)) else {
+            if options.verbose {
+                eprintln!("kernel: no .ko found for module {module}");
+            }
+            continue;
+        };
+        let module_options = SymbolizeOptions {
+            object_path: path.clone(),
+            debug_dirs: options.debug_dirs.clone(),
+            locators: options.locators.clone(),
+            offline: options.offline,
+            verbose: options.verbose,
+            ..Default::default()
+        };
+        atosl::with_symbolizer(&module_options, |symbolizer, _, _| {
+            for (line_index, line) in lines.iter().enumerate() {
+                for (frame_index, frame) in line.frames.iter().enumerate() {
+                    if frame.module.as_deref() != Some(module.as_str()) {
+                        continue;
+                    }
+                    if let Some(resolution) = resolve_frame(symbolizer, frame) {
+                        resolved.insert(Span::Frame(line_index, frame_index), resolution);
+                    }
+                }
+            }
+        })?;
+    }
+
+    for (line_index, (text, line)) in text.lines().zip(&lines).enumerate() {
+        print!("{}", decode_line(text, line, line_index, &resolved));
+    }
+    Ok(0)
+}
+
+// A dmesg capture can carry stray bytes from a corrupted console; they must not
+// cost the rest of the report.
+fn read_report(input: Option<&Path>) -> Result<String> {
+    let bytes = match input {
+        Some(path) => fs::read(path)
+            .with_context(|| format!("failed to read kernel report: {}", path.display()))?,
+        None => {
+            let mut bytes = Vec::new();
+            io::stdin()
+                .read_to_end(&mut bytes)
+                .context("failed to read kernel report from stdin")?;
+            bytes
+        }
+    };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+// Frames without a module belong to vmlinux. Raw addresses are also resolved
+// here, shifted by the KASLR offset: either derived from `--kernel-symbol`, or
+// from a line that prints an address next to the symbol it resolves to.
+fn resolve_vmlinux(
+    options: &SymbolizeOptions,
+    lines: &[OopsLine],
+    resolved: &mut HashMap<Span, Resolution>,
+) -> Result<()> {
+    let vmlinux_options = SymbolizeOptions {
+        object_path: options.object_path.clone(),
+        debug_dirs: options.debug_dirs.clone(),
+        locators: options.locators.clone(),
+        offline: options.offline,
+        verbose: options.verbose,
+        ..Default::default()
+    };
+    atosl::with_symbolizer(&vmlinux_options, |symbolizer, _, _| -> Result<()> {
+        for (line_index, line) in lines.iter().enumerate() {
+            for (frame_index, frame) in line.frames.iter().enumerate() {
+                if frame.module.is_some() {
+                    continue;
+                }
+                if let Some(resolution) = resolve_frame(symbolizer, frame) {
+                    resolved.insert(Span::Frame(line_index, frame_index), resolution);
+                }
+            }
+        }
+
+        let kaslr_offset = match &options.kernel_symbol {
+            Some((name, runtime)) => {
+                let address = symbolizer.function_address(name, None).ok_or_else(|| {
+                    anyhow!(
+                        "--kernel-symbol {name} is not in {}",
+                        options.object_path.display()
+                    )
+                })?;
+                Some(runtime.wrapping_sub(address))
+            }
+            None => lines
+                .iter()
+                .find_map(|line| derive_kaslr_offset(symbolizer, line)),
+        };
+        if options.verbose {
+            match kaslr_offset {
+                Some(offset) => eprintln!("kernel: KASLR offset {offset:#x}"),
+                None => eprintln!("kernel: KASLR offset unknown; raw addresses are skipped"),
+            }
+        }
+
+        let Some(kaslr_offset) = kaslr_offset else {
+            return Ok(());
+        };
+        for (line_index, line) in lines.iter().enumerate() {
+            // A line that already names its symbol is decoded from the name.
+            if !line.frames.is_empty() {
+                continue;
+            }
+            for (raw_index, (_, _, address)) in line.raw_addresses.iter().enumerate() {
+                let outcome =
+                    symbolizer.symbolize_lookup(*address, address.wrapping_sub(kaslr_offset));
+                if let Some(resolution) = resolution_from_outcome(&outcome) {
+                    resolved.insert(Span::Raw(line_index, raw_index), resolution);
+                }
+            }
+        }
+        Ok(())
+    })?
+}
+
+fn derive_kaslr_offset(symbolizer: &atosl::ObjectSymbolizer<'_>, line: &OopsLine) -> Option<u64> {
+    let ([(_, _, runtime)], [frame]) = (&line.raw_addresses[..], &line.frames[..]) else {
+        return None;
+    };
+    if frame.module.is_some() {
+        return None;
+    }
+    let address = symbolizer.function_address(&frame.symbol, frame.size)?;
+    Some(runtime.wrapping_sub(address.wrapping_add(frame.offset)))
+}
+
+fn resolve_frame(
+    symbolizer: &atosl::ObjectSymbolizer<'_>,
+    frame: &SymbolFrame,
+) -> Option<Resolution> {
+    let address = symbolizer.function_address(&frame.symbol, frame.size)? + frame.offset;
+    resolution_from_outcome(&symbolizer.symbolize_lookup(address, address))
+}
+
+fn resolution_from_outcome(outcome: &SymbolizeOutcome) -> Option<Resolution> {
+    let SymbolizeOutcome::Resolved(SymbolizedFrame {
+        symbol,
+        location: Some(location),
+        inlined_by,
+        ..
+    }) = outcome
+    else {
+        return None;
+    };
+    let mut resolution = vec![(
+        symbol.clone(),
+        format!("{}:{}", location.file, location.line),
+    )];
+    for frame in inlined_by {
+        let location = frame
+            .location
+            .as_ref()
+            .map(|location| format!("{}:{}", location.file, location.line))
+            .unwrap_or_else(|| "?".to_string());
+        resolution.push((frame.symbol.clone(), location));
+    }
+    Some(resolution)
+}
+
+// Like `decode_stacktrace.sh`: the first decodable frame on the line is
+// replaced by `function (file:line)`, and every function it was inlined into
+// gets a line of its own with the same prefix and module.
+fn decode_line(
+    text: &str,
+    line: &OopsLine,
+    line_index: usize,
+    resolved: &HashMap<Span, Resolution>,
+) -> String {
+    let frames = line.frames.iter().enumerate().map(|(index, frame)| {
+        (
+            frame.start,
+            frame.end,
+            frame.module.as_deref(),
+            Span::Frame(line_index, index),
+        )
+    });
+    let raw = line
+        .raw_addresses
+        .iter()
+        .enumerate()
+        .map(|(index, (start, end, _))| (*start, *end, None, Span::Raw(line_index, index)));
+    let Some((start, end, module, resolution)) = frames
+        .chain(raw)
+        .find_map(|(start, end, module, span)| Some((start, end, module, resolved.get(&span)?)))
+    else {
+        return format!("{text}\n");
+    };
+
+    let (prefix, suffix) = (&text[..start], &text[end..]);
+    let module = module
+        .map(|module| format!(" [{module}]"))
+        .unwrap_or_default();
+    let mut output = String::new();
+    for (index, (function, location)) in resolution.iter().enumerate() {
+        if index == 0 {
+            output.push_str(&format!("{prefix}{function} ({location}){suffix}\n"));
+        } else {
+            output.push_str(&format!("{prefix}{function} ({location}){module}\n"));
+        }
+    }
+    output
+}
+
+fn parse_line(text: &str) -> OopsLine {
+    let mut line = OopsLine::default();
+    let bytes = text.as_bytes();
+
+    let mut search = 0;
+    while let Some(found) = text[search..].find("[<") {
+        let start = search + found;
+        let Some(close) = text[start..].find(">]") else {
+            break;
+        };
+        let end = start + close + 2;
+        if let Ok(address) = u64::from_str_radix(&text[start + 2..end - 2], 16) {
+            line.raw_addresses.push((start, end, address));
+        }
+        search = end;
+    }
+
+    let mut search = 0;
+    while let Some(found) = text[search..].find("+0x") {
+        let plus = search + found;
+        search = plus + 3;
+        let start = text[..plus]
+            .char_indices()
+            .rev()
+            .find(|&(_, c)| !is_symbol_char(c))
+            .map_or(0, |(index, c)| index + c.len_utf8());
+        if start == plus {
+            continue;
+        }
+        let offset_end = hex_end(bytes, plus + 3);
+        let Ok(offset) = u64::from_str_radix(&text[plus + 3..offset_end], 16) else {
+            continue;
+        };
+        let (size, end) = match text[offset_end..].strip_prefix("/0x") {
+            Some(rest) => {
+                let size_end = offset_end + 3 + hex_end(rest.as_bytes(), 0);
+                match u64::from_str_radix(&text[offset_end + 3..size_end], 16) {
+                    Ok(size) => (Some(size), size_end),
+                    Err(_) => (None, offset_end),
+                }
+            }
+            None => (None, offset_end),
+        };
+        let module = text[end..]
+            .strip_prefix(" [")
+            .and_then(|rest| rest.split_once(']'))
+            .map(|(module, _)| module)
+            .filter(|module| {
+                !module.is_empty() && module.chars().all(|c| is_symbol_char(c) || c == '-')
+            })
+            .map(str::to_string);
+        line.frames.push(SymbolFrame {
+            start,
+            end,
+            symbol: text[start..plus].to_string(),
+            offset,
+            size,
+            module,
+        });
+        search = end;
+    }
+    line
+}
+
+fn is_symbol_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.' || c == '$'
+}
+
+fn hex_end(bytes: &[u8], start: usize) -> usize {
+    start
+        + bytes[start..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_hexdigit())
+            .count()
+}
+
+// The kernel reports module names with underscores even when the file name
+// uses dashes.
+fn normalize_module_name(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+fn find_module_files(dirs: &[PathBuf]) -> HashMap<String, PathBuf> {
+    let mut files = HashMap::new();
+    let mut pending = dirs.to_vec();
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if let Some(stem) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_suffix(".ko"))
+            {
+                files.entry(normalize_module_name(stem)).or_insert(path);
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_symbol_frames_with_modules() {
+        let text = "[   12.345692]  ? mod_fault.cold+0x4/0x38 [my_mod]";
+        let line = parse_line(text);
+        assert!(line.raw_addresses.is_empty());
+        assert_eq!(line.frames.len(), 1);
+        let frame = &line.frames[0];
+        assert_eq!(frame.symbol, "mod_fault.cold");
+        assert_eq!((frame.offset, frame.size), (0x4, Some(0x38)));
+        assert_eq!(frame.module.as_deref(), Some("my_mod"));
+        assert_eq!(&text[frame.start..frame.end], "mod_fault.cold+0x4/0x38");
+
+        let line = parse_line("RIP: 0010:kernel_fn+0x1a");
+        assert_eq!(line.frames[0].symbol, "kernel_fn");
+        assert_eq!((line.frames[0].offset, line.frames[0].size), (0x1a, None));
+        assert_eq!(line.frames[0].module, None);
+    }
+
+    #[test]
+    fn parses_raw_addresses() {
+        let line = parse_line(" [<ffffffff81001144>] kernel_fn+0x4/0x5");
+        assert_eq!(line.raw_addresses, vec![(1, 21, 0xffffffff81001144)]);
+        assert_eq!(line.frames[0].symbol, "kernel_fn");
+
+        let line = parse_line("Read of size 8 at addr ffff888003c0e7f8 by task sh/1");
+        assert_eq!(line, OopsLine::default());
+    }
+
+    #[test]
+    fn parses_frames_next_to_non_ascii_text() {
+        let text = " ééfoo+0x10/0x20 «ü»";
+        let line = parse_line(text);
+        assert_eq!(line.frames.len(), 1);
+        assert_eq!(line.frames[0].symbol, "foo");
+        assert_eq!(
+            &text[line.frames[0].start..line.frames[0].end],
+            "foo+0x10/0x20"
+        );
+
+        let line = parse_line("é+0x10");
+        assert!(line.frames.is_empty());
+    }
+}
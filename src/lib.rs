@@ -23,10 +23,28 @@
 
 #![deny(unsafe_op_in_unsafe_fn)]
 
+mod arch;
 pub mod atosl;
+mod debug_map;
+pub mod debuginfod;
 pub mod demangle;
+mod dsym;
+mod dyld_cache;
+mod http;
+pub mod index;
+mod kernel;
+pub mod locate;
+mod object_cache;
+mod relocate;
+pub mod service;
+mod stdio;
+mod stubs;
+pub mod symbolizer;
+mod symbols;
 
 pub use atosl::{
     InlineFrame, OutputFormat, ResolverKind, SelectedSlice, SourceLocation, SymbolizeOptions,
     SymbolizeOutcome, SymbolizeReport, SymbolizedFrame,
 };
+pub use locate::{ModuleIdentity, SymbolLocator};
+pub use symbolizer::{ObjectInfo, OpenOptions, Symbolizer};
@@ -0,0 +1,1204 @@
+//! Finding the file that carries a module's symbols.
+//!
+//! When `-o` names a binary without DWARF (or a directory to search), atosl
+//! describes the module it wants as a [`ModuleIdentity`] and asks a chain of
+//! [`SymbolLocator`]s for candidate files: the built-in strategies below first,
+//! then any configured through [`SymbolizeOptions::locators`]. The first
+//! candidate that exists is used.
+//!
+//! [`SymbolizeOptions::locators`]: crate::SymbolizeOptions::locators
+use crate::arch;
+use crate::debuginfod::{Artifact, DebuginfodClient};
+use crate::dsym;
+use crate::index;
+use anyhow::{anyhow, bail, Context as _, Result};
+use object::macho;
+use object::read::macho::{FatArch, FatHeader};
+use object::{Object, ObjectSection};
+use serde_json::Value;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io::Read as _;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Arc;
+use std::thread;
+
+/// What is known about a module whose symbols are wanted.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ModuleIdentity {
+    /// Mach-O `LC_UUID` of the slice.
+    pub uuid: Option<[u8; 16]>,
+    /// ELF build-id.
+    pub build_id: Option<Vec<u8>>,
+    /// Canonical architecture name (`arm64`, `x86_64`, …).
+    pub arch: Option<String>,
+    /// File name of the binary.
+    pub name: Option<String>,
+    /// Path of the binary, when the lookup started from one.
+    pub path: Option<PathBuf>,
+    /// ELF `.gnu_debuglink` file name and CRC.
+    pub debuglink: Option<(String, u32)>,
+}
+
+impl ModuleIdentity {
+    /// An identity known only by a UUID or build-id written as hex (hyphens
+    /// allowed). 16 bytes are taken as a Mach-O UUID, anything else as a
+    /// build-id.
+    pub fn from_id(value: &str) -> Result<Self> {
+        let hex = normalize_hex_id(value);
+        let well_formed = value.chars().all(|c| c == '-' || c.is_ascii_hexdigit());
+        if !well_formed || hex.is_empty() || hex.len() % 2 != 0 {
+            bail!("invalid --uuid value: '{value}'");
+        }
+        let bytes = (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| anyhow!("invalid --uuid value: '{value}'"))?;
+        Ok(match <[u8; 16]>::try_from(bytes.as_slice()) {
+            Ok(uuid) => Self {
+                uuid: Some(uuid),
+                ..Self::default()
+            },
+            Err(_) => Self {
+                build_id: Some(bytes),
+                ..Self::default()
+            },
+        })
+    }
+
+    /// The UUID or build-id as lowercase hex without separators.
+    pub fn hex_id(&self) -> Option<String> {
+        match (&self.uuid, &self.build_id) {
+            (Some(uuid), _) => Some(format_hex(uuid)),
+            (None, Some(build_id)) => Some(format_hex(build_id)),
+            (None, None) => None,
+        }
+    }
+
+    /// The UUID as `dsymForUUID` takes it (`01234567-89AB-…`), or the build-id
+    /// as lowercase hex.
+    pub fn display_id(&self) -> Option<String> {
+        match (&self.uuid, &self.build_id) {
+            (Some(uuid), _) => Some(crate::atosl::format_uuid(*uuid)),
+            (None, Some(build_id)) => Some(format_hex(build_id)),
+            (None, None) => None,
+        }
+    }
+}
+
+/// A strategy for finding the files that may carry a module's symbols.
+///
+/// Candidates are returned best first. A locator only returns files it has
+/// reason to believe match the module; a `.dSYM` bundle directory may be
+/// returned in place of its payload.
+pub trait SymbolLocator: fmt::Debug + Send + Sync {
+    fn locate(&self, module: &ModuleIdentity) -> Result<Vec<PathBuf>>;
+}
+
+/// The `.dSYM` bundle of a Mach-O slice, matched by UUID: `<Binary>.dSYM` next
+/// to the binary, the bundles around an enclosing `.app` or `.xcarchive`, then
+/// every bundle under `dsym_dirs`.
+#[derive(Clone, Debug, Default)]
+pub struct DsymLocator {
+    pub dsym_dirs: Vec<PathBuf>,
+}
+
+impl SymbolLocator for DsymLocator {
+    fn locate(&self, module: &ModuleIdentity) -> Result<Vec<PathBuf>> {
+        let Some(uuid) = module.uuid else {
+            return Ok(Vec::new());
+        };
+        let found = dsym::candidate_bundles(module.path.as_deref(), &[])
+            .iter()
+            .find_map(|bundle| dsym::matching_payload(bundle, &[uuid]));
+        if found.is_some() {
+            return Ok(found.into_iter().collect());
+        }
+        let hex = format_hex(&uuid);
+        for dir in &self.dsym_dirs {
+            // An indexed root is answered from its index, without a walk.
+            if let Some(paths) = index::lookup(dir, &hex)? {
+                if let Some(payload) = paths.into_iter().find(|path| dsym::in_bundle(path)) {
+                    return Ok(vec![payload]);
+                }
+                continue;
+            }
+            let found = dsym::candidate_bundles(None, std::slice::from_ref(dir))
+                .iter()
+                .find_map(|bundle| dsym::matching_payload(bundle, &[uuid]));
+            if found.is_some() {
+                return Ok(found.into_iter().collect());
+            }
+        }
+        Ok(Vec::new())
+    }
+}
+
+/// The ELF `.gnu_debuglink` target next to the binary, in its `.debug`
+/// directory, or mirrored under `/usr/lib/debug` and `debug_dirs`. The CRC
+/// must match, as in gdb.
+#[derive(Clone, Debug, Default)]
+pub struct DebuglinkLocator {
+    pub debug_dirs: Vec<PathBuf>,
+}
+
+impl SymbolLocator for DebuglinkLocator {
+    fn locate(&self, module: &ModuleIdentity) -> Result<Vec<PathBuf>> {
+        let (Some((name, crc)), Some(path)) = (&module.debuglink, &module.path) else {
+            return Ok(Vec::new());
+        };
+        let dir = path.parent().unwrap_or_else(|| Path::new("."));
+        // The CRC guards against using a debug file that no longer matches the
+        // binary.
+        Ok(debuglink_candidates(dir, name, &self.debug_dirs)
+            .into_iter()
+            .filter(|candidate| candidate.is_file() && file_crc32(candidate) == Some(*crc))
+            .take(1)
+            .collect())
+    }
+}
+
+/// `.build-id/xx/yyyy.debug` under `/usr/lib/debug` and `debug_dirs`. The path
+/// itself identifies the matching debug file.
+#[derive(Clone, Debug, Default)]
+pub struct BuildIdLocator {
+    pub debug_dirs: Vec<PathBuf>,
+}
+
+impl SymbolLocator for BuildIdLocator {
+    fn locate(&self, module: &ModuleIdentity) -> Result<Vec<PathBuf>> {
+        let Some(build_id) = &module.build_id else {
+            return Ok(Vec::new());
+        };
+        let subpath = build_id_debug_subpath(build_id);
+        let roots = std::iter::once(PathBuf::from("/usr/lib/debug")).chain(self.debug_dirs.clone());
+        Ok(roots
+            .map(|root| root.join(".build-id").join(&subpath))
+            .collect())
+    }
+}
+
+/// Debug files on a symbol-server share under `debug_dirs`, for ELF build-ids
+/// and Mach-O UUIDs: the Microsoft symstore layout (`name/ID/name`, or
+/// `na/name/ID/name` when the root has an `index2.txt`) keyed as SSQP does
+/// (`_.debug/elf-buildid-sym-<id>/_.debug`, `app/mach-uuid-<uuid>/app`), and the
+/// unified layout (`ab/cdef…/debuginfo`, then `executable`). A share may hold
+/// stripped executables too, so only files with DWARF for the module are used.
+#[derive(Clone, Debug, Default)]
+pub struct SymbolStoreLocator {
+    pub debug_dirs: Vec<PathBuf>,
+}
+
+impl SymbolLocator for SymbolStoreLocator {
+    fn locate(&self, module: &ModuleIdentity) -> Result<Vec<PathBuf>> {
+        let Some(hex) = module.hex_id() else {
+            return Ok(Vec::new());
+        };
+        Ok(self
+            .debug_dirs
+            .iter()
+            .flat_map(|root| symbol_store_candidates(root, module, &hex))
+            .filter(|candidate| {
+                scan_file(candidate)
+                    .iter()
+                    .any(|scanned| scanned.id == hex && scanned.dwarf)
+            })
+            .collect())
+    }
+}
+
+fn symbol_store_candidates(root: &Path, module: &ModuleIdentity, hex: &str) -> Vec<PathBuf> {
+    let (debug_file, debug_key, key) = if module.uuid.is_some() {
+        ("_.dwarf", "mach-uuid-sym", "mach-uuid")
+    } else {
+        ("_.debug", "elf-buildid-sym", "elf-buildid")
+    };
+    let mut keys = vec![(debug_file.to_string(), format!("{debug_key}-{hex}"))];
+    if let Some(name) = &module.name {
+        keys.push((name.clone(), format!("{key}-{hex}")));
+        keys.push((name.clone(), hex.to_uppercase()));
+    }
+
+    let two_tier = root.join("index2.txt").is_file();
+    let mut candidates = keys
+        .into_iter()
+        .map(|(name, id)| {
+            let prefix: String = name.to_lowercase().chars().take(2).collect();
+            let dir = if two_tier {
+                root.join(prefix).join(&name)
+            } else {
+                root.join(&name)
+            };
+            dir.join(id).join(name)
+        })
+        .collect::<Vec<_>>();
+    if let Some((prefix, rest)) = hex.split_at_checked(2) {
+        let unified = root.join(prefix).join(rest);
+        candidates.push(unified.join("debuginfo"));
+        candidates.push(unified.join("executable"));
+    }
+    candidates
+}
+
+/// Files already fetched by a debuginfod client, at
+/// `<cache>/<build-id hex>/debuginfo`.
+#[derive(Clone, Debug, Default)]
+pub struct DebuginfodCacheLocator {
+    pub cache_dirs: Vec<PathBuf>,
+}
+
+impl DebuginfodCacheLocator {
+    /// The cache the debuginfod client uses: `$DEBUGINFOD_CACHE_PATH`,
+    /// `$XDG_CACHE_HOME/debuginfod_client` or `~/.cache/debuginfod_client`.
+    pub fn from_env() -> Self {
+        Self {
+            cache_dirs: debuginfod_cache_dirs(),
+        }
+    }
+}
+
+impl SymbolLocator for DebuginfodCacheLocator {
+    fn locate(&self, module: &ModuleIdentity) -> Result<Vec<PathBuf>> {
+        let Some(build_id) = &module.build_id else {
+            return Ok(Vec::new());
+        };
+        let hex = format_hex(build_id);
+        Ok(self
+            .cache_dirs
+            .iter()
+            .map(|cache| cache.join(&hex).join("debuginfo"))
+            .collect())
+    }
+}
+
+/// Debug files fetched by build-id from debuginfod servers into the client
+/// cache: the `debuginfo`, or else the `executable` when it carries DWARF.
+#[derive(Clone, Debug)]
+pub struct DebuginfodLocator {
+    pub client: DebuginfodClient,
+}
+
+impl SymbolLocator for DebuginfodLocator {
+    fn locate(&self, module: &ModuleIdentity) -> Result<Vec<PathBuf>> {
+        let Some(build_id) = &module.build_id else {
+            return Ok(Vec::new());
+        };
+        if let Some(debuginfo) = self.client.fetch(build_id, &Artifact::Debuginfo)? {
+            return Ok(vec![debuginfo]);
+        }
+        let hex = format_hex(build_id);
+        Ok(self
+            .client
+            .fetch(build_id, &Artifact::Executable)?
+            .filter(|executable| {
+                scan_file(executable)
+                    .iter()
+                    .any(|scanned| scanned.id == hex && scanned.dwarf)
+            })
+            .into_iter()
+            .collect())
+    }
+}
+
+/// The binaries and dSYM payloads under `root` whose Mach-O UUID or ELF
+/// build-id equals the module's, those carrying DWARF (dSYM payloads, `.debug`
+/// files) first. A root indexed with `atosl index build` is answered from its
+/// index.
+#[derive(Clone, Debug)]
+pub struct DirectoryLocator {
+    pub root: PathBuf,
+}
+
+impl SymbolLocator for DirectoryLocator {
+    fn locate(&self, module: &ModuleIdentity) -> Result<Vec<PathBuf>> {
+        let Some(wanted) = module.hex_id() else {
+            return Ok(Vec::new());
+        };
+        if let Some(paths) = index::lookup(&self.root, &wanted)? {
+            return Ok(paths);
+        }
+
+        let mut candidates = Vec::new();
+        collect_candidate_files(&self.root, &mut candidates)?;
+        candidates.sort();
+
+        let scanned = parallel_map(&candidates, |candidate| scan_file(candidate));
+        let mut found = candidates
+            .into_iter()
+            .zip(scanned)
+            .filter_map(|(candidate, modules)| {
+                let module = modules.into_iter().find(|module| module.id == wanted)?;
+                Some((candidate, module.dwarf))
+            })
+            .collect::<Vec<_>>();
+        found.sort_by_key(|(_, dwarf)| !dwarf);
+        Ok(found.into_iter().map(|(candidate, _)| candidate).collect())
+    }
+}
+
+/// Runs a command, like `dsymForUUID`, to find a module's symbols.
+///
+/// The command is run with the module's id (see [`ModuleIdentity::display_id`])
+/// as its last argument, and with `ATOSL_MODULE_ID`, `ATOSL_MODULE_ARCH`,
+/// `ATOSL_MODULE_NAME` and `ATOSL_MODULE_PATH` set to what is known. It replies
+/// on stdout with paths, one per line, or with a property list or JSON. Those
+/// may be a path, a list of them, or a dictionary; from a dictionary, the `DBGDSYMPath`,
+/// `DBGSymbolRichExecutable`, `DBGExecutablePath`, `paths` and `path` entries
+/// are used, or, when it has none, the dictionaries nested in it (as
+/// `dsymForUUID` keys its reply by UUID).
+#[derive(Clone, Debug)]
+pub struct CommandLocator {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+}
+
+impl CommandLocator {
+    /// Splits `command` into a program and its arguments the way a POSIX
+    /// shell splits words: single quotes keep everything, double quotes keep
+    /// all but `\"`, `\\`, `\$` and `` \` `` escapes, and a backslash outside
+    /// quotes keeps the next character. Nothing is expanded.
+    pub fn from_command_line(command: &str) -> Result<Self> {
+        let mut words = split_command_line(command)?.into_iter();
+        let program = words
+            .next()
+            .ok_or_else(|| anyhow!("symbol command is empty"))?;
+        Ok(Self {
+            program: PathBuf::from(program),
+            args: words.collect(),
+        })
+    }
+}
+
+fn split_command_line(command: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => words.extend(word.take()),
+            '\\' => {
+                let escaped = chars
+                    .next()
+                    .ok_or_else(|| anyhow!("symbol command ends with a backslash"))?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated ' in symbol command"),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => bail!("unterminated \" in symbol command"),
+                        },
+                        Some(c) => word.push(c),
+                        None => bail!("unterminated \" in symbol command"),
+                    }
+                }
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+impl SymbolLocator for CommandLocator {
+    fn locate(&self, module: &ModuleIdentity) -> Result<Vec<PathBuf>> {
+        let Some(id) = module.display_id() else {
+            return Ok(Vec::new());
+        };
+        let mut command = Command::new(&self.program);
+        command
+            .args(&self.args)
+            .arg(&id)
+            .env("ATOSL_MODULE_ID", &id)
+            .stdin(Stdio::null());
+        if let Some(arch) = &module.arch {
+            command.env("ATOSL_MODULE_ARCH", arch);
+        }
+        if let Some(name) = &module.name {
+            command.env("ATOSL_MODULE_NAME", name);
+        }
+        if let Some(path) = &module.path {
+            command.env("ATOSL_MODULE_PATH", path);
+        }
+
+        let output = command
+            .output()
+            .with_context(|| format!("failed to run {}", self.program.display()))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            match stderr.trim() {
+                "" => bail!("{} exited with {}", self.program.display(), output.status),
+                stderr => bail!(
+                    "{} exited with {}: {stderr}",
+                    self.program.display(),
+                    output.status
+                ),
+            }
+        }
+        parse_locator_reply(&String::from_utf8_lossy(&output.stdout))
+            .with_context(|| format!("unreadable reply from {}", self.program.display()))
+    }
+}
+
+/// The built-in locators for a binary without DWARF, followed by `extra`.
+/// Unless `offline`, debuginfod servers are asked after the local searches.
+pub(crate) fn companion_locators(
+    debug_dirs: &[PathBuf],
+    dsym_dirs: &[PathBuf],
+    offline: bool,
+    extra: &[Arc<dyn SymbolLocator>],
+) -> Vec<Arc<dyn SymbolLocator>> {
+    let mut locators: Vec<Arc<dyn SymbolLocator>> = vec![
+        Arc::new(DsymLocator {
+            dsym_dirs: dsym_dirs.to_vec(),
+        }),
+        Arc::new(DebuglinkLocator {
+            debug_dirs: debug_dirs.to_vec(),
+        }),
+        Arc::new(BuildIdLocator {
+            debug_dirs: debug_dirs.to_vec(),
+        }),
+        Arc::new(SymbolStoreLocator {
+            debug_dirs: debug_dirs.to_vec(),
+        }),
+        Arc::new(DebuginfodCacheLocator::from_env()),
+    ];
+    locators.extend(debuginfod_locator(offline));
+    locators.extend(extra.iter().cloned());
+    locators
+}
+
+/// The debuginfod servers from the environment, unless `offline`.
+pub(crate) fn debuginfod_locator(offline: bool) -> Option<Arc<dyn SymbolLocator>> {
+    if offline {
+        return None;
+    }
+    let client = DebuginfodClient::from_env()?;
+    Some(Arc::new(DebuginfodLocator { client }))
+}
+
+/// Asks each locator in turn and returns the first candidate that exists,
+/// with a `.dSYM` bundle replaced by its payload.
+pub(crate) fn find_symbols(
+    locators: &[Arc<dyn SymbolLocator>],
+    module: &ModuleIdentity,
+    verbose: bool,
+) -> Option<PathBuf> {
+    for locator in locators {
+        let candidates = match locator.locate(module) {
+            Ok(candidates) => candidates,
+            Err(err) => {
+                if verbose {
+                    eprintln!("locate: {locator:?} failed: {err:#}");
+                }
+                continue;
+            }
+        };
+        let mut found = candidates.into_iter().filter_map(|candidate| {
+            if candidate.is_dir() {
+                dsym::bundle_payload(&candidate, module.uuid)
+            } else {
+                candidate.is_file().then_some(candidate)
+            }
+        });
+        if let Some(first) = found.next() {
+            if verbose {
+                eprintln!("locate: {} from {locator:?}", first.display());
+                for duplicate in found {
+                    eprintln!("locate: also matched {}", duplicate.display());
+                }
+            }
+            return Some(first);
+        }
+    }
+    if verbose {
+        if let Some(id) = module.display_id() {
+            eprintln!("locate: no symbols found for {id}");
+        }
+    }
+    None
+}
+
+/// The identities to look up for the binary at `path` (mapped as `data`): one
+/// per Mach-O slice without DWARF that `arch`/`uuid` would select, or the ELF
+/// file itself when it has no DWARF. Files with DWARF need no lookup.
+pub(crate) fn module_identities(
+    path: &Path,
+    data: &[u8],
+    arch: Option<&str>,
+    uuid: Option<[u8; 16]>,
+) -> Vec<ModuleIdentity> {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    let base = ModuleIdentity {
+        name,
+        path: Some(path.to_path_buf()),
+        ..ModuleIdentity::default()
+    };
+
+    match object::FileKind::parse(data) {
+        Ok(
+            object::FileKind::MachOFat32
+            | object::FileKind::MachOFat64
+            | object::FileKind::MachO32
+            | object::FileKind::MachO64,
+        ) => dsym::stripped_slice_uuids(data)
+            .into_iter()
+            .filter(|(slice_arch, slice_uuid)| {
+                arch.is_none_or(|filter| arch::arch_matches_filter(slice_arch, filter, true))
+                    && uuid.is_none_or(|filter| filter == *slice_uuid)
+            })
+            .map(|(slice_arch, slice_uuid)| ModuleIdentity {
+                uuid: Some(slice_uuid),
+                arch: Some(slice_arch),
+                ..base.clone()
+            })
+            .collect(),
+        _ => {
+            let Ok(file) = object::File::parse(data) else {
+                return Vec::new();
+            };
+            if file.section_by_name(".debug_line").is_some() {
+                return Vec::new();
+            }
+            vec![ModuleIdentity {
+                build_id: file.build_id().ok().flatten().map(<[u8]>::to_vec),
+                arch: arch::file_arch_name(data),
+                debuglink: read_debuglink(&file),
+                ..base
+            }]
+        }
+    }
+}
+
+fn read_debuglink<'data>(file: &object::File<'data, &'data [u8]>) -> Option<(String, u32)> {
+    let section = file.section_by_name(".gnu_debuglink")?;
+    let data = section.data().ok()?;
+    if data.len() < 4 {
+        return None;
+    }
+    let end = data
+        .iter()
+        .position(|byte| *byte == 0)
+        .unwrap_or(data.len());
+    let name = std::str::from_utf8(&data[..end]).ok()?;
+    if name.is_empty() {
+        return None;
+    }
+    // The 4-byte CRC trails the NUL-padded name, in the file's byte order.
+    let crc_bytes: [u8; 4] = data[data.len() - 4..].try_into().ok()?;
+    let crc = if file.is_little_endian() {
+        u32::from_le_bytes(crc_bytes)
+    } else {
+        u32::from_be_bytes(crc_bytes)
+    };
+    Some((name.to_string(), crc))
+}
+
+fn debuglink_candidates(binary_dir: &Path, name: &str, debug_dirs: &[PathBuf]) -> Vec<PathBuf> {
+    let mut candidates = vec![binary_dir.join(name), binary_dir.join(".debug").join(name)];
+
+    let mirrored = binary_dir
+        .canonicalize()
+        .ok()
+        .map(|abs| abs.strip_prefix("/").unwrap_or(&abs).to_path_buf());
+
+    let roots = std::iter::once(PathBuf::from("/usr/lib/debug")).chain(debug_dirs.iter().cloned());
+    for root in roots {
+        candidates.push(root.join(name));
+        if let Some(mirror) = &mirrored {
+            candidates.push(root.join(mirror).join(name));
+        }
+    }
+
+    candidates
+}
+
+pub(crate) fn debuginfod_cache_dirs() -> Vec<PathBuf> {
+    if let Ok(path) = env::var("DEBUGINFOD_CACHE_PATH") {
+        return vec![PathBuf::from(path)];
+    }
+    if let Ok(xdg) = env::var("XDG_CACHE_HOME") {
+        return vec![PathBuf::from(xdg).join("debuginfod_client")];
+    }
+    if let Ok(home) = env::var("HOME") {
+        return vec![PathBuf::from(home).join(".cache").join("debuginfod_client")];
+    }
+    Vec::new()
+}
+
+fn build_id_debug_subpath(build_id: &[u8]) -> PathBuf {
+    let hex = format_hex(build_id);
+    match hex.split_at_checked(2) {
+        Some((prefix, rest)) => PathBuf::from(prefix).join(format!("{rest}.debug")),
+        None => PathBuf::from(format!("{hex}.debug")),
+    }
+}
+
+fn file_crc32(path: &Path) -> Option<u32> {
+    let handle = fs::File::open(path).ok()?;
+    let mmap = unsafe { memmap2::Mmap::map(&handle) }.ok()?;
+    Some(crc32(&mmap))
+}
+
+// Standard CRC-32 (reflected, polynomial 0xedb88320), as used by gnu_debuglink.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc: u32 = 0xffff_ffff;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+pub(crate) fn collect_candidate_files(dir: &Path, out: &mut Vec<PathBuf>) -> Result<()> {
+    let entries = fs::read_dir(dir)
+        .with_context(|| format!("failed to read directory: {}", dir.display()))?;
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            continue;
+        }
+        let path = entry.path();
+        if file_type.is_dir() {
+            collect_candidate_files(&path, out)?;
+        } else if file_type.is_file() {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// A Mach-O UUID or ELF build-id found in a file, as lowercase hex.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct ScannedModule {
+    pub(crate) id: String,
+    pub(crate) arch: Option<String>,
+    /// Whether the slice carries a DWARF line table.
+    pub(crate) dwarf: bool,
+}
+
+/// The modules in the file at `path`, reading only the headers and load
+/// commands it needs; files without an object magic number are not mapped.
+pub(crate) fn scan_file(path: &Path) -> Vec<ScannedModule> {
+    let Ok(mut handle) = fs::File::open(path) else {
+        return Vec::new();
+    };
+    let mut magic = [0; 4];
+    if handle.read_exact(&mut magic).is_err() || !is_object_magic(magic) {
+        return Vec::new();
+    }
+    match unsafe { memmap2::Mmap::map(&handle) } {
+        Ok(mmap) => collect_modules(&mmap),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn is_object_magic(magic: [u8; 4]) -> bool {
+    const MACHO_FAT: [[u8; 4]; 2] = [[0xca, 0xfe, 0xba, 0xbe], [0xca, 0xfe, 0xba, 0xbf]];
+    magic == *b"\x7fELF"
+        || MACHO_FAT.contains(&magic)
+        || matches!(
+            u32::from_le_bytes(magic),
+            macho::MH_MAGIC | macho::MH_CIGAM | macho::MH_MAGIC_64 | macho::MH_CIGAM_64
+        )
+}
+
+/// `f` applied to every item, spread over the available cores, in order.
+pub(crate) fn parallel_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = thread::available_parallelism().map_or(1, usize::from);
+    let chunk = items.len().div_ceil(threads).max(1);
+    thread::scope(|scope| {
+        let workers = items
+            .chunks(chunk)
+            .map(|chunk| scope.spawn(|| chunk.iter().map(&f).collect::<Vec<_>>()))
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("scan thread panicked"))
+            .collect()
+    })
+}
+
+pub(crate) fn collect_modules(data: &[u8]) -> Vec<ScannedModule> {
+    match object::FileKind::parse(data) {
+        Ok(object::FileKind::MachOFat32) => FatHeader::parse_arch32(data)
+            .map(|arches| fat_slice_ids(arches, data))
+            .unwrap_or_default(),
+        Ok(object::FileKind::MachOFat64) => FatHeader::parse_arch64(data)
+            .map(|arches| fat_slice_ids(arches, data))
+            .unwrap_or_default(),
+        Ok(_) => slice_ids(data),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn fat_slice_ids<A: FatArch>(arches: &[A], data: &[u8]) -> Vec<ScannedModule> {
+    arches
+        .iter()
+        .filter_map(|arch| arch.data(data).ok())
+        .flat_map(slice_ids)
+        .collect()
+}
+
+fn slice_ids(data: &[u8]) -> Vec<ScannedModule> {
+    let Ok(file) = object::File::parse(data) else {
+        return Vec::new();
+    };
+    let arch = arch::file_arch_name(data);
+    let dwarf = file.section_by_name("__debug_line").is_some()
+        || file.section_by_name(".debug_line").is_some();
+    let uuid = file
+        .mach_uuid()
+        .ok()
+        .flatten()
+        .map(|uuid| format_hex(&uuid));
+    let build_id = file.build_id().ok().flatten().map(format_hex);
+    uuid.into_iter()
+        .chain(build_id)
+        .map(|id| ScannedModule {
+            id,
+            arch: arch.clone(),
+            dwarf,
+        })
+        .collect()
+}
+
+fn format_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+pub(crate) fn normalize_hex_id(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .flat_map(|c| c.to_lowercase())
+        .collect()
+}
+
+fn parse_locator_reply(reply: &str) -> Result<Vec<PathBuf>> {
+    let reply = reply.trim();
+    if reply.is_empty() {
+        return Ok(Vec::new());
+    }
+    let value = match reply.chars().next() {
+        Some('<') => parse_plist(reply)?,
+        Some('{' | '[' | '"') => serde_json::from_str(reply)?,
+        _ => {
+            return Ok(reply
+                .lines()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .map(PathBuf::from)
+                .collect())
+        }
+    };
+    let mut paths = Vec::new();
+    collect_reply_paths(&value, &mut paths);
+    Ok(paths)
+}
+
+const REPLY_PATH_KEYS: [&str; 5] = [
+    "DBGDSYMPath",
+    "DBGSymbolRichExecutable",
+    "DBGExecutablePath",
+    "paths",
+    "path",
+];
+
+fn collect_reply_paths(value: &Value, out: &mut Vec<PathBuf>) {
+    match value {
+        Value::String(path) => out.push(PathBuf::from(path)),
+        Value::Array(items) => items.iter().for_each(|item| collect_reply_paths(item, out)),
+        Value::Object(map) => {
+            let known = REPLY_PATH_KEYS
+                .iter()
+                .filter_map(|key| map.get(*key))
+                .collect::<Vec<_>>();
+            if known.is_empty() {
+                map.values()
+                    .filter(|value| value.is_object())
+                    .for_each(|value| collect_reply_paths(value, out));
+            } else {
+                known
+                    .into_iter()
+                    .for_each(|value| collect_reply_paths(value, out));
+            }
+        }
+        _ => {}
+    }
+}
+
+// Reads an XML property list into the equivalent JSON value. Dates and data
+// are kept as their text.
+fn parse_plist(text: &str) -> Result<Value> {
+    let mut parser = PlistParser { rest: text };
+    match parser.next_tag()? {
+        Tag::Open("plist") => {}
+        Tag::Open(name) | Tag::Empty(name) | Tag::Close(name) => {
+            bail!("expected <plist>, found <{name}>")
+        }
+    }
+    let tag = parser.next_tag()?;
+    parser.value(tag)
+}
+
+enum Tag<'a> {
+    Open(&'a str),
+    Close(&'a str),
+    Empty(&'a str),
+}
+
+struct PlistParser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> PlistParser<'a> {
+    // Skips text, comments, the XML declaration and the DOCTYPE.
+    fn next_tag(&mut self) -> Result<Tag<'a>> {
+        loop {
+            let start = self
+                .rest
+                .find('<')
+                .ok_or_else(|| anyhow!("unexpected end of property list"))?;
+            self.rest = &self.rest[start..];
+            let (skip, terminator) = if self.rest.starts_with("<!--") {
+                (true, "-->")
+            } else if self.rest.starts_with("<?") || self.rest.starts_with("<!") {
+                (true, ">")
+            } else {
+                (false, ">")
+            };
+            let end = self
+                .rest
+                .find(terminator)
+                .ok_or_else(|| anyhow!("unterminated tag in property list"))?;
+            let tag = &self.rest[1..end];
+            self.rest = &self.rest[end + terminator.len()..];
+            if skip {
+                continue;
+            }
+            return Ok(if let Some(name) = tag.strip_prefix('/') {
+                Tag::Close(name.trim())
+            } else if let Some(tag) = tag.strip_suffix('/') {
+                Tag::Empty(tag.split_whitespace().next().unwrap_or_default())
+            } else {
+                Tag::Open(tag.split_whitespace().next().unwrap_or_default())
+            });
+        }
+    }
+
+    fn text(&mut self, name: &str) -> Result<String> {
+        let close = format!("</{name}>");
+        let end = self
+            .rest
+            .find(&close)
+            .ok_or_else(|| anyhow!("missing {close} in property list"))?;
+        let text = unescape_xml(&self.rest[..end]);
+        self.rest = &self.rest[end + close.len()..];
+        Ok(text)
+    }
+
+    fn value(&mut self, tag: Tag<'a>) -> Result<Value> {
+        Ok(match tag {
+            Tag::Open("dict") => {
+                let mut map = serde_json::Map::new();
+                loop {
+                    match self.next_tag()? {
+                        Tag::Close("dict") => break,
+                        Tag::Open("key") => {
+                            let key = self.text("key")?;
+                            let tag = self.next_tag()?;
+                            map.insert(key, self.value(tag)?);
+                        }
+                        _ => bail!("expected <key> in <dict>"),
+                    }
+                }
+                Value::Object(map)
+            }
+            Tag::Open("array") => {
+                let mut items = Vec::new();
+                loop {
+                    match self.next_tag()? {
+                        Tag::Close("array") => break,
+                        tag => items.push(self.value(tag)?),
+                    }
+                }
+                Value::Array(items)
+            }
+            Tag::Open(name @ ("integer" | "real")) => {
+                let text = self.text(name)?;
+                serde_json::from_str(text.trim()).unwrap_or(Value::String(text))
+            }
+            Tag::Open(name @ ("string" | "date" | "data")) => Value::String(self.text(name)?),
+            Tag::Empty("true") => Value::Bool(true),
+            Tag::Empty("false") => Value::Bool(false),
+            Tag::Empty("dict") => Value::Object(serde_json::Map::new()),
+            Tag::Empty("array") => Value::Array(Vec::new()),
+            Tag::Empty("string") => Value::String(String::new()),
+            Tag::Open(name) | Tag::Empty(name) | Tag::Close(name) => {
+                bail!("unexpected <{name}> in property list")
+            }
+        })
+    }
+}
+
+// Replaces the predefined entities and character references; anything else
+// that starts with `&` is kept as it is.
+fn unescape_xml(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let entity = rest[1..].find(';').map(|end| &rest[1..end + 1]);
+        let replacement = entity.and_then(|entity| match entity {
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            "amp" => Some('&'),
+            _ => {
+                let code = match entity.strip_prefix("#x").or(entity.strip_prefix("#X")) {
+                    Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                    None => entity.strip_prefix('#')?.parse().ok(),
+                };
+                code.and_then(char::from_u32)
+            }
+        });
+        match (entity, replacement) {
+            (Some(entity), Some(c)) => {
+                out.push(c);
+                rest = &rest[entity.len() + 2..];
+            }
+            _ => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbol_store_candidates_follow_symstore_and_unified_layouts() {
+        let root = tempfile::tempdir().unwrap();
+        let elf = ModuleIdentity {
+            build_id: Some(vec![0xab, 0xcd, 0xef]),
+            name: Some("App".to_string()),
+            ..ModuleIdentity::default()
+        };
+        assert_eq!(
+            symbol_store_candidates(root.path(), &elf, "abcdef"),
+            [
+                "_.debug/elf-buildid-sym-abcdef/_.debug",
+                "App/elf-buildid-abcdef/App",
+                "App/ABCDEF/App",
+                "ab/cdef/debuginfo",
+                "ab/cdef/executable",
+            ]
+            .map(|path| root.path().join(path))
+        );
+
+        fs::write(root.path().join("index2.txt"), "").unwrap();
+        let macho = ModuleIdentity {
+            uuid: Some([0x11; 16]),
+            ..ModuleIdentity::default()
+        };
+        let hex = "11".repeat(16);
+        assert_eq!(
+            symbol_store_candidates(root.path(), &macho, &hex),
+            [
+                format!("_./_.dwarf/mach-uuid-sym-{hex}/_.dwarf"),
+                format!("11/{}/debuginfo", &hex[2..]),
+                format!("11/{}/executable", &hex[2..]),
+            ]
+            .map(|path| root.path().join(path))
+        );
+    }
+
+    #[test]
+    fn scans_only_files_with_an_object_magic_number() {
+        assert!(is_object_magic(*b"\x7fELF"));
+        assert!(is_object_magic([0xcf, 0xfa, 0xed, 0xfe]));
+        assert!(is_object_magic([0xfe, 0xed, 0xfa, 0xce]));
+        assert!(is_object_magic([0xca, 0xfe, 0xba, 0xbe]));
+        assert!(!is_object_magic(*b"!<ar"));
+        assert!(!is_object_magic(*b"{\"ve"));
+
+        let items = (0..100).collect::<Vec<u32>>();
+        assert_eq!(
+            parallel_map(&items, |item| item * 2),
+            items.iter().map(|item| item * 2).collect::<Vec<_>>()
+        );
+        assert!(parallel_map(&[] as &[u32], |item| *item).is_empty());
+    }
+
+    #[test]
+    fn parses_dsym_for_uuid_plist_reply() {
+        let reply = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>4C4C44F5-5555-3144-A1E8-6F1D4D5F1F64</key>
+	<dict>
+		<key>DBGArchitecture</key>
+		<string>arm64</string>
+		<key>DBGBuildSourcePath</key>
+		<string>/build/src</string>
+		<key>DBGDSYMPath</key>
+		<string>/symbols/My &amp; App.dSYM</string>
+		<key>DBGSymbolRichExecutable</key>
+		<string>/symbols/MyApp</string>
+		<key>DBGDSYMHasUUID</key>
+		<true/>
+	</dict>
+</dict>
+</plist>
+"#;
+        assert_eq!(
+            parse_locator_reply(reply).unwrap(),
+            [
+                PathBuf::from("/symbols/My & App.dSYM"),
+                PathBuf::from("/symbols/MyApp")
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_plist_values_entities_and_nesting() {
+        let reply = r#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0">
+<!-- a <comment> -->
+<dict>
+	<key>A&#x26;B</key>
+	<dict>
+		<key>DBGDSYMPath</key>
+		<string>/s/&lt;x&gt; &#233;t&#xE9; &quot;q&quot; &apos;a&apos; &amp;amp; &bogus; &</string>
+	</dict>
+	<key>flags</key>
+	<array>
+		<true/>
+		<false />
+		<integer>42</integer>
+		<real>1.5</real>
+		<string/>
+		<dict>
+			<key>path</key>
+			<string>/nested.debug</string>
+		</dict>
+	</array>
+</dict>
+</plist>
+"#;
+        let value = parse_plist(reply).unwrap();
+        assert_eq!(
+            value["A&B"]["DBGDSYMPath"],
+            "/s/<x> été \"q\" 'a' &amp; &bogus; &"
+        );
+        assert_eq!(
+            value["flags"],
+            serde_json::json!([true, false, 42, 1.5, "", {"path": "/nested.debug"}])
+        );
+        // Neither value names a path key, so the nested dictionaries are used.
+        assert_eq!(
+            parse_locator_reply(reply).unwrap(),
+            [PathBuf::from("/s/<x> été \"q\" 'a' &amp; &bogus; &")]
+        );
+
+        assert!(parse_plist("<plist><dict><string>x</string></dict></plist>").is_err());
+        assert!(parse_plist("<plist><dict><key>k</key>").is_err());
+        assert!(parse_plist("<dict></dict>").is_err());
+    }
+
+    #[test]
+    fn splits_symbol_command_like_a_shell() {
+        let locator = CommandLocator::from_command_line(
+            r#"'/opt/My Tools/fetch' --server "symbols internal" a\ b "q\"uote\n" ''"#,
+        )
+        .unwrap();
+        assert_eq!(locator.program, PathBuf::from("/opt/My Tools/fetch"));
+        assert_eq!(
+            locator.args,
+            ["--server", "symbols internal", "a b", "q\"uote\\n", ""]
+        );
+        assert_eq!(
+            CommandLocator::from_command_line("  fetch   -v ")
+                .unwrap()
+                .args,
+            ["-v"]
+        );
+        assert!(CommandLocator::from_command_line("   ").is_err());
+        assert!(CommandLocator::from_command_line("fetch 'open").is_err());
+        assert!(CommandLocator::from_command_line("fetch \"open").is_err());
+        assert!(CommandLocator::from_command_line("fetch \\").is_err());
+    }
+
+    #[test]
+    fn parses_json_replies() {
+        assert_eq!(
+            parse_locator_reply(r#"{"paths": ["/a.debug", "/b.debug"]}"#).unwrap(),
+            [PathBuf::from("/a.debug"), PathBuf::from("/b.debug")]
+        );
+        assert_eq!(
+            parse_locator_reply(r#"{"abcd": {"DBGDSYMPath": "/x.dSYM"}}"#).unwrap(),
+            [PathBuf::from("/x.dSYM")]
+        );
+        assert_eq!(
+            parse_locator_reply("\"/only.debug\"\n").unwrap(),
+            [PathBuf::from("/only.debug")]
+        );
+        assert!(parse_locator_reply("").unwrap().is_empty());
+        assert!(parse_locator_reply("{not json").is_err());
+        assert_eq!(
+            parse_locator_reply("/a.debug\n\n  /b b.debug \n").unwrap(),
+            [PathBuf::from("/a.debug"), PathBuf::from("/b b.debug")]
+        );
+    }
+
+    #[test]
+    fn module_identity_from_uuid_or_build_id() {
+        let uuid = ModuleIdentity::from_id("4c4c44f5-5555-3144-a1e8-6f1d4d5f1f64").unwrap();
+        assert_eq!(
+            uuid.display_id().unwrap(),
+            "4C4C44F5-5555-3144-A1E8-6F1D4D5F1F64"
+        );
+        let build_id = ModuleIdentity::from_id("abcdef0123").unwrap();
+        assert_eq!(build_id.build_id, Some(vec![0xab, 0xcd, 0xef, 0x01, 0x23]));
+        assert_eq!(build_id.hex_id().unwrap(), "abcdef0123");
+        assert!(ModuleIdentity::from_id("xyz").is_err());
+        assert!(ModuleIdentity::from_id("abcd/../ef").is_err());
+        assert!(ModuleIdentity::from_id("ab cd").is_err());
+        assert!(ModuleIdentity::from_id("0xabcd").is_err());
+    }
+}
@@ -1,7 +1,14 @@
-use atosl::{OutputFormat, SymbolizeOptions};
-use clap::{Parser, ValueEnum};
+use anyhow::Context as _;
+use atosl::debuginfod::DebuginfodServer;
+use atosl::index;
+use atosl::locate::CommandLocator;
+use atosl::service::{ServiceOptions, SymbolService};
+use atosl::{OutputFormat, SymbolLocator, SymbolizeOptions};
+use clap::{Parser, Subcommand, ValueEnum};
+use std::net::TcpListener;
 use std::path::PathBuf;
 use std::process;
+use std::sync::Arc;
 
 #[derive(Copy, Clone, Debug, Eq, PartialEq, ValueEnum)]
 enum CliOutputFormat {
@@ -23,20 +30,58 @@ impl From<CliOutputFormat> for OutputFormat {
 }
 
 #[derive(Parser, Debug)]
-#[command(author, version, about)]
+#[command(
+    author,
+    version,
+    about,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<ToolCommand>,
+
     /// Symbol file path or binary file path
-    #[arg(short = 'o', long = "object", value_name = "OBJECT_PATH")]
-    object_path: PathBuf,
+    #[arg(
+        short = 'o',
+        long = "object",
+        value_name = "OBJECT_PATH",
+        required_unless_present = "server_stdio"
+    )]
+    object_path: Option<PathBuf>,
 
     /// Load address of binary image
-    #[arg(short = 'l', long = "load-address", value_parser = parse_address_string)]
-    load_address: u64,
+    #[arg(
+        short = 'l',
+        long = "load-address",
+        value_parser = parse_address_string,
+        required_unless_present_any = ["kernel_oops", "slide", "offset", "server_stdio"]
+    )]
+    load_address: Option<u64>,
+
+    /// Slide of the binary image (`atos -s`): addresses are static addresses
+    /// plus the slide
+    #[arg(
+        short = 's',
+        long = "slide",
+        value_parser = parse_address_string,
+        conflicts_with = "load_address"
+    )]
+    slide: Option<u64>,
+
+    /// Treat addresses as offsets from the start of the image (`atos
+    /// -offset`), the same as `-l 0`
+    #[arg(
+        long = "offset",
+        conflicts_with_all = ["load_address", "slide", "file_offset_type", "module_base", "translate_offsets"]
+    )]
+    offset: bool,
 
     /// Addresses that should be symbolized. When omitted, addresses are read
-    /// from --input or stdin (one or more per line, whitespace-separated).
-    #[arg(value_parser = parse_address_string)]
-    addresses: Vec<u64>,
+    /// from --input or stdin (one or more per line, whitespace-separated). A
+    /// `pc:` prefix marks the exact PC of the crashing frame.
+    #[arg(value_parser = parse_frame_address)]
+    addresses: Vec<(u64, bool)>,
 
     /// Read addresses from this file instead of the command line (use stdin
     /// when neither addresses nor this flag are given)
@@ -47,22 +92,76 @@ struct Args {
     #[arg(long = "debug-dir")]
     debug_dir: Vec<PathBuf>,
 
+    /// Extra directory searched recursively for the .dSYM bundle of a Mach-O
+    /// binary without DWARF, matched by UUID (repeatable)
+    #[arg(long = "dsym-dir")]
+    dsym_dir: Vec<PathBuf>,
+
+    /// Command run with a module's UUID or build-id to locate its symbols when
+    /// no local file matches, like dsymForUUID; it replies with a plist or JSON
+    /// (repeatable)
+    #[arg(long = "symbol-command", value_name = "COMMAND", value_parser = parse_symbol_command)]
+    symbol_command: Vec<CommandLocator>,
+
+    /// Never ask the debuginfod servers in `DEBUGINFOD_URLS`; use only local
+    /// files and caches
+    #[arg(long)]
+    offline: bool,
+
     /// Enable verbose diagnostics
     #[arg(short, long)]
     verbose: bool,
 
     /// Treat addresses as file offsets: the lookup uses `address -
-    /// load-address` directly, without re-basing onto the __TEXT vmaddr. To
-    /// reproduce `atos -offset N`, use the default mode instead: `atosl -l 0 N`.
+    /// load-address` directly, without re-basing onto the __TEXT vmaddr. This
+    /// is not `atos -offset`; use --offset for that.
     #[arg(short = 'f', long = "file-offsets")]
     file_offset_type: bool,
 
+    /// ELF: treat --load-address as the module's load base (the start of its
+    /// lowest PT_LOAD mapping, as in /proc/<pid>/maps or dladdr) and re-base
+    /// onto that segment's p_vaddr instead of the .text address
+    #[arg(long = "module-base", conflicts_with = "file_offset_type")]
+    module_base: bool,
+
+    /// Treat `address - load-address` as a real file offset (as in Android
+    /// tombstones, perf or /proc/<pid>/maps) and translate it to a virtual
+    /// address through the ELF program headers or Mach-O segments
+    #[arg(
+        long = "translate-offsets",
+        conflicts_with_all = ["file_offset_type", "module_base"]
+    )]
+    translate_offsets: bool,
+
+    /// Load address of a relocatable object's section as NAME=ADDRESS
+    /// (repeatable), e.g. from /sys/module/<module>/sections/. Addresses are
+    /// matched after subtracting -l, so pass -l 0 with absolute section
+    /// addresses. Without any, addresses of an ET_REL/MH_OBJECT file are
+    /// offsets into its text section.
+    #[arg(long = "section", value_name = "NAME=ADDRESS", value_parser = parse_section)]
+    sections: Vec<(String, u64)>,
+
     /// Expand inlined functions into the full call stack (innermost first),
     /// like `atos -i`. Off by default, which prints only the outermost frame.
     /// JSON output always includes inline frames under `inlined_by`.
     #[arg(long = "inline-frames")]
     inline_frames: bool,
 
+    /// Print inline frames of one address on a single line, separated by
+    /// DELIMITER (`atos -d`)
+    #[arg(short = 'd', long = "delimiter", value_name = "DELIMITER")]
+    delimiter: Option<String>,
+
+    /// Print full source file paths in text output (`atos -fullPath`). By
+    /// default only the file name is printed, as atos does.
+    #[arg(long = "full-path")]
+    full_path: bool,
+
+    /// Accepted for `atos -printHeader` compatibility. atos prints the header
+    /// only for a live process (-p), so with -o this prints nothing.
+    #[arg(long = "print-header")]
+    print_header: bool,
+
     /// Select architecture for Mach-O universal/fat files
     #[arg(short = 'a', long)]
     arch: Option<String>,
@@ -71,9 +170,191 @@ struct Args {
     #[arg(long)]
     uuid: Option<String>,
 
+    /// Select an image inside a dyld shared cache by install name or file
+    /// name (e.g. UIKitCore)
+    #[arg(long)]
+    image: Option<String>,
+
     /// Output format
     #[arg(long, value_enum, default_value_t = CliOutputFormat::Text)]
     format: CliOutputFormat,
+
+    /// Crashing-thread register value as NAME=VALUE (repeatable). The selector
+    /// register (x1 on arm64, rsi on x86_64) names the objc_msgSend selector.
+    #[arg(long = "register", value_name = "NAME=VALUE", value_parser = parse_register)]
+    registers: Vec<(String, u64)>,
+
+    /// Decode a Linux kernel oops, KASAN report or other splat read from
+    /// --input or stdin, with -o pointing at vmlinux. Frames such as
+    /// `func+0x1a/0x90 [module]` are resolved by name in vmlinux or the module.
+    #[arg(long = "kernel-oops", conflicts_with = "addresses")]
+    kernel_oops: bool,
+
+    /// Answer requests read line by line from stdin until it closes, like
+    /// llvm-symbolizer: `CODE <path> <addr>`, `DATA <path> <addr>` or one JSON
+    /// object per line. Objects stay loaded between requests; -o, when given,
+    /// is the object of requests that name none.
+    #[arg(
+        long = "server-stdio",
+        conflicts_with_all = ["addresses", "input", "kernel_oops"]
+    )]
+    server_stdio: bool,
+
+    /// Directory searched recursively for kernel module .ko files (repeatable)
+    #[arg(long = "module-dir", requires = "kernel_oops")]
+    module_dir: Vec<PathBuf>,
+
+    /// Runtime address of a vmlinux symbol as NAME=ADDRESS (e.g. `_text` from
+    /// /proc/kallsyms), used to compute the KASLR offset for raw addresses
+    #[arg(
+        long = "kernel-symbol",
+        value_name = "NAME=ADDRESS",
+        value_parser = parse_kernel_symbol,
+        requires = "kernel_oops"
+    )]
+    kernel_symbol: Option<(String, u64)>,
+
+    /// Pointer-authentication bits to clear from every address before lookup
+    /// (e.g. 0xffffff8000000000). arm64e images with a load address get a
+    /// mask automatically.
+    #[arg(long = "pac-mask", value_name = "MASK", value_parser = parse_address_string)]
+    pac_mask: Option<u64>,
+
+    /// Number of virtual-address bits; higher bits (PAC, tags) are cleared
+    /// before lookup. Defaults to 39 for arm64e with a nonzero load address
+    /// (47 above 2^39); without one, no bits are cleared.
+    #[arg(
+        long = "va-bits",
+        value_name = "BITS",
+        value_parser = clap::value_parser!(u32).range(1..=64),
+        conflicts_with = "pac_mask"
+    )]
+    va_bits: Option<u32>,
+
+    /// Treat every address not marked `pc:` as a return address from a caller
+    /// frame and look up the instruction before it, as lldb does
+    #[arg(long = "frames-are-return-addresses")]
+    frames_are_return_addresses: bool,
+}
+
+/// The subcommands that are not a symbolication: `atosl index …`,
+/// `atosl serve …` and `atosl serve-debuginfod …`.
+#[derive(Subcommand, Debug)]
+enum ToolCommand {
+    /// Keep an index of the UUIDs and build-ids under a directory, so `--uuid`
+    /// and `--dsym-dir` lookups there need not read every file
+    #[command(subcommand)]
+    Index(IndexCommand),
+    /// Serve the binaries, debug files and sources under directories over the
+    /// debuginfod protocol, by ELF build-id or Mach-O UUID
+    ServeDebuginfod {
+        /// Directory to serve (repeatable)
+        #[arg(long = "dir", value_name = "DIR", required = true)]
+        dirs: Vec<PathBuf>,
+        /// Address to listen on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8002")]
+        listen: String,
+        /// Log every request
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Symbolize crash frames sent as JSON over HTTP, finding each module by
+    /// UUID or build-id and keeping recently used modules loaded
+    Serve {
+        /// Directory searched for symbols, indexed or not (repeatable)
+        #[arg(long = "symbols", value_name = "DIR", required = true)]
+        symbol_dirs: Vec<PathBuf>,
+        /// Address to listen on
+        #[arg(long, value_name = "ADDR", default_value = "127.0.0.1:8003")]
+        listen: String,
+        /// Requests handled at once [default: number of CPUs]
+        #[arg(long, value_name = "N")]
+        workers: Option<usize>,
+        /// Modules kept loaded between requests
+        #[arg(long, value_name = "N", default_value_t = 64)]
+        cache_size: usize,
+        /// Never ask the debuginfod servers in DEBUGINFOD_URLS
+        #[arg(long)]
+        offline: bool,
+        /// Log every request
+        #[arg(short, long)]
+        verbose: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum IndexCommand {
+    /// Index every file under DIR
+    Build { dir: PathBuf },
+    /// Re-read only the new and changed files under DIR
+    Update { dir: PathBuf },
+}
+
+fn run_tool(command: ToolCommand) -> anyhow::Result<()> {
+    match command {
+        ToolCommand::Index(command) => {
+            let summary = match command {
+                IndexCommand::Build { dir } => index::build_index(&dir)?,
+                IndexCommand::Update { dir } => index::update_index(&dir)?,
+            };
+            println!(
+                "{}: {} files, {} module ids ({} read, {} removed)",
+                summary.index_path.display(),
+                summary.files,
+                summary.modules,
+                summary.read,
+                summary.removed
+            );
+        }
+        ToolCommand::ServeDebuginfod {
+            dirs,
+            listen,
+            verbose,
+        } => {
+            let server = DebuginfodServer::new(dirs, verbose)?;
+            let listener = TcpListener::bind(&listen)
+                .with_context(|| format!("failed to listen on {listen}"))?;
+            println!(
+                "serving {} module ids on http://{}",
+                server.module_count(),
+                listener.local_addr()?
+            );
+            server.serve(listener)?;
+        }
+        ToolCommand::Serve {
+            symbol_dirs,
+            listen,
+            workers,
+            cache_size,
+            offline,
+            verbose,
+        } => {
+            let defaults = ServiceOptions::default();
+            let service = SymbolService::new(ServiceOptions {
+                symbol_dirs,
+                workers: workers.unwrap_or(defaults.workers),
+                cache_size,
+                offline,
+                verbose,
+            });
+            let listener = TcpListener::bind(&listen)
+                .with_context(|| format!("failed to listen on {listen}"))?;
+            println!("symbolicating on http://{}", listener.local_addr()?);
+            service.serve(listener)?;
+        }
+    }
+    Ok(())
+}
+
+// atos spells its long options with a single dash; accept those spellings.
+fn atos_compatible_args(args: impl Iterator<Item = String>) -> impl Iterator<Item = String> {
+    args.map(|arg| match arg.as_str() {
+        "-fullPath" => "--full-path".to_string(),
+        "-printHeader" => "--print-header".to_string(),
+        "-offset" => "--offset".to_string(),
+        "-arch" => "--arch".to_string(),
+        _ => arg,
+    })
 }
 
 fn parse_address_string(address: &str) -> Result<u64, String> {
@@ -87,20 +368,92 @@ fn parse_address_string(address: &str) -> Result<u64, String> {
     }
 }
 
+fn parse_frame_address(value: &str) -> Result<(u64, bool), String> {
+    match value.strip_prefix("pc:") {
+        Some(address) => Ok((parse_address_string(address)?, true)),
+        None => Ok((parse_address_string(value)?, false)),
+    }
+}
+
+fn parse_symbol_command(value: &str) -> Result<CommandLocator, String> {
+    CommandLocator::from_command_line(value).map_err(|err| err.to_string())
+}
+
+fn parse_register(value: &str) -> Result<(String, u64), String> {
+    parse_named_address(value, "register")
+}
+
+fn parse_section(value: &str) -> Result<(String, u64), String> {
+    parse_named_address(value, "section")
+}
+
+fn parse_kernel_symbol(value: &str) -> Result<(String, u64), String> {
+    parse_named_address(value, "symbol")
+}
+
+fn parse_named_address(value: &str, kind: &str) -> Result<(String, u64), String> {
+    let (name, address) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=VALUE, got '{value}'"))?;
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("missing {kind} name in '{value}'"));
+    }
+    Ok((name.to_string(), parse_address_string(address.trim())?))
+}
+
 fn main() {
-    let args = Args::parse();
+    let args = Args::parse_from(atos_compatible_args(std::env::args()));
+    if let Some(command) = args.command {
+        if let Err(err) = run_tool(command) {
+            eprintln!("{err:#}");
+            process::exit(1);
+        }
+        return;
+    }
+    // Without a live process there is no header to print; see --print-header.
+    let _ = args.print_header;
     let options = SymbolizeOptions {
-        object_path: args.object_path,
-        load_address: args.load_address,
-        addresses: args.addresses,
+        object_path: args.object_path.unwrap_or_default(),
+        load_address: args.load_address.unwrap_or_default(),
+        addresses: args.addresses.iter().map(|(address, _)| *address).collect(),
         verbose: args.verbose,
         file_offsets: args.file_offset_type,
+        module_base: args.module_base,
+        translate_offsets: args.translate_offsets,
+        sections: args.sections,
         inline_frames: args.inline_frames,
         arch: args.arch,
         uuid: args.uuid,
         format: args.format.into(),
         input: args.input,
         debug_dirs: args.debug_dir,
+        registers: args.registers,
+        image: args.image,
+        kernel_oops: args.kernel_oops,
+        server_stdio: args.server_stdio,
+        module_dirs: args.module_dir,
+        kernel_symbol: args.kernel_symbol,
+        pac_mask: args.pac_mask,
+        va_bits: args.va_bits,
+        return_addresses: args.frames_are_return_addresses,
+        exact_pc_frames: args
+            .addresses
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, exact_pc))| *exact_pc)
+            .map(|(index, _)| index)
+            .collect(),
+        slide: args.slide,
+        full_path: args.full_path,
+        inline_delimiter: args.delimiter,
+        dsym_dirs: args.dsym_dir,
+        locators: args
+            .symbol_command
+            .into_iter()
+            .map(|locator| Arc::new(locator) as Arc<dyn SymbolLocator>)
+            .collect(),
+        offline: args.offline,
     };
 
     let exit_code = match atosl::atosl::run(options) {
@@ -116,7 +469,29 @@ fn main() {
 
 #[cfg(test)]
 mod tests {
-    use super::parse_address_string;
+    use super::{
+        atos_compatible_args, parse_address_string, parse_register, Args, IndexCommand, ToolCommand,
+    };
+    use clap::{CommandFactory, Parser};
+
+    #[test]
+    fn parse_tools_as_subcommands() {
+        Args::command().debug_assert();
+        let args = Args::try_parse_from(["atosl", "index", "build", "/symbols"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Some(ToolCommand::Index(IndexCommand::Build { .. }))
+        ));
+        assert!(
+            Args::try_parse_from(["atosl", "-o", "app", "index", "build", "/symbols"]).is_err()
+        );
+        assert!(
+            Args::try_parse_from(["atosl", "-o", "app", "-l", "0x1000", "0x1234"])
+                .unwrap()
+                .command
+                .is_none()
+        );
+    }
 
     #[test]
     fn parse_hex_address() {
@@ -133,4 +508,32 @@ mod tests {
     fn parse_invalid_address() {
         assert!(parse_address_string("not_a_number").is_err());
     }
+
+    #[test]
+    fn parse_register_value() {
+        assert_eq!(
+            parse_register("x1=0x1f00").unwrap(),
+            ("x1".to_string(), 0x1f00)
+        );
+        assert!(parse_register("x1").is_err());
+        assert!(parse_register("=0x10").is_err());
+    }
+
+    #[test]
+    fn rewrite_atos_single_dash_options() {
+        let args = ["atosl", "-arch", "arm64", "-fullPath", "-offset", "0x10"]
+            .map(String::from)
+            .into_iter();
+        assert_eq!(
+            atos_compatible_args(args).collect::<Vec<_>>(),
+            [
+                "atosl",
+                "--arch",
+                "arm64",
+                "--full-path",
+                "--offset",
+                "0x10"
+            ]
+        );
+    }
 }
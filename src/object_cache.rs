@@ -0,0 +1,277 @@
+// Objects kept loaded between requests by the long-running modes (`atosl
+// serve`, `--server-stdio`).
+//
+// A symbolizer borrows the mapped file and the DWARF context built from it, so
+// each loaded object lives on a thread of its own that runs the jobs sent to
+// it, and unmaps everything once the last handle to it is dropped. Jobs for one
+// object therefore run one after another, however many callers share it. A
+// job that panics fails only its own caller; the thread goes on to the next.
+use crate::atosl::{with_symbolizer, ObjectSymbolizer, SymbolizeOptions};
+use crate::symbolizer::ObjectInfo;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+
+type Job = Box<dyn for<'a> FnOnce(&ObjectSymbolizer<'a>) + Send>;
+
+pub(crate) struct LoadedObject {
+    jobs: mpsc::Sender<Job>,
+    ready: Mutex<mpsc::Receiver<Result<ObjectInfo, String>>>,
+    info: OnceLock<Result<ObjectInfo, String>>,
+    /// Set once a job found the object's thread gone.
+    stopped: AtomicBool,
+}
+
+impl LoadedObject {
+    /// Starts loading the object `open` describes on a thread of its own.
+    /// `open` runs on that thread, so it may search for the object.
+    pub(crate) fn spawn(open: impl FnOnce() -> Result<SymbolizeOptions> + Send + 'static) -> Self {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let (loaded, ready) = mpsc::channel();
+        thread::spawn(move || {
+            let result = open().and_then(|options| {
+                with_symbolizer(&options, |symbolizer, object_path, selected_slice| {
+                    let _ = loaded.send(Ok(ObjectInfo {
+                        object_path,
+                        object_name: symbolizer.object_name.to_string(),
+                        arch: symbolizer.arch_name.to_string(),
+                        selected_slice,
+                    }));
+                    for job in queue {
+                        job(symbolizer);
+                    }
+                })
+            });
+            if let Err(err) = result {
+                let _ = loaded.send(Err(format!("{err:#}")));
+            }
+        });
+        Self {
+            jobs,
+            ready: Mutex::new(ready),
+            info: OnceLock::new(),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    /// Waits for the object to be loaded.
+    pub(crate) fn info(&self) -> Result<&ObjectInfo, String> {
+        self.info
+            .get_or_init(|| {
+                self.ready
+                    .lock()
+                    .expect("ready lock")
+                    .recv()
+                    .unwrap_or_else(|_| Err(stopped()))
+            })
+            .as_ref()
+            .map_err(String::clone)
+    }
+
+    /// Runs `job` against the object's symbolizer and waits for its result.
+    /// Once the object's thread is gone every job fails, and the cache loads
+    /// the object again on its next lookup.
+    pub(crate) fn run<T: Send + 'static>(
+        &self,
+        job: impl for<'a> FnOnce(&ObjectSymbolizer<'a>) -> T + Send + 'static,
+    ) -> Result<T, String> {
+        let (reply, result) = mpsc::channel();
+        // The symbolizer's lazily built state is only ever filled in whole, so
+        // it is still usable after a job panicked halfway through a lookup.
+        let sent = self.jobs.send(Box::new(move |symbolizer| {
+            let _ = reply.send(panic::catch_unwind(AssertUnwindSafe(|| job(symbolizer))));
+        }));
+        match sent.ok().and_then(|()| result.recv().ok()) {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(payload)) => Err(format!(
+                "the object's symbolizer panicked: {}",
+                panic_message(payload.as_ref())
+            )),
+            None => {
+                self.stopped.store(true, Ordering::Relaxed);
+                Err(stopped())
+            }
+        }
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+}
+
+fn stopped() -> String {
+    "the object's symbolizer stopped".to_string()
+}
+
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("(no message)")
+}
+
+/// The most recently used objects, by a key that identifies what was asked
+/// for.
+pub(crate) struct ObjectCache<K> {
+    capacity: usize,
+    entries: Mutex<CacheEntries<K>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+struct CacheEntries<K> {
+    objects: HashMap<K, (Arc<LoadedObject>, u64)>,
+    clock: u64,
+}
+
+impl<K: Clone + Eq + Hash> ObjectCache<K> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Mutex::new(CacheEntries {
+                objects: HashMap::new(),
+                clock: 0,
+            }),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// The loaded object for `key`, loading it with `load` when it is not
+    /// cached. An object that fails to load is not kept, so symbols that show
+    /// up later are found on the next try.
+    pub(crate) fn open(
+        &self,
+        key: &K,
+        load: impl FnOnce() -> LoadedObject,
+    ) -> Result<Arc<LoadedObject>, String> {
+        let object = self.get_or_load(key, load);
+        match object.info() {
+            Ok(_) => Ok(object),
+            Err(error) => {
+                let mut entries = self.entries.lock().expect("cache lock");
+                if entries
+                    .objects
+                    .get(key)
+                    .is_some_and(|(cached, _)| Arc::ptr_eq(cached, &object))
+                {
+                    entries.objects.remove(key);
+                }
+                Err(error)
+            }
+        }
+    }
+
+    // Loading happens on the object's own thread, so holding the lock here
+    // only covers starting it. An object whose thread stopped is replaced.
+    fn get_or_load(&self, key: &K, load: impl FnOnce() -> LoadedObject) -> Arc<LoadedObject> {
+        let mut entries = self.entries.lock().expect("cache lock");
+        entries.clock += 1;
+        let now = entries.clock;
+        if entries
+            .objects
+            .get(key)
+            .is_some_and(|(object, _)| object.is_stopped())
+        {
+            entries.objects.remove(key);
+        }
+        if let Some((object, last_used)) = entries.objects.get_mut(key) {
+            *last_used = now;
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            return Arc::clone(object);
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        if entries.objects.len() >= self.capacity {
+            let oldest = entries
+                .objects
+                .iter()
+                .min_by_key(|(_, (_, last_used))| *last_used)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                entries.objects.remove(&oldest);
+            }
+        }
+        let object = Arc::new(load());
+        entries
+            .objects
+            .insert(key.clone(), (Arc::clone(&object), now));
+        object
+    }
+
+    /// How many objects are loaded.
+    pub(crate) fn len(&self) -> usize {
+        self.entries.lock().expect("cache lock").objects.len()
+    }
+
+    /// Lookups answered by a loaded object.
+    pub(crate) fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    /// Lookups that had to load the object.
+    pub(crate) fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // An object that loaded but whose thread has since gone away.
+    fn orphaned() -> LoadedObject {
+        let (jobs, _) = mpsc::channel::<Job>();
+        let (_, ready) = mpsc::channel();
+        LoadedObject {
+            jobs,
+            ready: Mutex::new(ready),
+            info: OnceLock::from(Ok(ObjectInfo {
+                object_path: "fixture".into(),
+                object_name: "fixture".to_string(),
+                arch: "x86_64".to_string(),
+                selected_slice: None,
+            })),
+            stopped: AtomicBool::new(false),
+        }
+    }
+
+    #[test]
+    fn reloads_an_object_whose_symbolizer_stopped() {
+        let cache = ObjectCache::new(4);
+        let first = cache.open(&"key", orphaned).unwrap();
+        assert!(cache.open(&"key", || unreachable!()).is_ok());
+        assert_eq!(first.run(|_| ()), Err(stopped()));
+
+        let second = cache.open(&"key", orphaned).unwrap();
+        assert!(!Arc::ptr_eq(&first, &second));
+        assert_eq!((cache.hits(), cache.misses()), (1, 2));
+    }
+
+    #[test]
+    fn keeps_running_jobs_after_one_panics() {
+        let object = LoadedObject::spawn(|| {
+            Ok(SymbolizeOptions {
+                object_path: std::env::current_exe()?,
+                ..SymbolizeOptions::default()
+            })
+        });
+        object.info().unwrap();
+
+        let error = object
+            .run(|_| -> u64 { panic!("fixture job failed") })
+            .unwrap_err();
+        assert_eq!(
+            error,
+            "the object's symbolizer panicked: fixture job failed"
+        );
+        // The same thread still answers.
+        assert_eq!(object.run(|_| 7), Ok(7));
+        assert!(!object.is_stopped());
+    }
+}
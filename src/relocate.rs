@@ -0,0 +1,409 @@
+// Relocatable objects (ELF `ET_REL`, Mach-O `MH_OBJECT`) have not been through
+// a linker. Every ELF section starts at address 0, and the DWARF only holds
+// real addresses once its relocations are applied. A `SectionLayout` gives
+// each allocated section an address of its own so that symbols and DWARF from
+// different sections do not overlap.
+//
+// RISC-V and LoongArch linkers relax code, so their assemblers cannot resolve
+// the distance between two labels (a function's length, a line-table advance)
+// and emit ADD/SUB relocation pairs for it instead. `object` 0.28 only
+// classifies the plain 32/64-bit data relocations, and not even those for
+// LoongArch, so both are decoded here by relocation type.
+use anyhow::{bail, Result};
+use object::{
+    elf, Object, ObjectSection, ObjectSymbol, RelocationKind, RelocationTarget, SectionFlags,
+    SectionIndex,
+};
+use std::borrow::Cow;
+use std::collections::HashMap;
+
+#[derive(Debug, Default)]
+pub(crate) struct SectionLayout {
+    /// Sections moved away from their own address. Sections not listed keep
+    /// it, which is what non-allocated ELF sections (the DWARF itself) need:
+    /// references into them are offsets from 0.
+    bases: HashMap<SectionIndex, u64>,
+    machine: Machine,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+enum Machine {
+    #[default]
+    Other,
+    Riscv,
+    LoongArch,
+}
+
+impl Machine {
+    fn from_arch_name(arch_name: &str) -> Self {
+        match arch_name {
+            "riscv32" | "riscv64" => Self::Riscv,
+            "loongarch32" | "loongarch64" => Self::LoongArch,
+            _ => Self::Other,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Other => "ELF",
+            Self::Riscv => "RISC-V",
+            Self::LoongArch => "LoongArch",
+        }
+    }
+
+    /// What relocation type `r_type` does to a DWARF section, or `None` when
+    /// it is not one atosl knows how to apply. Only meaningful for RISC-V and
+    /// LoongArch; other machines go through `RelocationKind::Absolute`.
+    fn elf_action(self, r_type: u32) -> Option<Action> {
+        use Field::{Bytes, Low6, Uleb128};
+        Some(match (self, r_type) {
+            // Markers for the linker's relaxation pass, and TLS offsets
+            // (DW_OP_form_tls_address operands) that are not addresses.
+            (Self::Riscv, elf::R_RISCV_RELAX | R_RISCV_ALIGN | R_TLS_DTPREL32 | R_TLS_DTPREL64)
+            | (Self::LoongArch, R_LARCH_RELAX | R_LARCH_ALIGN | R_TLS_DTPREL32 | R_TLS_DTPREL64) => {
+                Action::Ignore
+            }
+
+            (Self::Riscv, elf::R_RISCV_ADD8) => Action::Add(Bytes(1)),
+            (Self::Riscv, elf::R_RISCV_ADD16) => Action::Add(Bytes(2)),
+            (Self::Riscv, elf::R_RISCV_ADD32) => Action::Add(Bytes(4)),
+            (Self::Riscv, elf::R_RISCV_ADD64) => Action::Add(Bytes(8)),
+            (Self::Riscv, elf::R_RISCV_SUB6) => Action::Sub(Low6),
+            (Self::Riscv, elf::R_RISCV_SUB8) => Action::Sub(Bytes(1)),
+            (Self::Riscv, elf::R_RISCV_SUB16) => Action::Sub(Bytes(2)),
+            (Self::Riscv, elf::R_RISCV_SUB32) => Action::Sub(Bytes(4)),
+            (Self::Riscv, elf::R_RISCV_SUB64) => Action::Sub(Bytes(8)),
+            (Self::Riscv, elf::R_RISCV_SET6) => Action::Set(Low6),
+            (Self::Riscv, elf::R_RISCV_SET8) => Action::Set(Bytes(1)),
+            (Self::Riscv, elf::R_RISCV_SET16) => Action::Set(Bytes(2)),
+            (Self::Riscv, elf::R_RISCV_SET32) => Action::Set(Bytes(4)),
+            (Self::Riscv, R_RISCV_SET_ULEB128) => Action::Set(Uleb128),
+            (Self::Riscv, R_RISCV_SUB_ULEB128) => Action::Sub(Uleb128),
+
+            (Self::LoongArch, R_LARCH_32) => Action::Set(Bytes(4)),
+            (Self::LoongArch, R_LARCH_64) => Action::Set(Bytes(8)),
+            (Self::LoongArch, R_LARCH_ADD6) => Action::Add(Low6),
+            (Self::LoongArch, R_LARCH_ADD8) => Action::Add(Bytes(1)),
+            (Self::LoongArch, R_LARCH_ADD16) => Action::Add(Bytes(2)),
+            (Self::LoongArch, R_LARCH_ADD24) => Action::Add(Bytes(3)),
+            (Self::LoongArch, R_LARCH_ADD32) => Action::Add(Bytes(4)),
+            (Self::LoongArch, R_LARCH_ADD64) => Action::Add(Bytes(8)),
+            (Self::LoongArch, R_LARCH_SUB6) => Action::Sub(Low6),
+            (Self::LoongArch, R_LARCH_SUB8) => Action::Sub(Bytes(1)),
+            (Self::LoongArch, R_LARCH_SUB16) => Action::Sub(Bytes(2)),
+            (Self::LoongArch, R_LARCH_SUB24) => Action::Sub(Bytes(3)),
+            (Self::LoongArch, R_LARCH_SUB32) => Action::Sub(Bytes(4)),
+            (Self::LoongArch, R_LARCH_SUB64) => Action::Sub(Bytes(8)),
+            (Self::LoongArch, R_LARCH_ADD_ULEB128) => Action::Add(Uleb128),
+            (Self::LoongArch, R_LARCH_SUB_ULEB128) => Action::Sub(Uleb128),
+            _ => return None,
+        })
+    }
+}
+
+// Relocation types `object` 0.28 has no constants for. The TLS ones share
+// their numbers between RISC-V and LoongArch.
+const R_TLS_DTPREL32: u32 = 8;
+const R_TLS_DTPREL64: u32 = 9;
+const R_RISCV_ALIGN: u32 = 43;
+const R_RISCV_SET_ULEB128: u32 = 60;
+const R_RISCV_SUB_ULEB128: u32 = 61;
+const R_LARCH_32: u32 = 1;
+const R_LARCH_64: u32 = 2;
+const R_LARCH_ADD8: u32 = 47;
+const R_LARCH_ADD16: u32 = 48;
+const R_LARCH_ADD24: u32 = 49;
+const R_LARCH_ADD32: u32 = 50;
+const R_LARCH_ADD64: u32 = 51;
+const R_LARCH_SUB8: u32 = 52;
+const R_LARCH_SUB16: u32 = 53;
+const R_LARCH_SUB24: u32 = 54;
+const R_LARCH_SUB32: u32 = 55;
+const R_LARCH_SUB64: u32 = 56;
+const R_LARCH_RELAX: u32 = 100;
+const R_LARCH_ALIGN: u32 = 102;
+const R_LARCH_ADD6: u32 = 105;
+const R_LARCH_SUB6: u32 = 106;
+const R_LARCH_ADD_ULEB128: u32 = 107;
+const R_LARCH_SUB_ULEB128: u32 = 108;
+
+/// How a relocation combines its value `S + A` with the field in place.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Action {
+    Set(Field),
+    Add(Field),
+    Sub(Field),
+    Ignore,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Bytes(usize),
+    /// The low six bits of a byte (DW_CFA_advance_loc).
+    Low6,
+    /// A ULEB128 that keeps its encoded length.
+    Uleb128,
+}
+
+impl SectionLayout {
+    /// Keeps every section at its own address. Mach-O objects are already laid
+    /// out this way.
+    pub fn native() -> Self {
+        Self::default()
+    }
+
+    /// Places the allocated sections of an ELF object one after another,
+    /// honouring their alignment; other formats keep their own layout.
+    /// `arch_name` picks the relocation types to decode.
+    pub fn for_object<'data>(object: &object::File<'data, &'data [u8]>, arch_name: &str) -> Self {
+        if object.format() != object::BinaryFormat::Elf {
+            return Self::native();
+        }
+        let mut bases = HashMap::new();
+        let mut cursor = 0u64;
+        for section in object.sections() {
+            let SectionFlags::Elf { sh_flags } = section.flags() else {
+                continue;
+            };
+            if sh_flags & u64::from(elf::SHF_ALLOC) == 0 || section.size() == 0 {
+                continue;
+            }
+            let align = section.align().max(1);
+            cursor = cursor.div_ceil(align) * align;
+            bases.insert(section.index(), cursor);
+            cursor += section.size();
+        }
+        Self {
+            bases,
+            machine: Machine::from_arch_name(arch_name),
+        }
+    }
+
+    pub fn section_address<'data>(
+        &self,
+        object: &object::File<'data, &'data [u8]>,
+        index: SectionIndex,
+    ) -> Option<u64> {
+        match self.bases.get(&index) {
+            Some(base) => Some(*base),
+            None => object
+                .section_by_index(index)
+                .ok()
+                .map(|section| section.address()),
+        }
+    }
+
+    pub fn symbol_address<'data>(
+        &self,
+        object: &object::File<'data, &'data [u8]>,
+        symbol: &object::Symbol<'data, '_>,
+    ) -> u64 {
+        let Some(index) = symbol.section_index() else {
+            return symbol.address();
+        };
+        match (self.bases.get(&index), object.section_by_index(index).ok()) {
+            (Some(base), Some(section)) => base + (symbol.address() - section.address()),
+            _ => symbol.address(),
+        }
+    }
+}
+
+/// Returns the (uncompressed) contents of `section` with its relocations
+/// applied for `layout`. A RISC-V or LoongArch relocation type atosl cannot
+/// apply is an error rather than a silently wrong value; other machines'
+/// non-absolute relocations do not occur in DWARF and are left alone.
+pub(crate) fn relocated_section_data<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    section: &object::Section<'data, '_, &'data [u8]>,
+    layout: &SectionLayout,
+) -> Result<Cow<'data, [u8]>> {
+    let mut data = section.uncompressed_data()?;
+    let little_endian = object.is_little_endian();
+    for (offset, relocation) in section.relocations() {
+        let action = match relocation.kind() {
+            RelocationKind::Absolute => {
+                let field = match relocation.size() {
+                    32 => Field::Bytes(4),
+                    64 => Field::Bytes(8),
+                    _ => continue,
+                };
+                if relocation.has_implicit_addend() {
+                    Action::Add(field)
+                } else {
+                    Action::Set(field)
+                }
+            }
+            RelocationKind::Elf(r_type) if layout.machine != Machine::Other => {
+                match layout.machine.elf_action(r_type) {
+                    Some(action) => action,
+                    None => bail!(
+                        "unsupported {} relocation type {r_type} at {}+{offset:#x}",
+                        layout.machine.name(),
+                        section.name().unwrap_or("?"),
+                    ),
+                }
+            }
+            _ => continue,
+        };
+        if action == Action::Ignore {
+            continue;
+        }
+
+        let target = match relocation.target() {
+            RelocationTarget::Symbol(index) => {
+                let Ok(symbol) = object.symbol_by_index(index) else {
+                    continue;
+                };
+                if symbol.is_undefined() {
+                    continue;
+                }
+                layout.symbol_address(object, &symbol)
+            }
+            // Mach-O section relocations already store the full address in
+            // place; only a moved section adds anything.
+            RelocationTarget::Section(index) => {
+                let Ok(section) = object.section_by_index(index) else {
+                    continue;
+                };
+                let Some(address) = layout.section_address(object, index) else {
+                    continue;
+                };
+                address.wrapping_sub(section.address())
+            }
+            _ => continue,
+        };
+        let Ok(start) = usize::try_from(offset) else {
+            continue;
+        };
+        let value = target.wrapping_add_signed(relocation.addend());
+        apply(action, little_endian, data.to_mut(), start, value);
+    }
+    Ok(data)
+}
+
+/// Applies `action` with `value` to the field at `start`. Fields that run
+/// past the end of the section are left alone.
+fn apply(action: Action, little_endian: bool, data: &mut [u8], start: usize, value: u64) {
+    let combine = |current: u64| match action {
+        Action::Set(_) => value,
+        Action::Add(_) => current.wrapping_add(value),
+        Action::Sub(_) => current.wrapping_sub(value),
+        Action::Ignore => current,
+    };
+    let (Action::Set(field) | Action::Add(field) | Action::Sub(field)) = action else {
+        return;
+    };
+    match field {
+        Field::Bytes(width) => {
+            let Some(bytes) = data.get_mut(start..start.saturating_add(width)) else {
+                return;
+            };
+            let value = combine(read_value(little_endian, bytes));
+            write_value(little_endian, bytes, value);
+        }
+        Field::Low6 => {
+            let Some(byte) = data.get_mut(start) else {
+                return;
+            };
+            let value = combine(u64::from(*byte & 0x3f));
+            *byte = (*byte & 0xc0) | (value as u8 & 0x3f);
+        }
+        Field::Uleb128 => {
+            let Some(bytes) = data.get_mut(start..) else {
+                return;
+            };
+            let Some(length) = bytes.iter().position(|byte| byte & 0x80 == 0) else {
+                return;
+            };
+            let bytes = &mut bytes[..=length];
+            let current = bytes
+                .iter()
+                .enumerate()
+                .take(10)
+                .fold(0u64, |sum, (i, byte)| {
+                    sum | u64::from(byte & 0x7f) << (7 * i)
+                });
+            let mut value = combine(current);
+            let last = bytes.len() - 1;
+            for (i, byte) in bytes.iter_mut().enumerate() {
+                *byte = (value & 0x7f) as u8 | if i == last { 0 } else { 0x80 };
+                value >>= 7;
+            }
+        }
+    }
+}
+
+fn read_value(little_endian: bool, bytes: &[u8]) -> u64 {
+    let mut buffer = [0u8; 8];
+    if little_endian {
+        buffer[..bytes.len()].copy_from_slice(bytes);
+        u64::from_le_bytes(buffer)
+    } else {
+        buffer[8 - bytes.len()..].copy_from_slice(bytes);
+        u64::from_be_bytes(buffer)
+    }
+}
+
+fn write_value(little_endian: bool, bytes: &mut [u8], value: u64) {
+    let width = bytes.len();
+    if little_endian {
+        bytes.copy_from_slice(&value.to_le_bytes()[..width]);
+    } else {
+        bytes.copy_from_slice(&value.to_be_bytes()[8 - width..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_and_writes_relocated_values_in_both_byte_orders() {
+        let mut little = [0x10, 0, 0, 0];
+        let value = read_value(true, &little) + 0x2000;
+        write_value(true, &mut little, value);
+        assert_eq!(little, [0x10, 0x20, 0, 0]);
+
+        let mut big = [0, 0, 0, 0, 0, 0, 0, 0x10];
+        let value = read_value(false, &big) + 0x2000;
+        write_value(false, &mut big, value);
+        assert_eq!(big, [0, 0, 0, 0, 0, 0, 0x20, 0x10]);
+    }
+
+    #[test]
+    fn applies_riscv_and_loongarch_label_differences() {
+        // A function length `end - start` as an ADD32/SUB32 pair.
+        let add = Machine::Riscv.elf_action(elf::R_RISCV_ADD32).unwrap();
+        let sub = Machine::Riscv.elf_action(elf::R_RISCV_SUB32).unwrap();
+        let mut data = [0u8; 4];
+        apply(add, true, &mut data, 0, 0x1034);
+        apply(sub, true, &mut data, 0, 0x1000);
+        assert_eq!(data, [0x34, 0, 0, 0]);
+
+        // DW_CFA_advance_loc keeps its opcode bits.
+        let add = Machine::LoongArch.elf_action(R_LARCH_ADD6).unwrap();
+        let sub = Machine::LoongArch.elf_action(R_LARCH_SUB6).unwrap();
+        let mut data = [0x40];
+        apply(add, true, &mut data, 0, 0x118);
+        apply(sub, true, &mut data, 0, 0x100);
+        assert_eq!(data, [0x58]);
+
+        // A ULEB128 keeps the length the assembler reserved for it.
+        let set = Machine::Riscv.elf_action(R_RISCV_SET_ULEB128).unwrap();
+        let sub = Machine::Riscv.elf_action(R_RISCV_SUB_ULEB128).unwrap();
+        let mut data = [0x80, 0x00, 0xff];
+        apply(set, true, &mut data, 0, 0x2000 + 200);
+        apply(sub, true, &mut data, 0, 0x2000);
+        assert_eq!(data, [0xc8, 0x01, 0xff]);
+
+        assert_eq!(
+            Machine::LoongArch.elf_action(R_LARCH_64),
+            Some(Action::Set(Field::Bytes(8)))
+        );
+        assert_eq!(
+            Machine::Riscv.elf_action(elf::R_RISCV_RELAX),
+            Some(Action::Ignore)
+        );
+        assert_eq!(Machine::Riscv.elf_action(elf::R_RISCV_CALL), None);
+    }
+}
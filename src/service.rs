@@ -0,0 +1,518 @@
+//! An HTTP service that symbolizes crash frames against the symbols under a
+//! set of directories.
+//!
+//! [`SymbolService`] finds each module of a request by its UUID or build-id,
+//! the way `-o <dir> --uuid` does (reading the directory's index when it has
+//! one), and keeps the parsed object and DWARF context of recently used
+//! modules loaded between requests. `POST /symbolicate` takes a
+//! [`SymbolicateRequest`] and answers with a [`SymbolicateResponse`];
+//! `GET /health` and `GET /metrics` report on the service itself.
+use crate::atosl::{
+    parse_address_token, InputAddress, SymbolizeOptions, SymbolizeOutcome, SymbolizeReport,
+};
+use crate::http;
+use crate::locate::{self, DirectoryLocator, ModuleIdentity, SymbolLocator};
+use crate::object_cache::{LoadedObject, ObjectCache};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{self, Read};
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Largest request body accepted, in bytes.
+const MAX_REQUEST_BYTES: u64 = 16 << 20;
+
+/// How long a module that failed to load is answered with the same error,
+/// rather than searched for again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How the service finds symbols and how much work it takes on.
+#[derive(Clone, Debug)]
+pub struct ServiceOptions {
+    /// Directories searched for the binaries, debug files and dSYMs of a
+    /// module, in order.
+    pub symbol_dirs: Vec<PathBuf>,
+    /// Requests handled at once; more wait for a free worker. This is the
+    /// only limit: requests for the same module queue behind each other on
+    /// that module's one symbolizer thread, each holding its worker meanwhile.
+    pub workers: usize,
+    /// Modules kept loaded between requests; the least recently used one is
+    /// dropped to make room.
+    pub cache_size: usize,
+    /// Never ask the debuginfod servers in `DEBUGINFOD_URLS`.
+    pub offline: bool,
+    /// Log every request.
+    pub verbose: bool,
+}
+
+impl Default for ServiceOptions {
+    fn default() -> Self {
+        Self {
+            symbol_dirs: Vec::new(),
+            workers: thread::available_parallelism().map_or(1, usize::from),
+            cache_size: 64,
+            offline: false,
+            verbose: false,
+        }
+    }
+}
+
+/// The body of `POST /symbolicate`.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SymbolicateRequest {
+    pub modules: Vec<ModuleRequest>,
+    /// Every frame except those written `pc:0x…` is a return address, looked
+    /// up at the instruction before it (`--frames-are-return-addresses`).
+    #[serde(default)]
+    pub return_addresses: bool,
+}
+
+/// One loaded image and the frames that fell in it.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ModuleRequest {
+    /// Mach-O UUID or ELF build-id, with or without dashes.
+    pub id: String,
+    /// Where the image was loaded, as a number or a `0x…` string.
+    pub load_address: Address,
+    /// Slice of a universal binary, such as `arm64`.
+    #[serde(default)]
+    pub arch: Option<String>,
+    /// Addresses as numbers or strings, the crashing PC written `pc:0x…`.
+    pub frames: Vec<Address>,
+}
+
+/// An address written as a JSON number or as a string the CLI accepts.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum Address {
+    Number(u64),
+    Text(String),
+}
+
+impl Default for Address {
+    fn default() -> Self {
+        Address::Number(0)
+    }
+}
+
+impl Address {
+    pub(crate) fn parse(&self) -> Result<InputAddress, String> {
+        match self {
+            Address::Number(address) => Ok(InputAddress {
+                address: *address,
+                exact_pc: false,
+            }),
+            // The frame reports address 0, so the error names what was sent.
+            Address::Text(text) => parse_address_token(text.trim()).map_err(|_| {
+                format!(
+                    "invalid address {text:?}: expected a decimal or 0x hex address, \
+optionally written pc:0x…"
+                )
+            }),
+        }
+    }
+}
+
+/// The answer to a [`SymbolicateRequest`], a report per module in request
+/// order.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize)]
+pub struct SymbolicateResponse {
+    pub modules: Vec<ModuleReport>,
+}
+
+/// The frames of one module, or why its symbols could not be loaded.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ModuleReport {
+    pub id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(flatten)]
+    pub report: Option<SymbolizeReport>,
+}
+
+/// The answer to one HTTP request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ServiceReply {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl ServiceReply {
+    fn json(status: u16, body: &impl Serialize) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_string(body).expect("serializable reply"),
+        }
+    }
+
+    fn error(status: u16, error: impl Into<String>) -> Self {
+        Self::json(status, &serde_json::json!({ "error": error.into() }))
+    }
+}
+
+/// Symbolizes crash frames over HTTP, keeping recently used modules loaded.
+pub struct SymbolService {
+    options: ServiceOptions,
+    locators: Vec<Arc<dyn SymbolLocator>>,
+    cache: ObjectCache<ModuleKey>,
+    // Modules that failed to load, with when and why. Crash reports name
+    // system libraries nobody uploaded, and each miss costs a directory walk.
+    failures: Mutex<HashMap<ModuleKey, (Instant, String)>>,
+    metrics: Metrics,
+}
+
+#[derive(Default)]
+struct Metrics {
+    requests: AtomicU64,
+    request_errors: AtomicU64,
+    in_flight: AtomicU64,
+    frames: AtomicU64,
+    frames_resolved: AtomicU64,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct ModuleKey {
+    id: String,
+    arch: Option<String>,
+}
+
+impl SymbolService {
+    pub fn new(options: ServiceOptions) -> Self {
+        let mut locators = options
+            .symbol_dirs
+            .iter()
+            .map(|root| Arc::new(DirectoryLocator { root: root.clone() }) as Arc<dyn SymbolLocator>)
+            .collect::<Vec<_>>();
+        locators.extend(locate::debuginfod_locator(options.offline));
+        Self {
+            cache: ObjectCache::new(options.cache_size),
+            options,
+            locators,
+            failures: Mutex::new(HashMap::new()),
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Symbolizes every frame of `request`. A module whose symbols cannot be
+    /// found or loaded gets an `error` instead of frames.
+    pub fn symbolicate(&self, request: &SymbolicateRequest) -> SymbolicateResponse {
+        let modules = request
+            .modules
+            .iter()
+            .map(|module| {
+                let report = self.symbolicate_module(module, request.return_addresses);
+                if let Ok(report) = &report {
+                    let resolved = report
+                        .frames
+                        .iter()
+                        .filter(|frame| matches!(frame, SymbolizeOutcome::Resolved(_)))
+                        .count();
+                    self.metrics
+                        .frames_resolved
+                        .fetch_add(resolved as u64, Ordering::Relaxed);
+                }
+                self.metrics
+                    .frames
+                    .fetch_add(module.frames.len() as u64, Ordering::Relaxed);
+                match report {
+                    Ok(report) => ModuleReport {
+                        id: module.id.clone(),
+                        error: None,
+                        report: Some(report),
+                    },
+                    Err(error) => ModuleReport {
+                        id: module.id.clone(),
+                        error: Some(error),
+                        report: None,
+                    },
+                }
+            })
+            .collect();
+        SymbolicateResponse { modules }
+    }
+
+    fn symbolicate_module(
+        &self,
+        module: &ModuleRequest,
+        return_addresses: bool,
+    ) -> Result<SymbolizeReport, String> {
+        let load_address = match module.load_address.parse()? {
+            InputAddress { exact_pc: true, .. } => {
+                return Err("a load address cannot be marked pc:".to_string())
+            }
+            input => input.address,
+        };
+        let key = ModuleKey {
+            id: locate::normalize_hex_id(&module.id),
+            arch: module.arch.clone(),
+        };
+        let object = self.open(&key)?;
+        let info = object.info()?;
+        let frames = module.frames.iter().map(Address::parse).collect::<Vec<_>>();
+        let frames = object.run(move |symbolizer| {
+            frames
+                .into_iter()
+                .map(|frame| match frame {
+                    // The symbolizer treats every frame as a return address; a
+                    // request that does not is all exact PCs.
+                    Ok(input) => symbolizer.symbolize(
+                        load_address,
+                        input.address,
+                        false,
+                        input.exact_pc || !return_addresses,
+                    ),
+                    Err(error) => SymbolizeOutcome::Unresolved {
+                        requested_address: 0,
+                        canonical_address: None,
+                        error,
+                        selector: None,
+                    },
+                })
+                .collect()
+        })?;
+        Ok(SymbolizeReport {
+            object_path: info.object_path.clone(),
+            object_name: info.object_name.clone(),
+            selected_slice: info.selected_slice.clone(),
+            frames,
+        })
+    }
+
+    fn open(&self, key: &ModuleKey) -> Result<Arc<LoadedObject>, String> {
+        if let Some((failed_at, error)) = self.failures.lock().expect("failures lock").get(key) {
+            if failed_at.elapsed() < RETRY_INTERVAL {
+                return Err(error.clone());
+            }
+        }
+        let object = self.cache.open(key, || self.load(key));
+        let mut failures = self.failures.lock().expect("failures lock");
+        match &object {
+            Ok(_) => {
+                failures.remove(key);
+            }
+            Err(error) => {
+                failures.retain(|_, (failed_at, _)| failed_at.elapsed() < RETRY_INTERVAL);
+                failures.insert(key.clone(), (Instant::now(), error.clone()));
+            }
+        }
+        object
+    }
+
+    fn load(&self, key: &ModuleKey) -> LoadedObject {
+        let locators = self.locators.clone();
+        let symbol_dirs = self.options.symbol_dirs.clone();
+        let offline = self.options.offline;
+        let verbose = self.options.verbose;
+        let key = key.clone();
+        LoadedObject::spawn(move || {
+            Ok(SymbolizeOptions {
+                object_path: find_module(&locators, &key.id, verbose)?,
+                uuid: Some(key.id),
+                arch: key.arch,
+                return_addresses: true,
+                debug_dirs: symbol_dirs.clone(),
+                dsym_dirs: symbol_dirs,
+                offline,
+                verbose,
+                ..SymbolizeOptions::default()
+            })
+        })
+    }
+
+    /// Answers one HTTP request.
+    pub fn reply(&self, method: &str, url: &str, body: &[u8]) -> ServiceReply {
+        let path = url.split(['?', '#']).next().unwrap_or_default();
+        match (method, path) {
+            ("POST", "/symbolicate") => match serde_json::from_slice::<SymbolicateRequest>(body) {
+                Ok(request) => ServiceReply::json(200, &self.symbolicate(&request)),
+                Err(err) => ServiceReply::error(400, format!("invalid request: {err}")),
+            },
+            ("GET", "/health") => ServiceReply::json(
+                200,
+                &serde_json::json!({
+                    "status": "ok",
+                    "modules_cached": self.cached_modules(),
+                }),
+            ),
+            ("GET", "/metrics") => ServiceReply {
+                status: 200,
+                content_type: "text/plain; version=0.0.4",
+                body: self.metrics_text(),
+            },
+            (_, "/symbolicate" | "/health" | "/metrics") => {
+                ServiceReply::error(405, format!("{method} is not allowed on {path}"))
+            }
+            _ => ServiceReply::error(404, format!("no such endpoint: {path}")),
+        }
+    }
+
+    fn cached_modules(&self) -> usize {
+        self.cache.len()
+    }
+
+    // Prometheus text exposition format.
+    fn metrics_text(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let metrics = [
+            (
+                "atosl_requests_total",
+                "counter",
+                "HTTP requests answered.",
+                load(&self.metrics.requests),
+            ),
+            (
+                "atosl_request_errors_total",
+                "counter",
+                "HTTP requests answered with an error status.",
+                load(&self.metrics.request_errors),
+            ),
+            (
+                "atosl_requests_in_flight",
+                "gauge",
+                "HTTP requests being answered.",
+                load(&self.metrics.in_flight),
+            ),
+            (
+                "atosl_frames_total",
+                "counter",
+                "Frames symbolized.",
+                load(&self.metrics.frames),
+            ),
+            (
+                "atosl_frames_resolved_total",
+                "counter",
+                "Frames resolved to a symbol.",
+                load(&self.metrics.frames_resolved),
+            ),
+            (
+                "atosl_module_cache_hits_total",
+                "counter",
+                "Module lookups answered by a loaded module.",
+                self.cache.hits(),
+            ),
+            (
+                "atosl_module_cache_misses_total",
+                "counter",
+                "Module lookups that had to load the module.",
+                self.cache.misses(),
+            ),
+            (
+                "atosl_modules_cached",
+                "gauge",
+                "Modules kept loaded.",
+                self.cached_modules() as u64,
+            ),
+        ];
+        metrics
+            .iter()
+            .map(|(name, kind, help, value)| {
+                format!("# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n")
+            })
+            .collect()
+    }
+
+    /// Answers HTTP requests on `listener` until it fails, on
+    /// [`ServiceOptions::workers`] threads.
+    pub fn serve(&self, listener: TcpListener) -> Result<()> {
+        http::serve_with_workers(listener, self.options.workers, |request| {
+            self.answer(request)
+        })
+    }
+
+    fn answer(&self, mut request: tiny_http::Request) -> io::Result<()> {
+        self.metrics.in_flight.fetch_add(1, Ordering::Relaxed);
+        let mut body = Vec::new();
+        let read = request
+            .as_reader()
+            .take(MAX_REQUEST_BYTES + 1)
+            .read_to_end(&mut body);
+        let method = request.method().to_string();
+        let reply = match read {
+            Err(err) => ServiceReply::error(400, format!("failed to read the request: {err}")),
+            Ok(_) if body.len() as u64 > MAX_REQUEST_BYTES => ServiceReply::error(
+                413,
+                format!("requests are limited to {MAX_REQUEST_BYTES} bytes"),
+            ),
+            Ok(_) => self.reply(&method, request.url(), &body),
+        };
+        if self.options.verbose {
+            eprintln!("{} {} {}", reply.status, method, request.url());
+        }
+        self.metrics.requests.fetch_add(1, Ordering::Relaxed);
+        if reply.status >= 400 {
+            self.metrics.request_errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let header = tiny_http::Header::from_bytes(&b"Content-Type"[..], reply.content_type)
+            .expect("valid header");
+        let response = tiny_http::Response::from_string(reply.body)
+            .with_status_code(reply.status)
+            .with_header(header);
+        let result = request.respond(response);
+        self.metrics.in_flight.fetch_sub(1, Ordering::Relaxed);
+        result
+    }
+}
+
+fn find_module(locators: &[Arc<dyn SymbolLocator>], id: &str, verbose: bool) -> Result<PathBuf> {
+    let module = ModuleIdentity::from_id(id)?;
+    locate::find_symbols(locators, &module, verbose)
+        .ok_or_else(|| anyhow!("no binary or dSYM matched uuid {id}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_health_metrics_and_bad_requests() {
+        let symbols = tempfile::tempdir().unwrap();
+        let service = SymbolService::new(ServiceOptions {
+            symbol_dirs: vec![symbols.path().to_path_buf()],
+            offline: true,
+            ..ServiceOptions::default()
+        });
+
+        assert_eq!(service.reply("GET", "/health", b"").status, 200);
+        assert_eq!(service.reply("GET", "/symbolicate", b"").status, 405);
+        assert_eq!(service.reply("GET", "/nope", b"").status, 404);
+        assert_eq!(service.reply("POST", "/symbolicate", b"{").status, 400);
+
+        let missing = service.reply(
+            "POST",
+            "/symbolicate",
+            br#"{"modules":[{"id":"0011","load_address":"0x1000","frames":[4097]}]}"#,
+        );
+        assert_eq!(missing.status, 200);
+        let body = serde_json::from_str::<serde_json::Value>(&missing.body).unwrap();
+        assert_eq!(body["modules"][0]["id"], "0011");
+        assert!(body["modules"][0]["error"]
+            .as_str()
+            .unwrap()
+            .contains("no binary or dSYM matched"));
+        // A module that failed to load is not kept, and for a while asking
+        // for it again answers the same error without another search.
+        assert_eq!(service.cached_modules(), 0);
+        let again = service.reply(
+            "POST",
+            "/symbolicate",
+            br#"{"modules":[{"id":"0011","load_address":"0x1000","frames":[4097]}]}"#,
+        );
+        assert_eq!(again.body, missing.body);
+
+        let metrics = service.reply("GET", "/metrics", b"").body;
+        assert!(metrics.contains("atosl_frames_total 2\n"), "{metrics}");
+        assert!(
+            metrics.contains("atosl_module_cache_misses_total 1\n"),
+            "{metrics}"
+        );
+    }
+}
@@ -0,0 +1,291 @@
+// `--server-stdio`: answer symbolization requests read line by line from stdin
+// for as long as it stays open, the way sanitizer runtimes, fuzzers and IDE
+// plugins drive llvm-symbolizer.
+//
+// A line is `[CODE|DATA] [path] address`, answered in llvm-symbolizer's
+// format (a function and `file:line:column` per inline frame, or a name and
+// `start size`, then an empty line), or a JSON object answered with a
+// `SymbolizeReport` on one line. Objects stay loaded between requests, keyed by
+// path, arch and UUID, and every answer is flushed as soon as it is written.
+use crate::atosl::{
+    parse_address_token, InputAddress, SourceLocation, SymbolizeOptions, SymbolizeOutcome,
+    SymbolizeReport,
+};
+use crate::object_cache::{LoadedObject, ObjectCache};
+use crate::service::Address;
+use anyhow::{Context as _, Result};
+use serde::Deserialize;
+use std::fmt::Write as _;
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Objects kept loaded; the least recently used one is dropped beyond this.
+const CACHE_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    Code,
+    Data,
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct TextRequest {
+    kind: Kind,
+    object: Option<PathBuf>,
+    address: InputAddress,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonRequest {
+    #[serde(default)]
+    object: Option<PathBuf>,
+    #[serde(default)]
+    arch: Option<String>,
+    #[serde(default)]
+    uuid: Option<String>,
+    #[serde(default)]
+    load_address: Option<Address>,
+    addresses: Vec<Address>,
+}
+
+type ObjectKey = (PathBuf, Option<String>, Option<String>);
+
+pub(crate) fn run(options: &SymbolizeOptions) -> Result<i32> {
+    let cache = ObjectCache::new(CACHE_SIZE);
+    let mut stdout = io::stdout().lock();
+    for line in io::stdin().lock().lines() {
+        let line = line.context("failed to read a request")?;
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let answer = match line.starts_with('{') {
+            true => answer_json(&cache, options, line),
+            false => answer_text(&cache, options, line),
+        };
+        stdout
+            .write_all(answer.as_bytes())
+            .and_then(|()| stdout.flush())
+            .context("failed to write an answer")?;
+    }
+    Ok(0)
+}
+
+fn answer_text(cache: &ObjectCache<ObjectKey>, options: &SymbolizeOptions, line: &str) -> String {
+    let answer = parse_text_request(line).and_then(|request| {
+        let object = open(cache, options, request.object, None, None)?;
+        let InputAddress { address, exact_pc } = request.address;
+        let load_address = options.load_address;
+        match request.kind {
+            Kind::Code => object
+                .run(move |symbolizer| symbolizer.symbolize(load_address, address, false, exact_pc))
+                .map(|outcome| format_code(&outcome)),
+            Kind::Data => object
+                .run(move |symbolizer| symbolizer.symbolize_data(load_address, address))
+                .map(|symbol| match symbol {
+                    Ok(symbol) => format!("{}\n{} {}\n\n", symbol.name, symbol.start, symbol.size),
+                    Err(_) => UNKNOWN_DATA.to_string(),
+                }),
+        }
+    });
+    match answer {
+        Ok(answer) => answer,
+        Err(error) => {
+            // llvm-symbolizer reports the error aside and answers "unknown", so
+            // a client waiting for its answer never hangs.
+            eprintln!("atosl: {error}");
+            match line.starts_with("DATA") {
+                true => UNKNOWN_DATA.to_string(),
+                false => UNKNOWN_CODE.to_string(),
+            }
+        }
+    }
+}
+
+const UNKNOWN_CODE: &str = "??\n??:0:0\n\n";
+const UNKNOWN_DATA: &str = "??\n0 0\n\n";
+
+fn answer_json(cache: &ObjectCache<ObjectKey>, options: &SymbolizeOptions, line: &str) -> String {
+    let report = serde_json::from_str::<JsonRequest>(line)
+        .map_err(|err| format!("invalid request: {err}"))
+        .and_then(|request| symbolize_json(cache, options, request));
+    let mut answer = match report {
+        Ok(report) => serde_json::to_string(&report),
+        Err(error) => serde_json::to_string(&serde_json::json!({ "error": error })),
+    }
+    .expect("serializable answer");
+    answer.push('\n');
+    answer
+}
+
+fn symbolize_json(
+    cache: &ObjectCache<ObjectKey>,
+    options: &SymbolizeOptions,
+    request: JsonRequest,
+) -> Result<SymbolizeReport, String> {
+    let load_address = match &request.load_address {
+        Some(address) => address.parse()?.address,
+        None => options.load_address,
+    };
+    let object = open(cache, options, request.object, request.arch, request.uuid)?;
+    let info = object.info()?;
+    let addresses = request
+        .addresses
+        .iter()
+        .map(Address::parse)
+        .collect::<Vec<_>>();
+    let frames = object.run(move |symbolizer| {
+        addresses
+            .into_iter()
+            .map(|address| match address {
+                Ok(input) => {
+                    symbolizer.symbolize(load_address, input.address, false, input.exact_pc)
+                }
+                Err(error) => SymbolizeOutcome::Unresolved {
+                    requested_address: 0,
+                    canonical_address: None,
+                    error,
+                    selector: None,
+                },
+            })
+            .collect()
+    })?;
+    Ok(SymbolizeReport {
+        object_path: info.object_path.clone(),
+        object_name: info.object_name.clone(),
+        selected_slice: info.selected_slice.clone(),
+        frames,
+    })
+}
+
+// The loaded object a request names, or the `-o` object when it names none.
+// Everything else about how to load it comes from the command line.
+fn open(
+    cache: &ObjectCache<ObjectKey>,
+    options: &SymbolizeOptions,
+    object: Option<PathBuf>,
+    arch: Option<String>,
+    uuid: Option<String>,
+) -> Result<Arc<LoadedObject>, String> {
+    let object_path = object
+        .filter(|path| !path.as_os_str().is_empty())
+        .or_else(|| Some(options.object_path.clone()).filter(|path| !path.as_os_str().is_empty()))
+        .ok_or_else(|| "no object named; name one in the request or pass -o".to_string())?;
+    let key = (
+        object_path,
+        arch.or_else(|| options.arch.clone()),
+        uuid.or_else(|| options.uuid.clone()),
+    );
+    cache.open(&key, || {
+        let options = SymbolizeOptions {
+            object_path: key.0.clone(),
+            arch: key.1.clone(),
+            uuid: key.2.clone(),
+            addresses: Vec::new(),
+            exact_pc_frames: Vec::new(),
+            // Like llvm-symbolizer's, addresses are offsets from where the
+            // module is loaded, which for ELF is its lowest segment.
+            module_base: true,
+            server_stdio: false,
+            ..options.clone()
+        };
+        LoadedObject::spawn(move || Ok(options))
+    })
+}
+
+// `[CODE|DATA] [path] address`, with a path that has spaces in double quotes.
+fn parse_text_request(line: &str) -> Result<TextRequest, String> {
+    let (kind, rest) = match line.split_once(char::is_whitespace) {
+        Some(("CODE", rest)) => (Kind::Code, rest.trim_start()),
+        Some(("DATA", rest)) => (Kind::Data, rest.trim_start()),
+        _ => (Kind::Code, line),
+    };
+    let (object, address) = match rest.strip_prefix('"') {
+        Some(quoted) => {
+            let (path, address) = quoted
+                .split_once('"')
+                .ok_or_else(|| format!("unterminated quote in '{line}'"))?;
+            (Some(path), address.trim())
+        }
+        None => match rest.rsplit_once(char::is_whitespace) {
+            Some((path, address)) => (Some(path.trim()), address),
+            None => (None, rest),
+        },
+    };
+    if address.is_empty() || address.contains(char::is_whitespace) {
+        return Err(format!(
+            "expected '[CODE|DATA] [path] address', got '{line}'"
+        ));
+    }
+    Ok(TextRequest {
+        kind,
+        object: object.map(PathBuf::from),
+        address: parse_address_token(address)?,
+    })
+}
+
+// A function and its location per frame, innermost first, then an empty line.
+fn format_code(outcome: &SymbolizeOutcome) -> String {
+    let SymbolizeOutcome::Resolved(frame) = outcome else {
+        return UNKNOWN_CODE.to_string();
+    };
+    let mut answer = String::new();
+    let mut push = |symbol: &str, location: Option<&SourceLocation>| {
+        let _ = match location {
+            Some(location) => writeln!(
+                answer,
+                "{symbol}\n{}:{}:{}",
+                location.file,
+                location.line,
+                location.column.unwrap_or(0)
+            ),
+            None => writeln!(answer, "{symbol}\n??:0:0"),
+        };
+    };
+    push(&frame.symbol, frame.location.as_ref());
+    for inline in &frame.inlined_by {
+        push(&inline.symbol, inline.location.as_ref());
+    }
+    answer.push('\n');
+    answer
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_llvm_symbolizer_request_lines() {
+        let address = |address| InputAddress {
+            address,
+            exact_pc: false,
+        };
+        assert_eq!(
+            parse_text_request("CODE /tmp/app 0x1234").unwrap(),
+            TextRequest {
+                kind: Kind::Code,
+                object: Some(PathBuf::from("/tmp/app")),
+                address: address(0x1234),
+            }
+        );
+        assert_eq!(
+            parse_text_request("DATA \"/tmp/my app\" 4096").unwrap(),
+            TextRequest {
+                kind: Kind::Data,
+                object: Some(PathBuf::from("/tmp/my app")),
+                address: address(4096),
+            }
+        );
+        assert_eq!(
+            parse_text_request("0x10").unwrap(),
+            TextRequest {
+                kind: Kind::Code,
+                object: None,
+                address: address(0x10),
+            }
+        );
+        assert!(parse_text_request("CODE /tmp/app").is_err());
+    }
+}
@@ -0,0 +1,311 @@
+// Names for linker-generated trampolines. Mach-O symbol stubs and ELF PLT
+// entries carry no symbol of their own, so the nearest preceding symbol is
+// wrong for them; instead, the callee is recovered from the indirect symbol
+// table (Mach-O) or from the GOT slot each PLT entry jumps through (ELF).
+use crate::demangle;
+use object::macho;
+use object::read::macho::{MachHeader, Nlist, Segment};
+use object::{
+    Architecture, Endianness, Object, ObjectSection, ObjectSymbol, ObjectSymbolTable, ReadRef,
+    RelocationTarget,
+};
+use std::collections::HashMap;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) struct Stub {
+    pub address: u64,
+    pub size: u64,
+    pub name: String,
+}
+
+/// Collects the stubs of `object`, sorted by address. `raw_data` is the
+/// object's own bytes, needed to read Mach-O section headers that the generic
+/// `object` API does not expose.
+pub(crate) fn collect_stubs<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    raw_data: Option<&'data [u8]>,
+) -> Vec<Stub> {
+    let mut stubs = match (object.format(), raw_data) {
+        (object::BinaryFormat::MachO, Some(data)) => {
+            if object.is_64() {
+                macho_stubs::<macho::MachHeader64<Endianness>>(data)
+            } else {
+                macho_stubs::<macho::MachHeader32<Endianness>>(data)
+            }
+        }
+        (object::BinaryFormat::Elf, _) => plt_stubs(object),
+        _ => None,
+    }
+    .unwrap_or_default();
+    stubs.sort_by_key(|stub| stub.address);
+    stubs
+}
+
+/// Whether PLT entries of `architecture` are decoded; elsewhere, addresses in
+/// the PLT fall back to the nearest preceding symbol.
+pub(crate) fn decodes_plt(architecture: Architecture) -> bool {
+    matches!(architecture, Architecture::X86_64 | Architecture::Aarch64)
+}
+
+pub(crate) fn find_stub(stubs: &[Stub], address: u64) -> Option<&Stub> {
+    let index = stubs.partition_point(|stub| stub.address <= address);
+    let stub = &stubs[index.checked_sub(1)?];
+    (address < stub.address + stub.size).then_some(stub)
+}
+
+struct StubSection {
+    address: u64,
+    size: u64,
+    first_indirect: u32,
+    stub_size: u32,
+}
+
+fn macho_stubs<Mach: MachHeader<Endian = Endianness>>(data: &[u8]) -> Option<Vec<Stub>> {
+    let header = Mach::parse(data, 0).ok()?;
+    let endian = header.endian().ok()?;
+
+    let mut sections = Vec::new();
+    let mut symtab = None;
+    let mut dysymtab = None;
+    let mut commands = header.load_commands(endian, data, 0).ok()?;
+    while let Ok(Some(command)) = commands.next() {
+        if let Ok(Some((segment, section_data))) = command.segment_64() {
+            for section in segment.sections(endian, section_data).ok()? {
+                if section.flags.get(endian) & macho::SECTION_TYPE == macho::S_SYMBOL_STUBS {
+                    sections.push(StubSection {
+                        address: section.addr.get(endian),
+                        size: section.size.get(endian),
+                        first_indirect: section.reserved1.get(endian),
+                        stub_size: section.reserved2.get(endian),
+                    });
+                }
+            }
+        } else if let Ok(Some((segment, section_data))) = command.segment_32() {
+            for section in segment.sections(endian, section_data).ok()? {
+                if section.flags.get(endian) & macho::SECTION_TYPE == macho::S_SYMBOL_STUBS {
+                    sections.push(StubSection {
+                        address: u64::from(section.addr.get(endian)),
+                        size: u64::from(section.size.get(endian)),
+                        first_indirect: section.reserved1.get(endian),
+                        stub_size: section.reserved2.get(endian),
+                    });
+                }
+            }
+        } else if let Ok(Some(command)) = command.symtab() {
+            symtab = Some(command);
+        } else if let Ok(Some(command)) = command.dysymtab() {
+            dysymtab = Some(command);
+        }
+    }
+
+    let symbols = symtab?.symbols::<Mach, _>(endian, data).ok()?;
+    let dysymtab = dysymtab?;
+    let indirect = data
+        .read_slice_at::<object::U32<Endianness>>(
+            dysymtab.indirectsymoff.get(endian).into(),
+            dysymtab.nindirectsyms.get(endian) as usize,
+        )
+        .ok()?;
+
+    let mut stubs = Vec::new();
+    for section in sections {
+        if section.stub_size == 0 {
+            continue;
+        }
+        let stub_size = u64::from(section.stub_size);
+        for index in 0..section.size / stub_size {
+            let Some(entry) = indirect.get(section.first_indirect as usize + index as usize) else {
+                break;
+            };
+            let symbol_index = entry.get(endian);
+            if symbol_index & (macho::INDIRECT_SYMBOL_LOCAL | macho::INDIRECT_SYMBOL_ABS) != 0 {
+                continue;
+            }
+            let Some(name) = symbols
+                .symbol(symbol_index as usize)
+                .ok()
+                .and_then(|nlist| nlist.name(endian, symbols.strings()).ok())
+                .and_then(|name| std::str::from_utf8(name).ok())
+            else {
+                continue;
+            };
+            stubs.push(Stub {
+                address: section.address + index * stub_size,
+                size: stub_size,
+                name: format!("symbol stub for: {}", macho_display_name(name)),
+            });
+        }
+    }
+    Some(stubs)
+}
+
+// C symbols carry a leading underscore in Mach-O; mangled names are demangled.
+fn macho_display_name(name: &str) -> String {
+    let demangled = demangle::demangle_symbol(name);
+    if demangled == name {
+        name.strip_prefix('_').unwrap_or(name).to_string()
+    } else {
+        demangled
+    }
+}
+
+fn plt_stubs<'data>(object: &object::File<'data, &'data [u8]>) -> Option<Vec<Stub>> {
+    // Each PLT entry jumps through a GOT slot whose dynamic relocation names
+    // the callee, whether the slot is lazily bound (JUMP_SLOT) or not (GLOB_DAT).
+    let dynamic_symbols = object.dynamic_symbol_table()?;
+    let slots = object
+        .dynamic_relocations()?
+        .filter_map(|(offset, relocation)| match relocation.target() {
+            RelocationTarget::Symbol(index) => dynamic_symbols
+                .symbol_by_index(index)
+                .ok()
+                .and_then(|symbol| symbol.name().ok())
+                .filter(|name| !name.is_empty())
+                .map(|name| (offset, name)),
+            _ => None,
+        })
+        .collect::<HashMap<_, _>>();
+
+    let architecture = object.architecture();
+    let mut stubs = Vec::new();
+    for name in [".plt", ".plt.sec", ".plt.got"] {
+        let Some(section) = object.section_by_name(name) else {
+            continue;
+        };
+        let Ok(data) = section.data() else {
+            continue;
+        };
+        for (entry, size, slot) in plt_entries(architecture, name, section.address(), data) {
+            if let Some(callee) = slots.get(&slot) {
+                stubs.push(Stub {
+                    address: entry,
+                    size,
+                    name: format!("{}@plt", demangle::demangle_symbol(callee)),
+                });
+            }
+        }
+    }
+    Some(stubs)
+}
+
+// Returns `(entry address, entry size, GOT slot)` for each decodable entry.
+fn plt_entries(
+    architecture: Architecture,
+    section_name: &str,
+    address: u64,
+    data: &[u8],
+) -> Vec<(u64, u64, u64)> {
+    match architecture {
+        Architecture::X86_64 => {
+            // Entries are 16 bytes, except non-IBT `.plt.got`, which packs
+            // 8-byte `jmp *slot(%rip); nop` entries.
+            let ibt = data.starts_with(&[0xf3, 0x0f, 0x1e, 0xfa]);
+            let entry_size = if section_name == ".plt.got" && !ibt {
+                8
+            } else {
+                16
+            };
+            data.chunks(entry_size)
+                .enumerate()
+                .filter_map(|(index, entry)| {
+                    let entry_address = address + (index * entry_size) as u64;
+                    x86_64_jump_slot(entry, entry_address)
+                        .map(|slot| (entry_address, entry_size as u64, slot))
+                })
+                .collect()
+        }
+        Architecture::Aarch64 => data
+            .chunks(16)
+            .enumerate()
+            .filter_map(|(index, entry)| {
+                let entry_address = address + (index * 16) as u64;
+                aarch64_jump_slot(entry, entry_address).map(|slot| (entry_address, 16, slot))
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+// Finds `jmp *disp32(%rip)` (`ff 25`, optionally `bnd`-prefixed) in the entry.
+fn x86_64_jump_slot(entry: &[u8], entry_address: u64) -> Option<u64> {
+    let position = entry.windows(2).position(|pair| pair == [0xff, 0x25])?;
+    let displacement = i32::from_le_bytes(entry.get(position + 2..position + 6)?.try_into().ok()?);
+    let next = entry_address + position as u64 + 6;
+    Some(next.wrapping_add_signed(i64::from(displacement)))
+}
+
+// Decodes `adrp x16, page; ldr x17, [x16, #offset]` at the start of the entry.
+fn aarch64_jump_slot(entry: &[u8], entry_address: u64) -> Option<u64> {
+    let word = |index: usize| -> Option<u32> {
+        Some(u32::from_le_bytes(
+            entry.get(index * 4..index * 4 + 4)?.try_into().ok()?,
+        ))
+    };
+    let (adrp, ldr) = (word(0)?, word(1)?);
+    if adrp & 0x9f00_0000 != 0x9000_0000 || ldr & 0xffc0_0000 != 0xf940_0000 {
+        return None;
+    }
+    let immediate = ((adrp >> 29) & 0x3) | (((adrp >> 5) & 0x7_ffff) << 2);
+    // Sign-extend the 21-bit page count.
+    let pages = (i64::from(immediate) << 43) >> 43;
+    let page = (entry_address & !0xfff).wrapping_add_signed(pages << 12);
+    let offset = u64::from((ldr >> 10) & 0xfff) * 8;
+    Some(page + offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_x86_64_plt_jump() {
+        // endbr64; bnd jmp *0x2fe2(%rip)
+        let entry = [
+            0xf3, 0x0f, 0x1e, 0xfa, 0xf2, 0xff, 0x25, 0xe2, 0x2f, 0x00, 0x00, 0x0f, 0x1f, 0x44,
+            0x00, 0x00,
+        ];
+        assert_eq!(x86_64_jump_slot(&entry, 0x1030), Some(0x1030 + 11 + 0x2fe2));
+    }
+
+    #[test]
+    fn decodes_aarch64_plt_jump() {
+        // adrp x16, 0x11000; ldr x17, [x16, #0xf80]
+        let mut entry = Vec::new();
+        entry.extend_from_slice(&0xb000_0090u32.to_le_bytes());
+        entry.extend_from_slice(&0xf947_c211u32.to_le_bytes());
+        entry.extend_from_slice(&0x913e_0210u32.to_le_bytes());
+        entry.extend_from_slice(&0xd61f_0220u32.to_le_bytes());
+        assert_eq!(aarch64_jump_slot(&entry, 0x400), Some(0x11000 + 0xf80));
+    }
+
+    #[test]
+    fn finds_stub_by_range() {
+        let stubs = vec![
+            Stub {
+                address: 0x100,
+                size: 12,
+                name: "symbol stub for: free".to_string(),
+            },
+            Stub {
+                address: 0x10c,
+                size: 12,
+                name: "symbol stub for: malloc".to_string(),
+            },
+        ];
+        assert_eq!(
+            find_stub(&stubs, 0x104).unwrap().name,
+            "symbol stub for: free"
+        );
+        assert_eq!(
+            find_stub(&stubs, 0x10c).unwrap().name,
+            "symbol stub for: malloc"
+        );
+        assert!(find_stub(&stubs, 0x118).is_none());
+        assert!(find_stub(&stubs, 0xff).is_none());
+    }
+
+    #[test]
+    fn strips_c_underscore_from_macho_names() {
+        assert_eq!(macho_display_name("_objc_msgSend"), "objc_msgSend");
+    }
+}
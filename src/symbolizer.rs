@@ -0,0 +1,170 @@
+//! A symbolizer that keeps one object loaded between lookups.
+//!
+//! [`symbolize_path`](crate::atosl::symbolize_path) opens, maps and parses the
+//! object every time it is called. [`Symbolizer::open`] does that once and
+//! keeps the mapping and DWARF context until the `Symbolizer` is dropped, so a
+//! service can share one per module across threads:
+//!
+//! ```no_run
+//! use atosl::{OpenOptions, Symbolizer};
+//! use std::sync::Arc;
+//!
+//! let symbolizer = Arc::new(Symbolizer::open(
+//!     "MyApp.app.dSYM",
+//!     &OpenOptions {
+//!         load_address: 0x1_0000_0000,
+//!         arch: Some("arm64".to_string()),
+//!         ..Default::default()
+//!     },
+//! )?);
+//! println!("{}", symbolizer.object_info().object_path);
+//! println!("{:?}", symbolizer.symbolize(0x1_0000_1234));
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+use crate::atosl::{SelectedSlice, SymbolizeOptions, SymbolizeOutcome};
+use crate::locate::SymbolLocator;
+use crate::object_cache::LoadedObject;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// How [`Symbolizer::open`] finds the object and maps addresses onto it. The
+/// fields mean what they do in [`SymbolizeOptions`].
+#[derive(Clone, Debug, Default)]
+pub struct OpenOptions {
+    /// Where the image was loaded; addresses are looked up relative to it.
+    pub load_address: u64,
+    /// The image's slide (`atos -s`). Replaces `load_address` when set.
+    pub slide: Option<u64>,
+    /// ELF: `load_address` is the module base (lowest `PT_LOAD`), not `.text`.
+    pub module_base: bool,
+    pub arch: Option<String>,
+    /// Selects a slice by UUID, or the file in a directory by UUID or build-id.
+    pub uuid: Option<String>,
+    /// Image inside a dyld shared cache, by install name or file name.
+    pub image: Option<String>,
+    pub debug_dirs: Vec<PathBuf>,
+    pub dsym_dirs: Vec<PathBuf>,
+    pub locators: Vec<Arc<dyn SymbolLocator>>,
+    pub offline: bool,
+    pub pac_mask: Option<u64>,
+    pub va_bits: Option<u32>,
+    /// Every address is a return address, looked up at the instruction before
+    /// it.
+    pub return_addresses: bool,
+    pub verbose: bool,
+}
+
+/// The object a [`Symbolizer`] loaded.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ObjectInfo {
+    /// The file the symbols are read from, which may be a dSYM payload or
+    /// debug file found for the path that was opened.
+    pub object_path: String,
+    pub object_name: String,
+    /// Canonical architecture name (`arm64e`, `x86_64`).
+    pub arch: String,
+    pub selected_slice: Option<SelectedSlice>,
+}
+
+/// One object, loaded once and kept loaded until dropped.
+///
+/// The mapping and DWARF context are owned by a thread of the symbolizer's
+/// own, which answers lookups in turn; a `Symbolizer` is `Send + Sync`, and
+/// lookups from several threads wait for one another.
+pub struct Symbolizer {
+    object: LoadedObject,
+    info: ObjectInfo,
+    load_address: u64,
+}
+
+impl Symbolizer {
+    /// Loads `path`: an object, a dSYM payload or `.dSYM` bundle, or with
+    /// `uuid` a directory to search, as `-o` accepts.
+    pub fn open(path: impl AsRef<Path>, options: &OpenOptions) -> Result<Self> {
+        let options = options.clone();
+        let load_address = options.load_address;
+        let symbolize_options = SymbolizeOptions {
+            object_path: path.as_ref().to_path_buf(),
+            load_address: options.load_address,
+            slide: options.slide,
+            module_base: options.module_base,
+            arch: options.arch,
+            uuid: options.uuid,
+            image: options.image,
+            debug_dirs: options.debug_dirs,
+            dsym_dirs: options.dsym_dirs,
+            locators: options.locators,
+            offline: options.offline,
+            pac_mask: options.pac_mask,
+            va_bits: options.va_bits,
+            return_addresses: options.return_addresses,
+            verbose: options.verbose,
+            ..SymbolizeOptions::default()
+        };
+        let object = LoadedObject::spawn(move || Ok(symbolize_options));
+        let info = object.info().map_err(|error| anyhow!(error))?.clone();
+        Ok(Self {
+            object,
+            info,
+            load_address,
+        })
+    }
+
+    /// Resolves one runtime address.
+    pub fn symbolize(&self, address: u64) -> SymbolizeOutcome {
+        self.symbolize_all(&[address])
+            .pop()
+            .expect("one outcome per address")
+    }
+
+    /// Resolves a batch of runtime addresses in one go, in order.
+    pub fn symbolize_all(&self, addresses: &[u64]) -> Vec<SymbolizeOutcome> {
+        let load_address = self.load_address;
+        let batch = addresses.to_vec();
+        self.object
+            .run(move |symbolizer| {
+                batch
+                    .into_iter()
+                    .map(|address| symbolizer.symbolize(load_address, address, false, false))
+                    .collect()
+            })
+            .unwrap_or_else(|error| {
+                addresses
+                    .iter()
+                    .map(|&requested_address| SymbolizeOutcome::Unresolved {
+                        requested_address,
+                        canonical_address: None,
+                        error: error.clone(),
+                        selector: None,
+                    })
+                    .collect()
+            })
+    }
+
+    pub fn object_info(&self) -> &ObjectInfo {
+        &self.info
+    }
+
+    /// The slice chosen in a universal binary, or the UUID of a thin Mach-O.
+    pub fn selected_slice(&self) -> Option<&SelectedSlice> {
+        self.info.selected_slice.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbolizer_is_shareable_across_threads() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Symbolizer>();
+
+        let error = Symbolizer::open("/nonexistent/atosl-object", &OpenOptions::default())
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("does not exist"), "{error}");
+    }
+}
@@ -0,0 +1,473 @@
+// Symbol-table lookup with explicit symbol extents. `object`'s `SymbolMap` only
+// knows start addresses, so an address in padding, in data after code, or in a
+// stripped function would be blamed on whatever symbol happens to precede it.
+// Here every symbol gets an end (ELF `st_size`, otherwise the next symbol, the
+// next Mach-O function start, or its section end) and lookups outside it fail.
+use crate::demangle;
+use crate::relocate::SectionLayout;
+use object::macho;
+use object::read::macho::MachHeader;
+use object::{
+    Architecture, BinaryFormat, Endianness, Object, ObjectSection, ObjectSegment, ObjectSymbol,
+    SymbolKind, SymbolMapName, SymbolSection,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct TableSymbol<'data> {
+    pub address: u64,
+    /// Exclusive end; `u64::MAX` when nothing bounds the symbol.
+    pub end: u64,
+    pub name: &'data str,
+    pub global: bool,
+    /// ELF symbol version (`GLIBC_2.14`), for symbols from `.dynsym`.
+    pub version: Option<SymbolVersion<'data>>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) struct SymbolVersion<'data> {
+    pub name: &'data str,
+    /// A hidden (non-default) version prints as `name@V` instead of `name@@V`.
+    pub hidden: bool,
+}
+
+impl TableSymbol<'_> {
+    /// The demangled name, with its ELF version appended when it has one.
+    pub fn display_name(&self) -> String {
+        let name = demangle::demangle_symbol(self.name);
+        match self.version {
+            Some(version) if version.hidden => format!("{name}@{}", version.name),
+            Some(version) => format!("{name}@@{}", version.name),
+            None => name,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Candidate<'data> {
+    address: u64,
+    size: u64,
+    name: &'data str,
+    global: bool,
+    section_end: Option<u64>,
+    version: Option<SymbolVersion<'data>>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct SymbolTable<'data> {
+    symbols: Vec<TableSymbol<'data>>,
+    /// Built from the ELF dynamic symbol table because `.symtab` was stripped.
+    dynamic: bool,
+}
+
+impl<'data> SymbolTable<'data> {
+    /// Builds the table from `object`'s symbols plus `extra` symbols kept
+    /// outside it (dyld shared cache locals). `raw_data` is the object's own
+    /// bytes, used to read Mach-O function starts. A relocatable object's
+    /// symbols are placed according to `layout`.
+    pub fn build(
+        object: &object::File<'data, &'data [u8]>,
+        raw_data: Option<&'data [u8]>,
+        extra: &[SymbolMapName<'data>],
+        layout: Option<&SectionLayout>,
+    ) -> Self {
+        let format = object.format();
+        let section_end = |index| {
+            let section = object.section_by_index(index).ok()?;
+            let address = match layout {
+                Some(layout) => layout.section_address(object, index)?,
+                None => section.address(),
+            };
+            Some(address.saturating_add(section.size()))
+        };
+
+        // ELF marks Thumb functions by setting bit 0 of their address.
+        let thumb_bit = format == BinaryFormat::Elf && object.architecture() == Architecture::Arm;
+        let candidate = |symbol: object::Symbol<'data, '_>,
+                         version: Option<SymbolVersion<'data>>| {
+            if !symbol.is_definition() {
+                return None;
+            }
+            let SymbolSection::Section(index) = symbol.section() else {
+                return None;
+            };
+            let name = symbol.name().ok()?;
+            if name.is_empty() || is_marker_symbol(name, format, symbol.is_global()) {
+                return None;
+            }
+            let address = match layout {
+                Some(layout) => layout.symbol_address(object, &symbol),
+                None => symbol.address(),
+            };
+            Some(Candidate {
+                address: match thumb_bit && symbol.kind() == SymbolKind::Text {
+                    true => address & !1,
+                    false => address,
+                },
+                size: symbol.size(),
+                name,
+                global: symbol.is_global(),
+                section_end: section_end(index),
+                version,
+            })
+        };
+        let mut candidates = object
+            .symbols()
+            .filter_map(|symbol| candidate(symbol, None))
+            .collect::<Vec<_>>();
+
+        // `strip --strip-all` removes `.symtab`, but `.dynsym` still names
+        // every exported function, with its symbol version.
+        let dynamic = candidates.is_empty() && format == BinaryFormat::Elf;
+        if dynamic {
+            let versions = elf_symbol_versions(object).unwrap_or_default();
+            candidates.extend(object.dynamic_symbols().filter_map(|symbol| {
+                let version = versions.get(symbol.index().0).copied().flatten();
+                candidate(symbol, version)
+            }));
+        }
+
+        for symbol in extra {
+            if is_marker_symbol(symbol.name(), format, false) {
+                continue;
+            }
+            candidates.push(Candidate {
+                address: symbol.address(),
+                size: 0,
+                name: symbol.name(),
+                global: false,
+                section_end: None,
+                version: None,
+            });
+        }
+
+        let function_starts = match (format, raw_data) {
+            (BinaryFormat::MachO, Some(data)) => macho_function_starts(object, data),
+            _ => Vec::new(),
+        };
+        let mut table = Self::from_candidates(candidates, &function_starts);
+        table.dynamic = dynamic;
+        table
+    }
+
+    fn from_candidates(mut candidates: Vec<Candidate<'data>>, function_starts: &[u64]) -> Self {
+        // Among aliases at one address keep the global one, then the one whose
+        // size is known.
+        candidates
+            .sort_by_key(|candidate| (candidate.address, !candidate.global, candidate.size == 0));
+        candidates.dedup_by_key(|candidate| candidate.address);
+
+        let symbols = candidates
+            .iter()
+            .enumerate()
+            .map(|(index, candidate)| {
+                let end = if candidate.size > 0 {
+                    candidate.address.saturating_add(candidate.size)
+                } else {
+                    let next_symbol = candidates.get(index + 1).map(|next| next.address);
+                    let next_function = function_starts
+                        .get(function_starts.partition_point(|start| *start <= candidate.address))
+                        .copied();
+                    [next_symbol, next_function, candidate.section_end]
+                        .into_iter()
+                        .flatten()
+                        .min()
+                        .unwrap_or(u64::MAX)
+                };
+                TableSymbol {
+                    address: candidate.address,
+                    end,
+                    name: candidate.name,
+                    global: candidate.global,
+                    version: candidate.version,
+                }
+            })
+            .collect();
+        Self {
+            symbols,
+            dynamic: false,
+        }
+    }
+
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic
+    }
+
+    /// Returns the symbol called `name`. Static functions often share a name,
+    /// so a symbol whose extent is exactly `size` bytes wins over the others.
+    pub fn find_by_name(&self, name: &str, size: Option<u64>) -> Option<&TableSymbol<'data>> {
+        let mut matches = self.symbols.iter().filter(|symbol| symbol.name == name);
+        let first = matches.next()?;
+        let Some(size) = size else {
+            return Some(first);
+        };
+        std::iter::once(first)
+            .chain(matches)
+            .find(|symbol| symbol.end.wrapping_sub(symbol.address) == size)
+            .or(Some(first))
+    }
+
+    /// Returns the symbol covering `address`, or an explanation of why none
+    /// does.
+    pub fn lookup(&self, address: u64) -> Result<&TableSymbol<'data>, String> {
+        let index = self
+            .symbols
+            .partition_point(|symbol| symbol.address <= address);
+        let Some(symbol) = index.checked_sub(1).map(|index| &self.symbols[index]) else {
+            return Err(format!(
+                "failed to search symbol table: no symbol precedes address {address:#x}"
+            ));
+        };
+        if address >= symbol.end {
+            return Err(format!(
+                "failed to search symbol table: address {address:#x} is not inside any symbol \
+(nearest preceding symbol {} ends at {:#x})",
+                symbol.name, symbol.end
+            ));
+        }
+        Ok(symbol)
+    }
+}
+
+// Symbols that mark positions rather than name code: ARM/AArch64 mapping
+// symbols (`$x`, `$d`, `$t`, `$a`, optionally `$x.123`), `ltmp*` section
+// markers, and assembler-local labels (`.L*`, and non-external `L*`/`l_*` in
+// Mach-O).
+fn is_marker_symbol(name: &str, format: BinaryFormat, global: bool) -> bool {
+    if let Some(rest) = name.strip_prefix('$') {
+        let mut chars = rest.chars();
+        if matches!(chars.next(), Some('a' | 'd' | 't' | 'x'))
+            && matches!(chars.next(), None | Some('.'))
+        {
+            return true;
+        }
+    }
+    if name.starts_with("ltmp") || name.starts_with(".L") {
+        return true;
+    }
+    format == BinaryFormat::MachO && !global && (name.starts_with('L') || name.starts_with("l_"))
+}
+
+// Maps each `.dynsym` index to the version it defines, from `.gnu.version`
+// (one version index per symbol) and `.gnu.version_d` (the version names).
+// Indices 0 and 1 are local/global and carry no name.
+fn elf_symbol_versions<'data>(
+    object: &object::File<'data, &'data [u8]>,
+) -> Option<Vec<Option<SymbolVersion<'data>>>> {
+    let versym = object.section_by_name(".gnu.version")?.data().ok()?;
+    let verdef = object.section_by_name(".gnu.version_d")?.data().ok()?;
+    let strings = object.section_by_name(".dynstr")?.data().ok()?;
+    let little = object.is_little_endian();
+    let read_u16 = |data: &[u8], offset: usize| -> Option<u16> {
+        let bytes: [u8; 2] = data.get(offset..offset + 2)?.try_into().ok()?;
+        Some(if little {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    };
+    let read_u32 = |data: &[u8], offset: usize| -> Option<u32> {
+        let bytes: [u8; 4] = data.get(offset..offset + 4)?.try_into().ok()?;
+        Some(if little {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    };
+
+    // Elf_Verdef: version, flags, ndx, cnt (u16), hash, aux, next (u32); the
+    // first Elf_Verdaux (name, next) holds the version's own name.
+    let mut names = std::collections::HashMap::new();
+    let mut offset = 0usize;
+    loop {
+        let index = read_u16(verdef, offset + 4)?;
+        let aux = read_u32(verdef, offset + 12)? as usize;
+        let next = read_u32(verdef, offset + 16)? as usize;
+        let name_offset = read_u32(verdef, offset + aux)? as usize;
+        let name = strings.get(name_offset..)?;
+        let end = name.iter().position(|byte| *byte == 0)?;
+        if let Ok(name) = std::str::from_utf8(&name[..end]) {
+            names.insert(index, name);
+        }
+        if next == 0 {
+            break;
+        }
+        offset = offset.checked_add(next)?;
+    }
+
+    Some(
+        versym
+            .chunks_exact(2)
+            .map(|entry| {
+                let value = read_u16(entry, 0)?;
+                let index = value & 0x7fff;
+                if index < 2 {
+                    return None;
+                }
+                names.get(&index).map(|name| SymbolVersion {
+                    name,
+                    hidden: value & 0x8000 != 0,
+                })
+            })
+            .collect(),
+    )
+}
+
+fn macho_function_starts<'data>(
+    object: &object::File<'data, &'data [u8]>,
+    data: &'data [u8],
+) -> Vec<u64> {
+    let Some(text) = object
+        .segments()
+        .find(|segment| segment.name().ok().flatten() == Some("__TEXT"))
+    else {
+        return Vec::new();
+    };
+    let encoded = if object.is_64() {
+        function_starts_data::<macho::MachHeader64<Endianness>>(data)
+    } else {
+        function_starts_data::<macho::MachHeader32<Endianness>>(data)
+    };
+    encoded
+        .map(|encoded| decode_function_starts(encoded, text.address()))
+        .unwrap_or_default()
+}
+
+fn function_starts_data<Mach: MachHeader<Endian = Endianness>>(data: &[u8]) -> Option<&[u8]> {
+    let header = Mach::parse(data, 0).ok()?;
+    let endian = header.endian().ok()?;
+    let mut commands = header.load_commands(endian, data, 0).ok()?;
+    while let Ok(Some(command)) = commands.next() {
+        if command.cmd() == macho::LC_FUNCTION_STARTS {
+            let linkedit = command
+                .data::<macho::LinkeditDataCommand<Endianness>>()
+                .ok()?;
+            let offset = linkedit.dataoff.get(endian) as usize;
+            let size = linkedit.datasize.get(endian) as usize;
+            return data.get(offset..offset.checked_add(size)?);
+        }
+    }
+    None
+}
+
+// LC_FUNCTION_STARTS is a run of ULEB128 deltas, the first relative to the
+// __TEXT vmaddr, terminated by a zero delta.
+fn decode_function_starts(encoded: &[u8], text_vmaddr: u64) -> Vec<u64> {
+    let mut starts = Vec::new();
+    let mut address = text_vmaddr;
+    let mut delta = 0u64;
+    let mut shift = 0;
+    for byte in encoded {
+        delta |= u64::from(byte & 0x7f).checked_shl(shift).unwrap_or(0);
+        shift += 7;
+        if byte & 0x80 != 0 {
+            continue;
+        }
+        if delta == 0 {
+            break;
+        }
+        address = address.wrapping_add(delta);
+        starts.push(address);
+        delta = 0;
+        shift = 0;
+    }
+    starts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(address: u64, size: u64, name: &str, global: bool) -> Candidate<'_> {
+        Candidate {
+            address,
+            size,
+            name,
+            global,
+            section_end: Some(0x1000),
+            version: None,
+        }
+    }
+
+    #[test]
+    fn lookup_respects_symbol_size() {
+        let table = SymbolTable::from_candidates(
+            vec![
+                candidate(0x100, 0x10, "sized", true),
+                candidate(0x200, 0, "tail", true),
+            ],
+            &[],
+        );
+        assert_eq!(table.lookup(0x10f).unwrap().name, "sized");
+        assert!(table
+            .lookup(0x110)
+            .unwrap_err()
+            .contains("not inside any symbol"));
+        assert!(table
+            .lookup(0xff)
+            .unwrap_err()
+            .contains("no symbol precedes"));
+        // An unsized symbol extends to its section end.
+        assert_eq!(table.lookup(0xfff).unwrap().name, "tail");
+        assert!(table.lookup(0x1000).is_err());
+    }
+
+    #[test]
+    fn function_starts_bound_unsized_symbols() {
+        let table =
+            SymbolTable::from_candidates(vec![candidate(0x100, 0, "first", true)], &[0x100, 0x140]);
+        assert_eq!(table.lookup(0x13f).unwrap().name, "first");
+        assert!(table.lookup(0x140).is_err());
+    }
+
+    #[test]
+    fn prefers_global_alias() {
+        let table = SymbolTable::from_candidates(
+            vec![
+                candidate(0x100, 0x10, "local_alias", false),
+                candidate(0x100, 0x10, "global_name", true),
+            ],
+            &[],
+        );
+        assert_eq!(table.lookup(0x104).unwrap().name, "global_name");
+    }
+
+    #[test]
+    fn display_name_appends_elf_version() {
+        let mut symbol = TableSymbol {
+            address: 0,
+            end: 1,
+            name: "memcpy",
+            global: true,
+            version: Some(SymbolVersion {
+                name: "GLIBC_2.14",
+                hidden: false,
+            }),
+        };
+        assert_eq!(symbol.display_name(), "memcpy@@GLIBC_2.14");
+        symbol.version = Some(SymbolVersion {
+            name: "GLIBC_2.2.5",
+            hidden: true,
+        });
+        assert_eq!(symbol.display_name(), "memcpy@GLIBC_2.2.5");
+    }
+
+    #[test]
+    fn filters_marker_symbols() {
+        for name in ["$x", "$d.12", "$t", "ltmp0", ".LBB0_1"] {
+            assert!(is_marker_symbol(name, BinaryFormat::Elf, false), "{name}");
+        }
+        assert!(is_marker_symbol("Ltmp3", BinaryFormat::MachO, false));
+        assert!(!is_marker_symbol("Lookup", BinaryFormat::Elf, false));
+        assert!(!is_marker_symbol("$xyz", BinaryFormat::Elf, false));
+        assert!(!is_marker_symbol("_main", BinaryFormat::MachO, true));
+    }
+
+    #[test]
+    fn decodes_function_starts() {
+        // 0x10 from __TEXT, then +0x90 (two-byte ULEB128), then terminator.
+        assert_eq!(
+            decode_function_starts(&[0x10, 0x90, 0x01, 0x00], 0x1000),
+            vec![0x1010, 0x10a0]
+        );
+    }
+}
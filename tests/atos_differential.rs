@@ -73,12 +73,12 @@ fn matches_atos_on_dwarf_and_inline_frames() -> anyhow::Result<()> {
     Ok(())
 }
 
-/// `atos -offset N` is reproduced by the default mode with a zero load address:
-/// `atosl -l 0 N` looks up `N + __TEXT vmaddr`, exactly what `atos -offset`
-/// does. (atosl's own `-f` flag is a different, historical mode and is not
-/// equivalent to `atos -offset`.)
+/// `atosl -offset N` looks up `N + __TEXT vmaddr`, exactly what `atos -offset`
+/// does; so does the default mode with a zero load address. (atosl's own `-f`
+/// flag is a different, historical mode and is not equivalent to `atos
+/// -offset`.)
 #[test]
-fn matches_atos_offset_via_zero_load() -> anyhow::Result<()> {
+fn matches_atos_offset() -> anyhow::Result<()> {
     let Some(fixture) = InlineFixture::build()? else {
         eprintln!("skipping: atos not available");
         return Ok(());
@@ -88,15 +88,104 @@ fn matches_atos_offset_via_zero_load() -> anyhow::Result<()> {
     let offset = outer - fixture.text_vmaddr;
     let payload = fixture.dsym_payload.to_str().unwrap();
 
-    let ours = run_atosl(&["-o", payload, "-l", "0", &hex(offset)]);
     let theirs = run_atos(&["-o", payload, "-offset", &hex(offset)])?;
+    let ours = run_atosl(&["-o", payload, "-offset", &hex(offset)]);
+    assert_eq!(ours, theirs, "-offset disagrees at {}", hex(offset));
 
-    assert_eq!(
-        normalize_frames(&ours),
-        normalize_frames(&theirs),
-        "atosl -l 0 / atos -offset disagree at offset {}\n  atosl: {ours:?}\n  atos : {theirs:?}",
-        hex(offset),
-    );
+    let zero_load = run_atosl(&["-o", payload, "-l", "0", &hex(offset)]);
+    assert_eq!(zero_load, theirs, "-l 0 disagrees at {}", hex(offset));
+
+    Ok(())
+}
+
+/// `-s` takes the image's slide instead of its load address.
+#[test]
+fn matches_atos_slide() -> anyhow::Result<()> {
+    let Some(fixture) = InlineFixture::build()? else {
+        eprintln!("skipping: atos not available");
+        return Ok(());
+    };
+
+    let slide = 0x4000_0000;
+    let address = fixture.symbol_address("_outer")? + slide;
+    let payload = fixture.dsym_payload.to_str().unwrap();
+
+    let ours = run_atosl(&["-o", payload, "-s", &hex(slide), &hex(address)]);
+    let theirs = run_atos(&["-o", payload, "-s", &hex(slide), &hex(address)])?;
+    assert_eq!(ours, theirs, "-s disagrees at {}", hex(address));
+
+    Ok(())
+}
+
+/// Without `-fullPath` both tools print the source file name only; with it,
+/// the path recorded in the DWARF.
+#[test]
+fn matches_atos_file_names_and_full_path() -> anyhow::Result<()> {
+    let Some(fixture) = InlineFixture::build()? else {
+        eprintln!("skipping: atos not available");
+        return Ok(());
+    };
+
+    let load = hex(fixture.text_vmaddr);
+    let main = hex(fixture.symbol_address("_main")?);
+    let payload = fixture.dsym_payload.to_str().unwrap();
+
+    for args in [
+        vec!["-o", payload, "-l", &load, &main],
+        vec!["-o", payload, "-l", &load, "-fullPath", &main],
+    ] {
+        assert_eq!(run_atosl(&args), run_atos(&args)?, "disagree on {args:?}");
+    }
+
+    Ok(())
+}
+
+/// `-d` joins the inline frames of one address onto a single line.
+#[test]
+fn matches_atos_inline_delimiter() -> anyhow::Result<()> {
+    let Some(fixture) = InlineFixture::build()? else {
+        eprintln!("skipping: atos not available");
+        return Ok(());
+    };
+
+    let outer = fixture.symbol_address("_outer")?;
+    let main = fixture.symbol_address("_main")?;
+    let load = hex(fixture.text_vmaddr);
+    let payload = fixture.dsym_payload.to_str().unwrap();
+
+    for address in (outer..main).step_by(4).map(hex) {
+        let ours = run_atosl(&[
+            "-o",
+            payload,
+            "-l",
+            &load,
+            "--inline-frames",
+            "-d",
+            " | ",
+            &address,
+        ]);
+        let theirs = run_atos(&["-o", payload, "-l", &load, "-i", "-d", " | ", &address])?;
+        assert_eq!(ours, theirs, "-d disagrees at {address}");
+    }
+
+    Ok(())
+}
+
+/// atos prints a header only for a live process, so `-printHeader` with `-o`
+/// changes nothing.
+#[test]
+fn matches_atos_print_header() -> anyhow::Result<()> {
+    let Some(fixture) = InlineFixture::build()? else {
+        eprintln!("skipping: atos not available");
+        return Ok(());
+    };
+
+    let load = hex(fixture.text_vmaddr);
+    let main = hex(fixture.symbol_address("_main")?);
+    let payload = fixture.dsym_payload.to_str().unwrap();
+
+    let args = ["-o", payload, "-l", &load, "-printHeader", &main];
+    assert_eq!(run_atosl(&args), run_atos(&args)?);
 
     Ok(())
 }
@@ -127,9 +216,8 @@ fn run_atos(args: &[&str]) -> anyhow::Result<String> {
 /// `symbol (basename:line)`, `symbol + offset`, or the raw line.
 ///
 /// This intentionally drops the `(in <image>)` qualifier and the source
-/// directory. atosl prints absolute source paths while atos prints just the
-/// file name (without `-fullPath`); that cosmetic difference is not what these
-/// tests are guarding.
+/// directory, so the inline-frame sweep only guards the frames themselves.
+/// The option tests above compare the exact output instead.
 fn normalize_frames(output: &str) -> Vec<String> {
     output
         .lines()
@@ -1,34 +1,23 @@
-use assert_cmd::Command;
-use object::{Object, ObjectSection, ObjectSymbol};
-use serde_json::Value;
+mod support;
+
+use object::macho;
+use object::{Object, ObjectSegment};
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::process::Command as ProcessCommand;
-use tempfile::TempDir;
+use support::macho::{dwarf_sections, elf_section, write_dyld_cache, MachO, Section};
+use support::{
+    atosl, atosl_for, hex, json_output, serve_http, symbol_addr, symbol_size, text_addr,
+    text_output, ServerProcess, Workspace,
+};
 
 #[test]
 fn cli_emits_json_for_resolved_symbol() {
-    let fixture = Fixture::build().unwrap();
-    let address = fixture.symbol_address("fixture_target").unwrap();
-
-    let output = Command::cargo_bin("atosl")
-        .unwrap()
-        .args([
-            "-o",
-            fixture.binary_path().to_str().unwrap(),
-            "-l",
-            &format!("0x{:x}", fixture.load_address().unwrap()),
-            "--format",
-            "json",
-            &format!("0x{address:x}"),
-        ])
-        .assert()
-        .success()
-        .get_output()
-        .stdout
-        .clone();
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = symbol_addr(&binary, "fixture_target");
 
-    let parsed: Value = serde_json::from_slice(&output).unwrap();
+    let parsed = json_output(atosl_for(&binary).args(["--format", "json", &hex(address)]));
     assert_eq!(parsed["frames"][0]["status"], "resolved");
     assert_eq!(parsed["object_name"], "fixture_bin");
     assert_eq!(parsed["frames"][0]["requested_address"], address);
@@ -36,18 +25,12 @@ fn cli_emits_json_for_resolved_symbol() {
 
 #[test]
 fn cli_emits_text_for_resolved_symbol() {
-    let fixture = Fixture::build().unwrap();
-    let address = fixture.symbol_address("fixture_target").unwrap();
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = symbol_addr(&binary, "fixture_target");
 
-    Command::cargo_bin("atosl")
-        .unwrap()
-        .args([
-            "-o",
-            fixture.binary_path().to_str().unwrap(),
-            "-l",
-            &format!("0x{:x}", fixture.load_address().unwrap()),
-            &format!("0x{address:x}"),
-        ])
+    atosl_for(&binary)
+        .arg(hex(address))
         .assert()
         .success()
         .stdout(predicates::str::contains("(in fixture_bin)"));
@@ -55,33 +38,15 @@ fn cli_emits_text_for_resolved_symbol() {
 
 #[test]
 fn cli_emits_json_lines_for_batch() {
-    let fixture = Fixture::build().unwrap();
-    let address = fixture.symbol_address("fixture_target").unwrap();
-    let address_arg = format!("0x{address:x}");
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = hex(symbol_addr(&binary, "fixture_target"));
 
-    let output = Command::cargo_bin("atosl")
-        .unwrap()
-        .args([
-            "-o",
-            fixture.binary_path().to_str().unwrap(),
-            "-l",
-            &format!("0x{:x}", fixture.load_address().unwrap()),
-            "--format",
-            "json-lines",
-            &address_arg,
-            &address_arg,
-        ])
-        .assert()
-        .success()
-        .get_output()
-        .stdout
-        .clone();
-
-    let text = String::from_utf8(output).unwrap();
+    let text = text_output(atosl_for(&binary).args(["--format", "json-lines", &address, &address]));
     let lines: Vec<&str> = text.lines().filter(|line| !line.is_empty()).collect();
     assert_eq!(lines.len(), 2);
     for line in lines {
-        let value: Value = serde_json::from_str(line).unwrap();
+        let value: serde_json::Value = serde_json::from_str(line).unwrap();
         assert_eq!(value["status"], "resolved");
         assert_eq!(value["object_name"], "fixture_bin");
     }
@@ -89,55 +54,38 @@ fn cli_emits_json_lines_for_batch() {
 
 #[test]
 fn cli_streams_json_lines_from_stdin() {
-    let fixture = Fixture::build().unwrap();
-    let address = fixture.symbol_address("fixture_target").unwrap();
-
-    let output = Command::cargo_bin("atosl")
-        .unwrap()
-        .args([
-            "-o",
-            fixture.binary_path().to_str().unwrap(),
-            "-l",
-            &format!("0x{:x}", fixture.load_address().unwrap()),
-            "--format",
-            "json-lines",
-        ])
-        .write_stdin(format!("0x{address:x}\n0x{address:x}\n"))
-        .assert()
-        .success()
-        .get_output()
-        .stdout
-        .clone();
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = hex(symbol_addr(&binary, "fixture_target"));
 
-    let text = String::from_utf8(output).unwrap();
+    let text = text_output(
+        atosl_for(&binary)
+            .args(["--format", "json-lines"])
+            .write_stdin(format!("{address}\n{address}\n")),
+    );
     assert_eq!(text.lines().filter(|line| !line.is_empty()).count(), 2);
-    let first: Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
+    let first: serde_json::Value = serde_json::from_str(text.lines().next().unwrap()).unwrap();
     assert_eq!(first["status"], "resolved");
 }
 
 #[test]
 fn cli_resolves_dsym_bundle_directory() {
-    let fixture = Fixture::build().unwrap();
-    let address = fixture.symbol_address("fixture_target").unwrap();
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = symbol_addr(&binary, "fixture_target");
 
-    let dwarf_dir = fixture
-        .binary_path()
-        .parent()
-        .unwrap()
-        .join("Fixture.dSYM/Contents/Resources/DWARF");
+    let dwarf_dir = workspace.join("Fixture.dSYM/Contents/Resources/DWARF");
     fs::create_dir_all(&dwarf_dir).unwrap();
-    let payload = dwarf_dir.join("fixture_bin");
-    fs::copy(fixture.binary_path(), &payload).unwrap();
-    let bundle = fixture.binary_path().parent().unwrap().join("Fixture.dSYM");
+    fs::copy(&binary, dwarf_dir.join("fixture_bin")).unwrap();
+    let bundle = workspace.join("Fixture.dSYM");
 
-    Command::cargo_bin("atosl")
-        .unwrap()
+    atosl()
         .args([
             "-o",
             bundle.to_str().unwrap(),
             "-l",
-            &format!("0x{:x}", fixture.load_address().unwrap()),
-            &format!("0x{address:x}"),
+            &hex(text_addr(&binary)),
+            &hex(address),
         ])
         .assert()
         .success()
@@ -146,18 +94,12 @@ fn cli_resolves_dsym_bundle_directory() {
 
 #[test]
 fn cli_reads_addresses_from_stdin() {
-    let fixture = Fixture::build().unwrap();
-    let address = fixture.symbol_address("fixture_target").unwrap();
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = symbol_addr(&binary, "fixture_target");
 
-    Command::cargo_bin("atosl")
-        .unwrap()
-        .args([
-            "-o",
-            fixture.binary_path().to_str().unwrap(),
-            "-l",
-            &format!("0x{:x}", fixture.load_address().unwrap()),
-        ])
-        .write_stdin(format!("0x{address:x}\n"))
+    atosl_for(&binary)
+        .write_stdin(format!("{}\n", hex(address)))
         .assert()
         .success()
         .stdout(predicates::str::contains("(in fixture_bin)"));
@@ -165,63 +107,36 @@ fn cli_reads_addresses_from_stdin() {
 
 #[test]
 fn cli_reads_addresses_from_input_file() {
-    let fixture = Fixture::build().unwrap();
-    let address = fixture.symbol_address("fixture_target").unwrap();
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = symbol_addr(&binary, "fixture_target");
+    let input = workspace.write("addrs.txt", format!("{}\n", hex(address)));
 
-    let input_path = fixture.binary_path().parent().unwrap().join("addrs.txt");
-    fs::write(&input_path, format!("0x{address:x}\n")).unwrap();
-
-    Command::cargo_bin("atosl")
-        .unwrap()
-        .args([
-            "-o",
-            fixture.binary_path().to_str().unwrap(),
-            "-l",
-            &format!("0x{:x}", fixture.load_address().unwrap()),
-            "--input",
-            input_path.to_str().unwrap(),
-        ])
+    atosl_for(&binary)
+        .args(["--input", input.to_str().unwrap()])
         .assert()
         .success()
         .stdout(predicates::str::contains("(in fixture_bin)"));
 }
 
 #[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
 fn cli_finds_object_in_directory_by_build_id() {
-    // build-id is an ELF/GNU-ld concept; skip on non-Linux toolchains.
-    if !cfg!(target_os = "linux") {
-        return;
-    }
-
-    let tempdir = tempfile::tempdir().unwrap();
-    let src = tempdir.path().join("f.c");
-    fs::write(
-        &src,
-        "int fixture_target(void){return 7;}\nint main(void){return fixture_target();}\n",
-    )
-    .unwrap();
-
-    let dir = tempdir.path().join("symbols");
-    fs::create_dir_all(&dir).unwrap();
-    let alpha = dir.join("alpha");
-    let beta = dir.join("beta");
+    let workspace = Workspace::new();
     let id_beta = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
-    build_with_build_id(&src, &alpha, "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
-    build_with_build_id(&src, &beta, id_beta);
-
+    workspace.build_target_with_id("symbols/alpha", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
+    let beta = workspace.build_target_with_id("symbols/beta", id_beta);
     let address = symbol_addr(&beta, "fixture_target");
-    let load = text_addr(&beta);
 
-    Command::cargo_bin("atosl")
-        .unwrap()
+    atosl()
         .args([
             "-o",
-            dir.to_str().unwrap(),
+            workspace.join("symbols").to_str().unwrap(),
             "--uuid",
             id_beta,
             "-l",
-            &format!("0x{load:x}"),
-            &format!("0x{address:x}"),
+            &hex(text_addr(&beta)),
+            &hex(address),
         ])
         .assert()
         .success()
@@ -229,324 +144,1430 @@ fn cli_finds_object_in_directory_by_build_id() {
 }
 
 #[test]
-fn cli_follows_gnu_debuglink_to_separate_debug_file() {
-    // .gnu_debuglink + objcopy are ELF/binutils features; skip elsewhere.
-    if !cfg!(target_os = "linux") {
-        return;
-    }
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
+fn cli_prefers_debug_file_among_directory_duplicates() {
+    // The stripped binary sorts first, but the debug file carries the DWARF.
+    let workspace = Workspace::new();
+    let id = "cccccccccccccccccccccccccccccccccccccccc";
+    let app = workspace.build_target_with_id("symbols/app", id);
+    workspace.split_debug("symbols/app");
+    workspace.write("symbols/notes.txt", "not an object");
+    let address = symbol_addr(&app, "fixture_target");
 
-    let tempdir = tempfile::tempdir().unwrap();
-    let src = tempdir.path().join("f.c");
-    fs::write(
-        &src,
-        "int fixture_target(void){return 7;}\nint main(void){return fixture_target();}\n",
-    )
-    .unwrap();
-
-    let bin = tempdir.path().join("app");
-    let status = ProcessCommand::new("cc")
+    atosl()
         .args([
-            "-g",
-            "-O0",
-            src.to_str().unwrap(),
             "-o",
-            bin.to_str().unwrap(),
+            workspace.join("symbols").to_str().unwrap(),
+            "--uuid",
+            id,
+            "-v",
+            "-l",
+            &hex(text_addr(&app)),
+            &hex(address),
         ])
-        .status()
-        .unwrap();
-    assert!(status.success());
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("(in app.debug) (f.c:1)"))
+        .stderr(predicates::str::contains(format!(
+            "locate: also matched {}",
+            app.display()
+        )));
+}
 
-    run_objcopy(&["--only-keep-debug", "app", "app.debug"], tempdir.path());
-    run_objcopy(&["--strip-debug", "app"], tempdir.path());
-    run_objcopy(&["--add-gnu-debuglink=app.debug", "app"], tempdir.path());
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
+fn cli_answers_uuid_lookup_from_directory_index() {
+    let workspace = Workspace::new();
+    let dir = workspace.join("symbols");
+    fs::create_dir_all(dir.join("nested")).unwrap();
+    workspace.build_target_with_id("symbols/alpha", "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa");
 
-    let address = symbol_addr(&bin, "fixture_target");
-    let load = text_addr(&bin);
+    atosl()
+        .args(["index", "build", dir.to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains(
+            "1 files, 1 module ids (1 read, 0 removed)",
+        ));
 
-    Command::cargo_bin("atosl")
-        .unwrap()
-        .args([
-            "-o",
-            bin.to_str().unwrap(),
-            "-l",
-            &format!("0x{load:x}"),
-            &format!("0x{address:x}"),
-        ])
+    // Added after the index was built: the lookup misses without walking
+    // the directory, until the index is updated.
+    let id_beta = "bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb";
+    let beta = workspace.build_target_with_id("symbols/nested/beta", id_beta);
+    let address = symbol_addr(&beta, "fixture_target");
+    let lookup = || {
+        atosl()
+            .args([
+                "-o",
+                dir.to_str().unwrap(),
+                "--uuid",
+                id_beta,
+                "--offline",
+                "-l",
+                &hex(text_addr(&beta)),
+                &hex(address),
+            ])
+            .assert()
+    };
+    lookup()
+        .failure()
+        .stderr(predicates::str::contains(format!("matched uuid {id_beta}")));
+
+    atosl()
+        .args(["index", "update", dir.to_str().unwrap()])
         .assert()
         .success()
-        .stdout(predicates::str::contains("(in app.debug)"));
+        .stdout(predicates::str::contains(
+            "2 files, 2 module ids (1 read, 0 removed)",
+        ));
+    lookup()
+        .success()
+        .stdout(predicates::str::contains("(in beta)"));
 }
 
-fn run_objcopy(args: &[&str], cwd: &Path) {
-    let status = ProcessCommand::new("objcopy")
-        .args(args)
-        .current_dir(cwd)
-        .status()
-        .unwrap();
-    assert!(status.success(), "objcopy {args:?} failed");
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs objcopy and ELF")]
+fn cli_follows_gnu_debuglink_to_separate_debug_file() {
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("app");
+    workspace.split_debug("app");
+    workspace.objcopy(&["--add-gnu-debuglink=app.debug", "app"]);
+    let address = symbol_addr(&binary, "fixture_target");
+
+    atosl_for(&binary)
+        .arg(hex(address))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("(in app.debug)"));
 }
 
 #[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs objcopy and ELF")]
 fn cli_rejects_debuglink_with_bad_crc() {
-    if !cfg!(target_os = "linux") {
-        return;
-    }
-
-    let tempdir = tempfile::tempdir().unwrap();
-    let src = tempdir.path().join("f.c");
-    fs::write(
-        &src,
-        "int fixture_target(void){return 7;}\nint main(void){return fixture_target();}\n",
-    )
-    .unwrap();
-    let bin = tempdir.path().join("app");
-    assert!(ProcessCommand::new("cc")
-        .args([
-            "-g",
-            "-O0",
-            src.to_str().unwrap(),
-            "-o",
-            bin.to_str().unwrap()
-        ])
-        .status()
-        .unwrap()
-        .success());
-
-    run_objcopy(&["--only-keep-debug", "app", "app.debug"], tempdir.path());
-    run_objcopy(&["--strip-debug", "app"], tempdir.path());
-    run_objcopy(&["--add-gnu-debuglink=app.debug", "app"], tempdir.path());
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("app");
+    let debug_path = workspace.split_debug("app");
+    workspace.objcopy(&["--add-gnu-debuglink=app.debug", "app"]);
 
     // Corrupt the debug file so its CRC no longer matches the recorded value.
-    let debug_path = tempdir.path().join("app.debug");
     let mut bytes = fs::read(&debug_path).unwrap();
     bytes.push(0);
     fs::write(&debug_path, &bytes).unwrap();
-
-    let address = symbol_addr(&bin, "fixture_target");
-    let load = text_addr(&bin);
+    let address = symbol_addr(&binary, "fixture_target");
 
     // The CRC mismatch makes atosl ignore app.debug and fall back to the
     // stripped binary's symbol table, so the object name is "app", not
     // "app.debug".
-    Command::cargo_bin("atosl")
-        .unwrap()
-        .args([
-            "-o",
-            bin.to_str().unwrap(),
-            "-l",
-            &format!("0x{load:x}"),
-            &format!("0x{address:x}"),
-        ])
+    atosl_for(&binary)
+        .arg(hex(address))
         .assert()
         .success()
         .stdout(predicates::str::contains("(in app)"));
 }
 
 #[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs objcopy and ELF")]
 fn cli_uses_extra_debug_dir() {
-    if !cfg!(target_os = "linux") {
-        return;
-    }
-
-    let tempdir = tempfile::tempdir().unwrap();
-    let src = tempdir.path().join("f.c");
-    fs::write(
-        &src,
-        "int fixture_target(void){return 7;}\nint main(void){return fixture_target();}\n",
-    )
-    .unwrap();
-    let bin = tempdir.path().join("app");
-    assert!(ProcessCommand::new("cc")
-        .args([
-            "-g",
-            "-O0",
-            src.to_str().unwrap(),
-            "-o",
-            bin.to_str().unwrap()
-        ])
-        .status()
-        .unwrap()
-        .success());
-
-    run_objcopy(&["--only-keep-debug", "app", "app.debug"], tempdir.path());
-    run_objcopy(&["--strip-debug", "app"], tempdir.path());
-    run_objcopy(&["--add-gnu-debuglink=app.debug", "app"], tempdir.path());
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("app");
+    let debug_path = workspace.split_debug("app");
+    workspace.objcopy(&["--add-gnu-debuglink=app.debug", "app"]);
 
     // Move the debug file out of the binary's directory so only --debug-dir can
     // find it.
-    let extra = tempdir.path().join("extra");
+    let extra = workspace.join("extra");
     fs::create_dir_all(&extra).unwrap();
-    fs::rename(tempdir.path().join("app.debug"), extra.join("app.debug")).unwrap();
-
-    let address = symbol_addr(&bin, "fixture_target");
-    let load = text_addr(&bin);
+    fs::rename(debug_path, extra.join("app.debug")).unwrap();
+    let address = symbol_addr(&binary, "fixture_target");
 
-    Command::cargo_bin("atosl")
-        .unwrap()
-        .args([
-            "-o",
-            bin.to_str().unwrap(),
-            "--debug-dir",
-            extra.to_str().unwrap(),
-            "-l",
-            &format!("0x{load:x}"),
-            &format!("0x{address:x}"),
-        ])
+    atosl_for(&binary)
+        .args(["--debug-dir", extra.to_str().unwrap(), &hex(address)])
         .assert()
         .success()
         .stdout(predicates::str::contains("(in app.debug)"));
 }
 
 #[test]
-fn cli_resolves_via_debuginfod_cache() {
-    if !cfg!(target_os = "linux") {
-        return;
-    }
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
+fn cli_probes_symbol_server_layouts_in_debug_dir() {
+    let workspace = Workspace::new();
+    let id = "dddddddddddddddddddddddddddddddddddddddd";
+    let binary = workspace.build_target_with_id("app", id);
+    let debug_path = workspace.split_debug("app");
 
-    let tempdir = tempfile::tempdir().unwrap();
-    let src = tempdir.path().join("f.c");
-    fs::write(
-        &src,
-        "int fixture_target(void){return 7;}\nint main(void){return fixture_target();}\n",
-    )
-    .unwrap();
-    let bin = tempdir.path().join("app");
-    let build_id = "abcdef0123456789abcdef0123456789abcdef01";
-    build_with_build_id(&src, &bin, build_id);
+    let address = symbol_addr(&binary, "fixture_target");
+    let symbolize_with = |store: &Path| {
+        atosl_for(&binary)
+            .env("DEBUGINFOD_CACHE_PATH", workspace.join("no-cache"))
+            .args(["--debug-dir", store.to_str().unwrap(), &hex(address)])
+            .assert()
+            .success()
+            .stdout(predicates::str::contains("(f.c:1)"));
+    };
 
-    run_objcopy(&["--only-keep-debug", "app", "app.debug"], tempdir.path());
-    run_objcopy(&["--strip-debug", "app"], tempdir.path());
+    // A two-tier symstore, keyed as SSQP; its stripped executable is skipped.
+    let symstore = workspace.join("symstore");
+    let debug = symstore.join(format!("_./_.debug/elf-buildid-sym-{id}"));
+    let executable = symstore.join(format!("ap/app/elf-buildid-{id}"));
+    fs::create_dir_all(&debug).unwrap();
+    fs::create_dir_all(&executable).unwrap();
+    fs::write(symstore.join("index2.txt"), "").unwrap();
+    fs::copy(&binary, executable.join("app")).unwrap();
+    fs::copy(&debug_path, debug.join("_.debug")).unwrap();
+    symbolize_with(&symstore);
+
+    // The unified layout.
+    let unified = workspace.join("unified");
+    let entry = unified.join("dd").join(&id[2..]);
+    fs::create_dir_all(&entry).unwrap();
+    fs::copy(&debug_path, entry.join("debuginfo")).unwrap();
+    symbolize_with(&unified);
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
+fn cli_resolves_via_debuginfod_cache() {
+    let workspace = Workspace::new();
+    let build_id = "abcdef0123456789abcdef0123456789abcdef01";
+    let binary = workspace.build_target_with_id("app", build_id);
+    let debug_path = workspace.split_debug("app");
 
     // Lay out the debug file the way the debuginfod client cache does.
-    let cache = tempdir.path().join("dicache");
+    let cache = workspace.join("dicache");
     let entry = cache.join(build_id);
     fs::create_dir_all(&entry).unwrap();
-    fs::rename(tempdir.path().join("app.debug"), entry.join("debuginfo")).unwrap();
-
-    let address = symbol_addr(&bin, "fixture_target");
-    let load = text_addr(&bin);
+    fs::rename(debug_path, entry.join("debuginfo")).unwrap();
+    let address = symbol_addr(&binary, "fixture_target");
 
-    Command::cargo_bin("atosl")
-        .unwrap()
+    atosl_for(&binary)
         .env("DEBUGINFOD_CACHE_PATH", cache.to_str().unwrap())
-        .args([
-            "-o",
-            bin.to_str().unwrap(),
-            "-l",
-            &format!("0x{load:x}"),
-            &format!("0x{address:x}"),
-        ])
+        .arg(hex(address))
         .assert()
         .success()
         .stdout(predicates::str::contains("(in debuginfo)"));
 }
 
-fn build_with_build_id(src: &Path, out: &Path, build_id: &str) {
-    let status = ProcessCommand::new("cc")
-        .args([
-            "-g",
-            "-O0",
-            &format!("-Wl,--build-id=0x{build_id}"),
-            src.to_str().unwrap(),
-            "-o",
-            out.to_str().unwrap(),
-        ])
-        .status()
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
+fn cli_fetches_debug_file_from_debuginfod_server() {
+    let workspace = Workspace::new();
+    let build_id = "1234567890abcdef1234567890abcdef12345678";
+    let binary = workspace.build_target_with_id("app", build_id);
+    let debug_path = workspace.split_debug("app");
+    let other_id = "fedcba0987654321fedcba0987654321fedcba09";
+    let other = workspace.build_target_with_id("other", other_id);
+    workspace.objcopy(&["--strip-debug", "other"]);
+
+    // The stand-in answers `other`'s build-id with `app`'s debug file.
+    let debug = fs::read(debug_path).unwrap();
+    let (server, requests) = serve_http(vec![
+        (format!("/buildid/{build_id}/debuginfo"), debug.clone()),
+        (format!("/buildid/{other_id}/debuginfo"), debug),
+    ]);
+
+    let address = symbol_addr(&binary, "fixture_target");
+    let load = text_addr(&binary);
+    let symbolize = |binary: &Path, urls: &str, cache: &Path, offline: bool| {
+        let mut command = atosl();
+        command
+            .env("DEBUGINFOD_URLS", urls)
+            .env("DEBUGINFOD_CACHE_PATH", cache)
+            .args([
+                "-o",
+                binary.to_str().unwrap(),
+                "-v",
+                "-l",
+                &hex(load),
+                &hex(address),
+            ]);
+        if offline {
+            command.arg("--offline");
+        }
+        command.assert().success()
+    };
+
+    let cache = workspace.join("cache");
+    symbolize(&binary, &server, &cache, false)
+        .stdout(predicates::str::contains("(in debuginfo) (f.c:1)"));
+    assert!(cache.join(build_id).join("debuginfo").is_file());
+
+    // Cached: no server needed.
+    symbolize(&binary, "http://127.0.0.1:9", &cache, false)
+        .stdout(predicates::str::contains("(in debuginfo) (f.c:1)"));
+
+    let requests_before = requests.load(std::sync::atomic::Ordering::SeqCst);
+    symbolize(&binary, &server, &workspace.join("empty"), true)
+        .stdout(predicates::str::contains("(in app)"));
+    assert_eq!(
+        requests.load(std::sync::atomic::Ordering::SeqCst),
+        requests_before
+    );
+
+    symbolize(&other, &server, &cache, false)
+        .stdout(predicates::str::contains("(in other)"))
+        .stderr(predicates::str::contains(format!(
+            "does not carry build-id {other_id}"
+        )));
+    assert!(!cache.join(other_id).join("debuginfo").exists());
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
+fn cli_serves_debug_directory_over_debuginfod() {
+    // Paths that are not ASCII must not cost the X-DEBUGINFOD-FILE header, or
+    // the server.
+    let workspace = Workspace::new();
+    let build_id = "0123456789abcdef0123456789abcdef01234567";
+    let built = workspace.build_target_with_id("débug/app", build_id);
+    let debug_path = workspace.split_debug("débug/app");
+    let out = workspace.join("débug");
+    let binary = workspace.join("app");
+    fs::copy(&built, &binary).unwrap();
+
+    let (_server, banner) = ServerProcess::start(&[
+        "serve-debuginfod",
+        "--dir",
+        out.to_str().unwrap(),
+        "--listen",
+        "127.0.0.1:0",
+    ]);
+    let url = banner.trim().rsplit(' ').next().unwrap().to_string();
+    assert!(
+        banner.starts_with("serving 1 module ids on http://"),
+        "{banner}"
+    );
+
+    let address = symbol_addr(&binary, "fixture_target");
+    atosl_for(&binary)
+        .env("DEBUGINFOD_URLS", &url)
+        .env("DEBUGINFOD_CACHE_PATH", workspace.join("cache"))
+        .arg(hex(address))
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("(in debuginfo) (f.c:1)"));
+
+    let response = ureq::get(&format!("{url}/buildid/{build_id}/debuginfo"))
+        .call()
         .unwrap();
-    assert!(status.success(), "failed to build {}", out.display());
+    assert_eq!(
+        response.header("X-DEBUGINFOD-FILE").unwrap(),
+        debug_path.to_str().unwrap().replace("é", "%C3%A9")
+    );
+
+    let get = |path: String| -> Result<Vec<u8>, u16> {
+        match ureq::get(&format!("{url}{path}")).call() {
+            Ok(response) => {
+                let mut body = Vec::new();
+                std::io::Read::read_to_end(&mut response.into_reader(), &mut body).unwrap();
+                Ok(body)
+            }
+            Err(ureq::Error::Status(status, _)) => Err(status),
+            Err(err) => panic!("{err}"),
+        }
+    };
+    let source = workspace.join("f.c");
+    assert_eq!(
+        get(format!("/buildid/{build_id}/executable")),
+        Ok(fs::read(&built).unwrap())
+    );
+    assert_eq!(
+        get(format!("/buildid/{build_id}/source{}", source.display())),
+        Ok(fs::read(&source).unwrap())
+    );
+    // Only sources the DWARF names are served.
+    assert_eq!(
+        get(format!("/buildid/{build_id}/source/etc/passwd")),
+        Err(404)
+    );
+    assert_eq!(get("/buildid/ffff/debuginfo".to_string()), Err(404));
 }
 
-fn symbol_addr(path: &Path, name: &str) -> u64 {
-    let bytes = fs::read(path).unwrap();
-    let object = object::File::parse(bytes.as_slice()).unwrap();
-    object
-        .symbols()
-        .chain(object.dynamic_symbols())
-        .find(|symbol| symbol.name().map(|n| n.contains(name)).unwrap_or(false))
-        .map(|symbol| symbol.address())
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
+fn cli_serves_symbolication_requests_over_http() {
+    let workspace = Workspace::new();
+    let build_id = "89abcdef0123456789abcdef0123456789abcdef";
+    let binary = workspace.build_target_with_id("symbols/app", build_id);
+
+    let (_server, banner) = ServerProcess::start(&[
+        "serve",
+        "--symbols",
+        workspace.join("symbols").to_str().unwrap(),
+        "--listen",
+        "127.0.0.1:0",
+        "--offline",
+    ]);
+    let url = banner.trim().rsplit(' ').next().unwrap().to_string();
+    assert!(banner.starts_with("symbolicating on http://"), "{banner}");
+
+    let request = serde_json::json!({
+        "modules": [
+            {
+                "id": build_id,
+                "load_address": hex(text_addr(&binary)),
+                "frames": [hex(symbol_addr(&binary, "fixture_target"))],
+            },
+            { "id": "ffff", "load_address": 0, "frames": [1] },
+        ],
+    })
+    .to_string();
+    for _ in 0..2 {
+        let response = ureq::post(&format!("{url}/symbolicate"))
+            .send_string(&request)
+            .unwrap()
+            .into_string()
+            .unwrap();
+        let response = serde_json::from_str::<serde_json::Value>(&response).unwrap();
+        let frame = &response["modules"][0]["frames"][0];
+        assert_eq!(frame["status"], "resolved", "{response}");
+        assert_eq!(frame["symbol"], "fixture_target");
+        assert_eq!(frame["location"]["line"], 1);
+        assert_eq!(
+            response["modules"][0]["object_path"],
+            binary.to_str().unwrap()
+        );
+        assert!(response["modules"][1]["error"].is_string(), "{response}");
+    }
+
+    let metrics = ureq::get(&format!("{url}/metrics"))
+        .call()
         .unwrap()
+        .into_string()
+        .unwrap();
+    // The second request reused the module loaded by the first.
+    assert!(
+        metrics.contains("atosl_module_cache_hits_total 1\n"),
+        "{metrics}"
+    );
+    assert!(
+        metrics.contains("atosl_frames_resolved_total 2\n"),
+        "{metrics}"
+    );
+    assert_eq!(
+        ureq::get(&format!("{url}/health")).call().unwrap().status(),
+        200
+    );
 }
 
-fn text_addr(path: &Path) -> u64 {
-    let bytes = fs::read(path).unwrap();
-    let object = object::File::parse(bytes.as_slice()).unwrap();
-    object
-        .section_by_name(".text")
-        .or_else(|| object.section_by_name("__text"))
-        .map(|section| section.address())
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
+fn cli_answers_llvm_symbolizer_requests_over_stdio() {
+    use std::io::{BufRead, BufReader, Write};
+
+    let workspace = Workspace::new();
+    let binary = workspace.build(
+        "f.c",
+        "int fixture_counter = 3;\nint fixture_target(void){return fixture_counter;}\nint main(void){return fixture_target();}\n",
+        "app",
+        &["-Wl,--build-id=0x00112233"],
+    );
+    let code = symbol_addr(&binary, "fixture_target");
+    let data = symbol_addr(&binary, "fixture_counter");
+
+    let mut server = ServerProcess(
+        ProcessCommand::new(assert_cmd::cargo::cargo_bin("atosl"))
+            .arg("--server-stdio")
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let mut stdin = server.0.stdin.take().unwrap();
+    let mut stdout = BufReader::new(server.0.stdout.take().unwrap());
+    // Each answer must arrive while the process is still waiting for more.
+    let mut ask = |request: String, lines: usize| {
+        writeln!(stdin, "{request}").unwrap();
+        stdin.flush().unwrap();
+        (0..lines)
+            .map(|_| {
+                let mut line = String::new();
+                stdout.read_line(&mut line).unwrap();
+                line
+            })
+            .collect::<String>()
+    };
+
+    let object = binary.to_str().unwrap();
+    assert_eq!(
+        ask(format!("CODE {object} {}", hex(code)), 3),
+        format!(
+            "fixture_target\n{}:2:25\n\n",
+            workspace.join("f.c").display()
+        )
+    );
+    assert_eq!(
+        ask(format!("DATA {object} {}", hex(data + 2)), 3),
+        format!("fixture_counter\n{data} 4\n\n")
+    );
+    assert_eq!(ask(format!("CODE {object} zzz"), 3), "??\n??:0:0\n\n");
+    let report = ask(
+        serde_json::json!({ "object": object, "addresses": [hex(code), " 0xzz"] }).to_string(),
+        1,
+    );
+    let report = serde_json::from_str::<serde_json::Value>(&report).unwrap();
+    assert_eq!(report["frames"][0]["symbol"], "fixture_target", "{report}");
+    assert_eq!(report["frames"][0]["location"]["line"], 2);
+    assert_eq!(report["frames"][0]["location"]["column"], 25);
+    assert!(report["frames"][1]["error"]
+        .as_str()
         .unwrap()
+        .starts_with("invalid address \" 0xzz\""));
+}
+
+#[test]
+fn library_symbolizer_is_shared_across_threads() {
+    let workspace = Workspace::new();
+    let binary = workspace.build(
+        "f.c",
+        "int fixture_left(void){return 1;}\nint fixture_right(void){return 2;}\nint main(void){return fixture_left()+fixture_right();}\n",
+        "app",
+        &[],
+    );
+
+    let symbolizer = std::sync::Arc::new(
+        atosl::Symbolizer::open(
+            &binary,
+            &atosl::OpenOptions {
+                load_address: text_addr(&binary),
+                ..Default::default()
+            },
+        )
+        .unwrap(),
+    );
+    assert_eq!(symbolizer.object_info().object_name, "app");
+    assert_eq!(symbolizer.selected_slice(), None);
+
+    let threads = ["fixture_left", "fixture_right"].map(|name| {
+        let symbolizer = std::sync::Arc::clone(&symbolizer);
+        let address = symbol_addr(&binary, name);
+        std::thread::spawn(move || match symbolizer.symbolize(address) {
+            atosl::SymbolizeOutcome::Resolved(frame) => frame.symbol,
+            outcome => panic!("{outcome:?}"),
+        })
+    });
+    let names = threads.map(|thread| thread.join().unwrap());
+    assert_eq!(names, ["fixture_left", "fixture_right"]);
+}
+
+#[test]
+#[cfg_attr(
+    not(target_os = "linux"),
+    ignore = "names an ELF section __objc_methname"
+)]
+fn cli_reports_objc_selector_from_register() {
+    // ELF lets us name a section __objc_methname, which is all the selector
+    // lookup needs; the register is the host's objc_msgSend selector argument.
+    let register = if cfg!(target_arch = "aarch64") {
+        "x1"
+    } else {
+        "rsi"
+    };
+
+    let workspace = Workspace::new();
+    let binary = workspace.build(
+        "f.c",
+        r#"
+__attribute__((section("__objc_methname"), used))
+const char fixture_selector[] = "viewDidLoad";
+int fixture_target(void){return 7;}
+int main(void){return fixture_target();}
+"#,
+        "app",
+        &[],
+    );
+    let address = hex(symbol_addr(&binary, "fixture_target"));
+    let selector = symbol_addr(&binary, "fixture_selector");
+
+    let parsed = json_output(atosl_for(&binary).args([
+        "--register",
+        &format!("{register}={}", hex(selector)),
+        "--format",
+        "json",
+        &address,
+        &address,
+    ]));
+    assert_eq!(parsed["frames"][0]["selector"], "viewDidLoad");
+    assert!(parsed["frames"][1].get("selector").is_none());
+}
+
+#[test]
+fn cli_reads_objc_selector_through_chained_fixup_selref() {
+    // An arm64 image whose __objc_selrefs slot is still the on-disk chained
+    // rebase (DYLD_CHAINED_PTR_64_OFFSET, next = 1) of the selector's offset
+    // from __TEXT, and a register that points at the slot.
+    let workspace = Workspace::new();
+    let binary = workspace.join("app");
+    let mut selref = (1u64 << 51 | 0x1f00).to_le_bytes().to_vec();
+    selref.resize(16, 0);
+    MachO::new(macho::MH_EXECUTE, macho::CPU_TYPE_ARM64, 0)
+        .segment(
+            "__TEXT",
+            0x1_0000_0000,
+            vec![
+                Section::new("__text", 0x1_0000_1000, [0u8; 16]),
+                Section::new("__objc_methname", 0x1_0000_1f00, *b"viewDidLoad\0"),
+            ],
+        )
+        .segment(
+            "__DATA",
+            0x1_0000_4000,
+            vec![Section::new("__objc_selrefs", 0x1_0000_4000, selref)],
+        )
+        .symbol(
+            "_fixture_target",
+            macho::N_SECT | macho::N_EXT,
+            1,
+            0x1_0000_1000,
+        )
+        .write(&binary);
+
+    // The selector belongs to the crashing frame whether or not it resolves.
+    let parsed = json_output(atosl().args([
+        "-o",
+        binary.to_str().unwrap(),
+        "-l",
+        "0x104000000",
+        "--register",
+        "x1=0x104004000",
+        "--format",
+        "json",
+        "0x104001004",
+        "0x104000800",
+    ]));
+    let frames = parsed["frames"].as_array().unwrap();
+    assert_eq!(frames[0]["symbol"], "_fixture_target");
+    assert_eq!(frames[0]["selector"], "viewDidLoad");
+    assert!(frames[1].get("selector").is_none());
+
+    let parsed = json_output(atosl().args([
+        "-o",
+        binary.to_str().unwrap(),
+        "-l",
+        "0x104000000",
+        "--register",
+        "x1=0x104004000",
+        "--format",
+        "json",
+        "0x104000800",
+    ]));
+    assert_eq!(parsed["frames"][0]["status"], "unresolved");
+    assert_eq!(parsed["frames"][0]["selector"], "viewDidLoad");
+}
+
+#[test]
+fn cli_resolves_local_symbol_of_image_in_split_dyld_cache() {
+    // The image lives in subcache .01; its own symbol table only exports
+    // UIApplicationMain, and the helper after it is in the .symbols file.
+    let workspace = Workspace::new();
+    let cache = workspace.join("dyld_shared_cache_arm64e");
+    let mut image = MachO::new(macho::MH_DYLIB, macho::CPU_TYPE_ARM64, 2);
+    image
+        .segment(
+            "__TEXT",
+            0x1_8000_5000,
+            vec![Section::new("__text", 0x1_8000_5400, [0u8; 0x100])],
+        )
+        .symbol(
+            "_UIApplicationMain",
+            macho::N_SECT | macho::N_EXT,
+            1,
+            0x1_8000_5400,
+        );
+    write_dyld_cache(
+        &cache,
+        &image,
+        0x1_8000_5000,
+        "/System/Library/PrivateFrameworks/UIKitCore.framework/UIKitCore",
+        &[("_local_helper", 0x1_8000_5480)],
+    );
+
+    let output = text_output(atosl().args([
+        "-o",
+        cache.to_str().unwrap(),
+        "--image",
+        "UIKitCore",
+        "-l",
+        "0x180005000",
+        "0x180005404",
+        "0x180005490",
+    ]));
+    assert_eq!(
+        output,
+        "_UIApplicationMain (in UIKitCore) + 4\n_local_helper (in UIKitCore) + 16\n"
+    );
 }
 
-struct Fixture {
-    _tempdir: TempDir,
-    binary_path: PathBuf,
+#[test]
+fn cli_rejects_image_that_selects_nothing() {
+    let workspace = Workspace::new();
+    // A cache header with 64-bit mapping entries and no images.
+    let mut cache = vec![0u8; 0x200];
+    cache[..16].copy_from_slice(b"dyld_v1   arm64\0");
+    cache[0x10..0x14].copy_from_slice(&0x1c8u32.to_le_bytes());
+    let cache_path = workspace.write("dyld_shared_cache_arm64e", cache);
+
+    let run = |object: &Path, image: Option<&str>| {
+        let mut command = atosl();
+        command.args(["-o", object.to_str().unwrap(), "-l", "0x1000"]);
+        if let Some(image) = image {
+            command.args(["--image", image]);
+        }
+        command.arg("0x1234").assert().failure()
+    };
+
+    run(&cache_path, None).stderr(predicates::str::contains(
+        "pass --image <install name> or --uuid to select one",
+    ));
+    run(&cache_path, Some("UIKitCore")).stderr(predicates::str::contains(
+        "no image in the dyld shared cache matched image=UIKitCore uuid=-",
+    ));
+
+    let binary = workspace.build_target("fixture_bin");
+    run(&binary, Some("UIKitCore")).stderr(predicates::str::contains(
+        "--image selects an image inside a dyld shared cache",
+    ));
 }
 
-impl Fixture {
-    fn build() -> anyhow::Result<Self> {
-        let tempdir = tempfile::tempdir()?;
-        let source_path = tempdir.path().join("fixture.c");
-        let binary_path = tempdir.path().join("fixture_bin");
-        fs::write(
-            &source_path,
-            r#"
-int fixture_target(void) {
-    return 42;
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs objdump and ELF")]
+fn cli_names_plt_entries_after_their_callee() {
+    let workspace = Workspace::new();
+    let binary = workspace.build(
+        "f.c",
+        "#include <stdio.h>\nint main(void){puts(\"hi\");return 0;}\n",
+        "app",
+        &[],
+    );
+
+    // objdump labels PLT entries independently of atosl, e.g. "<puts@plt>:".
+    let disassembly = ProcessCommand::new("objdump")
+        .args(["-d", binary.to_str().unwrap()])
+        .output()
+        .unwrap();
+    let disassembly = String::from_utf8(disassembly.stdout).unwrap();
+    let plt = disassembly
+        .lines()
+        .find(|line| line.ends_with("<puts@plt>:"))
+        .and_then(|line| line.split_whitespace().next())
+        .map(|address| u64::from_str_radix(address, 16).unwrap())
+        .expect("puts@plt entry");
+
+    let parsed = json_output(atosl().args([
+        "-o",
+        binary.to_str().unwrap(),
+        "-f",
+        "-l",
+        "0",
+        "--format",
+        "json",
+        &hex(plt + 4),
+    ]));
+    assert_eq!(parsed["frames"][0]["symbol"], "puts@plt");
+    assert_eq!(parsed["frames"][0]["resolver"], "stub");
+    assert_eq!(parsed["frames"][0]["offset"], 4);
+
+    // The PLT is still read from the binary once DWARF comes from its
+    // `.debug` file, which keeps no PLT contents.
+    workspace.split_debug("app");
+    workspace.objcopy(&["--add-gnu-debuglink=app.debug", "app"]);
+    let parsed = json_output(atosl().args([
+        "-o",
+        binary.to_str().unwrap(),
+        "-f",
+        "-l",
+        "0",
+        "--format",
+        "json",
+        &hex(plt + 4),
+    ]));
+    assert_eq!(parsed["frames"][0]["object_name"], "app.debug");
+    assert_eq!(parsed["frames"][0]["symbol"], "puts@plt");
 }
 
-int main(void) {
-    return fixture_target();
+#[test]
+fn cli_names_macho_symbol_stubs_from_binary_with_dsym() {
+    let workspace = Workspace::new();
+    let binary = workspace.join("app");
+    let uuid = [0x5a; 16];
+    let text = || Section::new("__text", 0x1_0000_1000, [0u8; 16]);
+    // Two 6-byte stubs, bound through indirect symbol table entries 0 and 1.
+    let stubs = || Section {
+        flags: macho::S_SYMBOL_STUBS | macho::S_ATTR_PURE_INSTRUCTIONS,
+        reserved2: 6,
+        ..Section::new("__stubs", 0x1_0000_1010, [0u8; 12])
+    };
+    MachO::new(macho::MH_EXECUTE, macho::CPU_TYPE_X86_64, 3)
+        .segment("__TEXT", 0x1_0000_0000, vec![text(), stubs()])
+        .symbol("_main", macho::N_SECT | macho::N_EXT, 1, 0x1_0000_1000)
+        .symbol("_free", macho::N_EXT, 0, 0)
+        .symbol("_malloc", macho::N_EXT, 0, 0)
+        .indirect_symbol(1)
+        .indirect_symbol(2)
+        .uuid(uuid)
+        .write(&binary);
+
+    let symbolize = |extra: &[&str]| {
+        text_output(
+            atosl()
+                .args(["-o", binary.to_str().unwrap(), "-l", "0x100000000"])
+                .args(extra)
+                .arg("0x100001018"),
+        )
+    };
+    assert_eq!(symbolize(&[]), "symbol stub for: malloc (in app) + 2\n");
+
+    // A dSYM keeps the stub section's header but not the indirect symbol
+    // table, so the stubs still come from the binary.
+    let dsym = workspace.join("symbols/app.dSYM/Contents/Resources/DWARF/app");
+    fs::create_dir_all(dsym.parent().unwrap()).unwrap();
+    MachO::new(macho::MH_DSYM, macho::CPU_TYPE_X86_64, 3)
+        .segment("__TEXT", 0x1_0000_0000, vec![text(), stubs()])
+        .symbol("_main", macho::N_SECT | macho::N_EXT, 1, 0x1_0000_1000)
+        .uuid(uuid)
+        .write(&dsym);
+    let symbols = workspace.join("symbols");
+    assert_eq!(
+        symbolize(&["--dsym-dir", symbols.to_str().unwrap()]),
+        "symbol stub for: malloc (in app) + 2\n"
+    );
 }
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs ELF symbol sizes")]
+fn cli_reports_address_past_symbol_end_as_unresolved() {
+    // The over-aligned function leaves padding after fixture_target that no
+    // symbol covers; it must not be blamed on fixture_target.
+    let workspace = Workspace::new();
+    workspace.write(
+        "f.c",
+        r#"
+int fixture_target(void){return 7;}
+__attribute__((aligned(256))) int fixture_aligned(void){return 8;}
+int main(void){return fixture_target() + fixture_aligned();}
 "#,
-        )?;
+    );
+    // No -g: exercise the symbol-table resolver rather than DWARF.
+    workspace.cc(&["-O0", "f.c", "-o", "app"]);
+    let binary = workspace.join("app");
+    let address = symbol_addr(&binary, "fixture_target");
+    let size = symbol_size(&binary, "fixture_target");
+
+    let parsed = json_output(atosl_for(&binary).args([
+        "--format",
+        "json",
+        &hex(address + size - 1),
+        &hex(address + size),
+    ]));
+    assert_eq!(parsed["frames"][0]["symbol"], "fixture_target");
+    assert_eq!(parsed["frames"][1]["status"], "unresolved");
+    assert!(parsed["frames"][1]["error"]
+        .as_str()
+        .unwrap()
+        .contains("not inside any symbol"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs GNU ld version scripts")]
+fn cli_falls_back_to_dynamic_symbols_when_symtab_is_stripped() {
+    let workspace = Workspace::new();
+    workspace.write("lib.c", "int fixture_export(int x){return x * 3;}\n");
+    workspace.write(
+        "lib.map",
+        "FIXTURE_1.0 { global: fixture_export; local: *; };\n",
+    );
+    workspace.cc(&[
+        "-shared",
+        "-fPIC",
+        "-O0",
+        "-Wl,--version-script=lib.map",
+        "lib.c",
+        "-o",
+        "libfixture.so",
+    ]);
+    let lib = workspace.join("libfixture.so");
+    assert!(ProcessCommand::new("strip")
+        .args(["--strip-all", lib.to_str().unwrap()])
+        .status()
+        .unwrap()
+        .success());
+    let address = symbol_addr(&lib, "fixture_export");
+
+    let parsed = json_output(atosl_for(&lib).args(["--format", "json", &hex(address + 1)]));
+    assert_eq!(parsed["frames"][0]["symbol"], "fixture_export@@FIXTURE_1.0");
+    assert_eq!(parsed["frames"][0]["resolver"], "dynamic_symbol_table");
+    assert_eq!(parsed["frames"][0]["offset"], 1);
+}
+
+#[test]
+#[cfg_attr(
+    not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )),
+    ignore = "assembles x86_64 or aarch64 code for ELF"
+)]
+fn cli_pairs_line_table_location_with_symbol_table_name() {
+    // Assembly gets a DWARF line table; without `.type` the assembler emits
+    // no subprogram DIE for the function either.
+    let body = if cfg!(target_arch = "aarch64") {
+        "    mov w0, #7\n    ret\n"
+    } else {
+        "    movl $7, %eax\n    ret\n"
+    };
+
+    let workspace = Workspace::new();
+    workspace.write(
+        "seven.s",
+        format!(".text\n.globl fixture_seven\nfixture_seven:\n{body}"),
+    );
+    let binary = workspace.build(
+        "main.c",
+        "int fixture_seven(void);\nint main(void){return fixture_seven();}\n",
+        "seven",
+        &["seven.s"],
+    );
+    let address = symbol_addr(&binary, "fixture_seven");
+
+    let parsed = json_output(atosl_for(&binary).args(["--format", "json", &hex(address)]));
+    let frame = &parsed["frames"][0];
+    assert_eq!(frame["symbol"], "fixture_seven");
+    assert_eq!(frame["resolver"], "dwarf_line_symbol_table");
+    assert_eq!(frame["offset"], 0);
+    assert!(frame["location"]["file"]
+        .as_str()
+        .unwrap()
+        .ends_with("seven.s"));
+    assert_eq!(frame["location"]["line"], 4);
+}
+
+#[test]
+#[cfg_attr(not(target_os = "macos"), ignore = "needs the Apple linker")]
+fn cli_follows_debug_map_to_object_file_dwarf() {
+    // Linked without dsymutil: the executable only carries N_OSO stabs that
+    // point at main.o, which holds the DWARF.
+    let workspace = Workspace::new();
+    workspace.write(
+        "main.c",
+        "int fixture_mapped(int x) {\n    return x + 5;\n}\nint main(void) { return fixture_mapped(1); }\n",
+    );
+    workspace.cc(&["-g", "-O0", "-c", "main.c", "-o", "main.o"]);
+    workspace.cc(&["main.o", "-o", "mapped"]);
+    let binary = workspace.join("mapped");
+    let address = symbol_addr(&binary, "fixture_mapped");
+
+    let parsed = json_output(atosl().args([
+        "-o",
+        binary.to_str().unwrap(),
+        "-l",
+        "0x100000000",
+        "--format",
+        "json",
+        &hex(address),
+    ]));
+    let frame = &parsed["frames"][0];
+    assert_eq!(frame["symbol"], "fixture_mapped");
+    assert_eq!(frame["resolver"], "dwarf");
+    assert!(frame["location"]["file"]
+        .as_str()
+        .unwrap()
+        .ends_with("main.c"));
+    assert_eq!(frame["location"]["line"], 1);
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "transplants ELF DWARF")]
+fn cli_loads_only_the_debug_map_object_a_lookup_needs() {
+    // Two Mach-O objects carrying the DWARF of a linked ELF program, at the
+    // addresses it describes, and an executable whose debug map links
+    // fixture_target from the first and main from the second elsewhere.
+    let workspace = Workspace::new();
+    let elf = workspace.build_target("elf");
+    let (text_address, text) = elf_section(&elf, ".text");
+    let target = symbol_addr(&elf, "fixture_target");
+    let main = symbol_addr(&elf, "main");
+    for name in ["a.o", "b.o"] {
+        MachO::new(macho::MH_OBJECT, macho::CPU_TYPE_X86_64, 3)
+            .segment(
+                "",
+                0,
+                vec![Section::new("__text", text_address, text.clone())],
+            )
+            .segment("__DWARF", 0, dwarf_sections(&elf))
+            .symbol("_fixture_target", macho::N_SECT | macho::N_EXT, 1, target)
+            .symbol("_main", macho::N_SECT | macho::N_EXT, 1, main)
+            .write(&workspace.join(name));
+    }
+
+    let binary = workspace.join("app");
+    let linked = 0x1_0000_1000;
+    let mut app = MachO::new(macho::MH_EXECUTE, macho::CPU_TYPE_X86_64, 3);
+    app.segment(
+        "__TEXT",
+        0x1_0000_0000,
+        vec![Section::new("__text", linked, [0u8; 0x40])],
+    );
+    for (object, name, address, size) in [
+        ("a.o", "_fixture_target", linked, 0x20),
+        ("b.o", "_main", linked + 0x20, 0x20),
+    ] {
+        let path = workspace.join(object);
+        app.symbol(path.to_str().unwrap(), macho::N_OSO, 0, 0)
+            .symbol(name, macho::N_FUN, 1, address)
+            .symbol("", macho::N_FUN, 0, size)
+            .symbol("", macho::N_SO, 0, 0);
+    }
+    app.symbol("_fixture_target", macho::N_SECT | macho::N_EXT, 1, linked)
+        .symbol("_main", macho::N_SECT | macho::N_EXT, 1, linked + 0x20)
+        .write(&binary);
 
-        let status = ProcessCommand::new("cc")
+    let assert = atosl()
+        .args(["-v", "-o", binary.to_str().unwrap(), "-l", "0x100000000"])
+        .args(["--format", "json", &hex(linked + 4)])
+        .assert()
+        .success();
+    let output = assert.get_output();
+    let parsed: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let frame = &parsed["frames"][0];
+    assert_eq!(frame["symbol"], "fixture_target");
+    assert_eq!(frame["resolver"], "dwarf");
+    assert!(frame["location"]["file"].as_str().unwrap().ends_with("f.c"));
+    assert_eq!(frame["location"]["line"], 1);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("debug_map: loaded") && stderr.contains("a.o"));
+    assert!(!stderr.contains("b.o"), "{stderr}");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs ELF program headers")]
+fn cli_rebases_elf_onto_lowest_load_segment_with_module_base() {
+    let workspace = Workspace::new();
+    workspace.write("lib.c", "int fixture_based(int x){return x * 5;}\n");
+    workspace.cc(&["-shared", "-fPIC", "-O0", "lib.c", "-o", "libbased.so"]);
+    let lib = workspace.join("libbased.so");
+
+    // A shared object's lowest PT_LOAD sits at p_vaddr 0, so a runtime address
+    // is the module base (as in /proc/<pid>/maps) plus the static address.
+    let base = 0x7f12_3400_0000u64;
+    let address = base + symbol_addr(&lib, "fixture_based") + 2;
+
+    let assert = atosl()
+        .args([
+            "-v",
+            "--module-base",
+            "-o",
+            lib.to_str().unwrap(),
+            "-l",
+            &hex(base),
+            &hex(address),
+        ])
+        .assert()
+        .success();
+    let output = assert.get_output();
+    let stdout = String::from_utf8(output.stdout.clone()).unwrap();
+    let stderr = String::from_utf8(output.stderr.clone()).unwrap();
+    assert!(stdout.contains("fixture_based (in libbased.so) + 2"));
+    assert!(stderr.contains("address_base: lowest PT_LOAD p_vaddr 0x0"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs a non-PIE ELF executable")]
+fn cli_translates_file_offsets_through_program_headers() {
+    // A non-PIE executable maps its segments at 0x400000 and up, so virtual
+    // addresses and file offsets differ.
+    let workspace = Workspace::new();
+    workspace.write(
+        "main.c",
+        "int fixture_offset(int x){return x + 9;}\nint main(void){return fixture_offset(1);}\n",
+    );
+    workspace.cc(&["-no-pie", "-g", "-O0", "main.c", "-o", "offsets"]);
+    let binary = workspace.join("offsets");
+
+    let address = symbol_addr(&binary, "fixture_offset");
+    let bytes = fs::read(&binary).unwrap();
+    let object = object::File::parse(bytes.as_slice()).unwrap();
+    let segment = object
+        .segments()
+        .find(|segment| (segment.address()..segment.address() + segment.size()).contains(&address))
+        .unwrap();
+    let offset = address - segment.address() + segment.file_range().0;
+    assert_ne!(offset, address);
+
+    let symbolize = || {
+        text_output(atosl().args([
+            "--translate-offsets",
+            "-o",
+            binary.to_str().unwrap(),
+            "-l",
+            "0",
+            &hex(offset + 1),
+            "0xffffff00",
+        ]))
+    };
+    let stdout = symbolize();
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert_eq!(lines[0], "fixture_offset (in offsets) (main.c:1)");
+    assert!(lines[1].contains("file offset 0xffffff00 is not inside any loadable segment"));
+
+    // Offsets are into the binary, whose segments still carry their file
+    // contents once the DWARF comes from its `.debug` file.
+    workspace.split_debug("offsets");
+    workspace.objcopy(&["--add-gnu-debuglink=offsets.debug", "offsets"]);
+    let stdout = symbolize();
+    assert_eq!(
+        stdout.lines().next(),
+        Some("fixture_offset (in offsets.debug) (main.c:1)")
+    );
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs ELF relocatable objects")]
+fn cli_symbolizes_relocatable_object_per_section() {
+    // With -ffunction-sections every function gets its own section at
+    // address 0, as in a kernel module.
+    let workspace = Workspace::new();
+    workspace.write(
+        "module.c",
+        "int fixture_first(int x) {\n    return x + 1;\n}\nint fixture_second(int x) {\n    return x - 1;\n}\n",
+    );
+    workspace.cc(&[
+        "-g",
+        "-O0",
+        "-c",
+        "-ffunction-sections",
+        "module.c",
+        "-o",
+        "module.o",
+    ]);
+
+    let parsed = json_output(atosl().args([
+        "-o",
+        workspace.join("module.o").to_str().unwrap(),
+        "-l",
+        "0",
+        "--section",
+        ".text.fixture_first=0xffffffffc0a00000",
+        "--section",
+        ".text.fixture_second=0xffffffffc0b00000",
+        "--format",
+        "json",
+        "0xffffffffc0a00000",
+        "0xffffffffc0b00000",
+        "0xffffffffc0c00000",
+    ]));
+    let frames = parsed["frames"].as_array().unwrap();
+    assert_eq!(frames[0]["symbol"], "fixture_first");
+    assert_eq!(frames[0]["location"]["line"], 1);
+    assert_eq!(frames[1]["symbol"], "fixture_second");
+    assert_eq!(frames[1]["location"]["line"], 4);
+    assert!(frames[2]["error"]
+        .as_str()
+        .unwrap()
+        .contains("not inside any mapped section"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs ELF relocatable objects")]
+fn cli_decodes_kernel_oops_against_vmlinux_and_modules() {
+    // An executable stands in for vmlinux and a relocatable object for a
+    // module; the oops names frames by symbol, offset and size.
+    let workspace = Workspace::new();
+    let vmlinux = workspace.build(
+        "core.c",
+        "int fixture_kernel_fn(int x) {\n    return x + 1;\n}\nint main(void) {\n    return fixture_kernel_fn(1);\n}\n",
+        "vmlinux",
+        &[],
+    );
+    let module = workspace.build(
+        "module.c",
+        "int fixture_mod_fn(int x) {\n    return x - 1;\n}\n",
+        "lib/modules/extra/fixture-mod.ko",
+        &["-c"],
+    );
+
+    let kernel_fn = symbol_addr(&vmlinux, "fixture_kernel_fn");
+    let kernel_fn_size = symbol_size(&vmlinux, "fixture_kernel_fn");
+    let mod_fn_size = symbol_size(&module, "fixture_mod_fn");
+    let kaslr = 0xffffffff80000000u64;
+    let oops = format!(
+        "[   12.345680] RIP: 0010:fixture_kernel_fn+0x0/{kernel_fn_size:#x}\n\
+[   12.345690] Call Trace:\n\
+[   12.345692]  fixture_mod_fn+0x0/{mod_fn_size:#x} [fixture_mod]\n\
+[   12.345693]  [<{:x}>] fixture_kernel_fn+0x0/{kernel_fn_size:#x}\n\
+[   12.345694]  [<{:x}>]\n\
+[   12.345695]  unknown_fn+0x4/0x10\n\
+[   12.345696] ééfixture_kernel_fn+0x0/{kernel_fn_size:#x} «»\n",
+        kernel_fn + kaslr,
+        kernel_fn + kaslr,
+    );
+    // A stray byte from a corrupted console costs only its own character.
+    let mut oops = oops.into_bytes();
+    oops.extend_from_slice(b"[   12.345697] \xff garbage\n");
+
+    let stdout = text_output(
+        atosl()
             .args([
-                "-g",
-                "-O0",
-                source_path.to_str().unwrap(),
                 "-o",
-                binary_path.to_str().unwrap(),
+                vmlinux.to_str().unwrap(),
+                "--kernel-oops",
+                "--module-dir",
+                workspace.join("lib/modules").to_str().unwrap(),
             ])
-            .status()?;
+            .write_stdin(oops),
+    );
+    let lines = stdout.lines().collect::<Vec<_>>();
+    assert!(lines[0].starts_with("[   12.345680] RIP: 0010:fixture_kernel_fn ("));
+    assert!(lines[0].ends_with("core.c:1)"));
+    assert_eq!(lines[1], "[   12.345690] Call Trace:");
+    assert!(lines[2].starts_with("[   12.345692]  fixture_mod_fn ("));
+    assert!(lines[2].ends_with("module.c:1) [fixture_mod]"));
+    assert!(lines[3].contains("fixture_kernel_fn (") && lines[3].ends_with("core.c:1)"));
+    // The KASLR offset comes from line 3, which pairs an address with its symbol.
+    assert!(lines[4].starts_with("[   12.345694]  fixture_kernel_fn ("));
+    assert_eq!(lines[5], "[   12.345695]  unknown_fn+0x4/0x10");
+    assert!(lines[6].starts_with("[   12.345696] ééfixture_kernel_fn ("));
+    assert!(lines[6].ends_with("core.c:1) «»"));
+    assert_eq!(lines[7], "[   12.345697] \u{fffd} garbage");
+}
 
-        anyhow::ensure!(status.success(), "failed to build test fixture");
+#[test]
+#[cfg_attr(
+    not(all(target_os = "linux", target_arch = "x86_64")),
+    ignore = "builds an x86_64 ELF"
+)]
+fn cli_filters_elf_by_canonical_architecture() {
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = hex(symbol_addr(&binary, "fixture_target"));
+    let run = |arch: &str| atosl_for(&binary).args(["-a", arch, &address]).assert();
 
-        Ok(Self {
-            _tempdir: tempdir,
-            binary_path,
-        })
-    }
+    run("amd64")
+        .success()
+        .stdout(predicates::str::contains("fixture_target"));
+    run("riscv64").failure().stderr(predicates::str::contains(
+        "architecture mismatch: requested 'riscv64', actual 'x86_64'",
+    ));
+}
 
-    fn binary_path(&self) -> &Path {
-        &self.binary_path
-    }
+#[test]
+fn cli_strips_pointer_authentication_bits_before_lookup() {
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = symbol_addr(&binary, "fixture_target");
+    let signed = address | 0x002b_1c00_0000_0000;
 
-    fn symbol_address(&self, symbol_name: &str) -> anyhow::Result<u64> {
-        let bytes = fs::read(&self.binary_path)?;
-        let object = object::File::parse(bytes.as_slice())?;
-
-        object
-            .symbols()
-            .chain(object.dynamic_symbols())
-            .find(|symbol| {
-                symbol
-                    .name()
-                    .map(|name| name.contains(symbol_name))
-                    .unwrap_or(false)
-            })
-            .map(|symbol| symbol.address())
-            .ok_or_else(|| anyhow::anyhow!("symbol not found: {symbol_name}"))
+    for mask in [["--va-bits", "40"], ["--pac-mask", "0xffffff0000000000"]] {
+        let parsed = json_output(atosl_for(&binary).args(mask).args([
+            "--format",
+            "json",
+            &hex(signed),
+            &hex(address),
+        ]));
+        let frames = parsed["frames"].as_array().unwrap();
+        assert_eq!(frames[0]["requested_address"], signed);
+        assert_eq!(frames[0]["canonical_address"], address);
+        assert_eq!(frames[0]["symbol"], frames[1]["symbol"]);
+        assert!(frames[1].get("canonical_address").is_none());
     }
+}
 
-    fn load_address(&self) -> anyhow::Result<u64> {
-        let bytes = fs::read(&self.binary_path)?;
-        let object = object::File::parse(bytes.as_slice())?;
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "needs ELF symbol sizes")]
+fn cli_looks_up_return_addresses_at_the_call() {
+    // `fixture_caller` ends with a call to a noreturn function, so its return
+    // address is the first byte of whatever follows it.
+    let workspace = Workspace::new();
+    let binary = workspace.build(
+        "return.c",
+        "#include <stdlib.h>\n\n__attribute__((noinline, noreturn)) void fixture_fail(void) {\n    abort();\n}\n\n__attribute__((noinline)) void fixture_caller(void) {\n    fixture_fail();\n}\n\nint main(void) {\n    fixture_caller();\n}\n",
+        "return",
+        &["-O1"],
+    );
 
-        object
-            .section_by_name("__text")
-            .or_else(|| object.section_by_name(".text"))
-            .map(|section| section.address())
-            .ok_or_else(|| anyhow::anyhow!("text section not found"))
-    }
+    let return_address =
+        symbol_addr(&binary, "fixture_caller") + symbol_size(&binary, "fixture_caller");
+    let parsed = json_output(atosl_for(&binary).args([
+        "--frames-are-return-addresses",
+        "--format",
+        "json",
+        &format!("pc:{}", hex(return_address)),
+        &hex(return_address),
+    ]));
+    let frames = parsed["frames"].as_array().unwrap();
+    assert_ne!(frames[0]["symbol"], "fixture_caller");
+    assert_eq!(frames[0]["lookup_address"], return_address);
+    assert_eq!(frames[1]["requested_address"], return_address);
+    assert!(frames[1]["lookup_address"].as_u64().unwrap() < return_address);
+    assert_eq!(frames[1]["symbol"], "fixture_caller");
+    assert_eq!(frames[1]["location"]["line"], 8);
+}
+
+#[test]
+fn cli_accepts_atos_slide_offset_and_full_path() {
+    let workspace = Workspace::new();
+    let binary = workspace.build_target("fixture_bin");
+    let address = symbol_addr(&binary, "fixture_target");
+    let load_address = text_addr(&binary);
+    let slide = 0x10_0000;
+
+    let run =
+        |args: &[&str]| text_output(atosl().args(["-o", binary.to_str().unwrap()]).args(args));
+
+    let slid = run(&["-s", &hex(slide), &hex(address + slide)]);
+    assert!(
+        slid.starts_with("fixture_target (in fixture_bin) (f.c:"),
+        "{slid}"
+    );
+
+    let offset = run(&["-offset", &hex(address - load_address)]);
+    assert_eq!(offset, slid);
+
+    let full = run(&["-fullPath", "-s", &hex(slide), &hex(address + slide)]);
+    let source = workspace.join("f.c");
+    assert!(full.contains(&format!("({}:", source.display())), "{full}");
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "transplants ELF DWARF")]
+fn cli_locates_synthetic_dsym_by_uuid() {
+    // A Mach-O executable with only a symbol table, and a dSYM of the same
+    // UUID carrying the DWARF of a linked ELF program at its own addresses.
+    let workspace = Workspace::new();
+    let elf = workspace.build_target("elf");
+    let (text_address, text) = elf_section(&elf, ".text");
+    let target = symbol_addr(&elf, "fixture_target");
+    let image = |filetype, uuid, dwarf: Vec<Section>| {
+        let mut image = MachO::new(filetype, macho::CPU_TYPE_X86_64, 3);
+        image
+            .segment(
+                "__TEXT",
+                0,
+                vec![Section::new("__text", text_address, text.clone())],
+            )
+            .symbol("_fixture_target", macho::N_SECT | macho::N_EXT, 1, target)
+            .uuid(uuid);
+        if !dwarf.is_empty() {
+            image.segment("__DWARF", 0, dwarf);
+        }
+        image
+    };
+    let app = workspace.join("Build/Located.app");
+    fs::create_dir_all(&app).unwrap();
+    let binary = app.join("Located");
+    image(macho::MH_EXECUTE, [0x41; 16], Vec::new()).write(&binary);
+
+    let dsym_at = |bundle: &Path, uuid| {
+        let payload = bundle.join("Contents/Resources/DWARF/Located");
+        fs::create_dir_all(payload.parent().unwrap()).unwrap();
+        image(macho::MH_DSYM, uuid, dwarf_sections(&elf)).write(&payload);
+    };
+    let symbols = workspace.join("symbols");
+    dsym_at(&symbols.join("stale/Located.dSYM"), [0x42; 16]);
+    dsym_at(&symbols.join("nested/Located.dSYM"), [0x41; 16]);
+
+    let address = hex(target);
+    let symbolize = |extra: &[&str]| {
+        atosl()
+            .args(["-o", binary.to_str().unwrap(), "-l", "0"])
+            .args(extra)
+            .arg(&address)
+            .assert()
+            .success()
+    };
+    symbolize(&[]).stdout(predicates::str::contains(
+        "_fixture_target (in Located) + 0",
+    ));
+    symbolize(&["-v", "--dsym-dir", symbols.to_str().unwrap()])
+        .stdout(predicates::str::contains("(f.c:1)"))
+        .stderr(predicates::str::contains("nested/Located.dSYM"));
+
+    // The bundle beside the enclosing .app is found without a flag.
+    fs::rename(
+        symbols.join("nested/Located.dSYM"),
+        app.with_file_name("Located.app.dSYM"),
+    )
+    .unwrap();
+    symbolize(&[]).stdout(predicates::str::contains("(f.c:1)"));
+}
+
+#[test]
+#[cfg_attr(
+    not(target_os = "macos"),
+    ignore = "needs the Apple linker and dsymutil"
+)]
+fn cli_locates_dsym_for_stripped_macho_by_uuid() {
+    let workspace = Workspace::new();
+    workspace.write(
+        "located.c",
+        "int located_target(void) { return 7; }\nint main(void) { return located_target(); }\n",
+    );
+    fs::create_dir_all(workspace.join("Build/Located.app")).unwrap();
+    workspace.cc(&["-g", "-O0", "-c", "located.c", "-o", "located.o"]);
+    workspace.cc(&["located.o", "-o", "Build/Located.app/Located"]);
+    let app = workspace.join("Build/Located.app");
+    let binary = app.join("Located");
+    let symbols = workspace.join("symbols");
+    let dsym = symbols.join("nested/Located.dSYM");
+    let status = ProcessCommand::new("dsymutil")
+        .args([binary.to_str().unwrap(), "-o", dsym.to_str().unwrap()])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    // Without the object file the debug map cannot be followed, so only the
+    // dSYM can supply file and line.
+    fs::remove_file(workspace.join("located.o")).unwrap();
+
+    let address = hex(symbol_addr(&binary, "located_target"));
+    let symbolize = |extra: &[&str]| {
+        atosl_for(&binary)
+            .args(extra)
+            .arg(&address)
+            .assert()
+            .success()
+    };
+
+    symbolize(&[]).stdout(predicates::str::contains("located_target (in Located) + 0"));
+    symbolize(&["-v", "--dsym-dir", symbols.to_str().unwrap()])
+        .stdout(predicates::str::contains("(located.c:1)"))
+        .stderr(predicates::str::contains("locate: "));
+
+    // The same bundle beside the enclosing .app is found without a flag.
+    fs::rename(&dsym, app.with_file_name("Located.app.dSYM")).unwrap();
+    symbolize(&[]).stdout(predicates::str::contains("(located.c:1)"));
+}
+
+#[test]
+#[cfg_attr(not(target_os = "linux"), ignore = "build-ids need GNU ld")]
+fn cli_asks_symbol_command_for_missing_debug_file() {
+    let workspace = Workspace::new();
+    let build_id = "0123456789abcdef0123456789abcdef01234567";
+    let binary = workspace.build_target_with_id("app", build_id);
+    let debug_path = workspace.split_debug("app");
+
+    // Keep the debug file where no built-in locator looks.
+    let debug_file = workspace.join("store/app.debug");
+    fs::create_dir_all(debug_file.parent().unwrap()).unwrap();
+    fs::rename(debug_path, &debug_file).unwrap();
+
+    let script = workspace.write(
+        "locate.sh",
+        format!(
+            "#!/bin/sh\n[ \"$1\" = {build_id} ] || exit 1\n\
+             echo '{{\"'\"$1\"'\": {{\"DBGSymbolRichExecutable\": \"{}\"}}}}'\n",
+            debug_file.display()
+        ),
+    );
+    assert!(ProcessCommand::new("chmod")
+        .args(["+x", script.to_str().unwrap()])
+        .status()
+        .unwrap()
+        .success());
+    let address = symbol_addr(&binary, "fixture_target");
+
+    atosl_for(&binary)
+        .env("DEBUGINFOD_CACHE_PATH", workspace.join("no-cache"))
+        .args(["--symbol-command", script.to_str().unwrap(), &hex(address)])
+        .assert()
+        .success()
+        .stdout(predicates::str::contains("(in app.debug) (f.c:1)"));
 }
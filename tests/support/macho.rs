@@ -0,0 +1,454 @@
+//! A writer for the small Mach-O images the tests need where Apple's linker
+//! and dsymutil are not available: 64-bit little-endian images with segments,
+//! a symbol table (which also carries the STABS debug map), the indirect
+//! symbol table of symbol stubs, and an `LC_UUID`. Also a dyld shared cache
+//! to hold such an image.
+
+use object::macho;
+use object::{Object, ObjectSection};
+use std::fs;
+use std::path::Path;
+
+pub struct Section {
+    pub name: String,
+    pub address: u64,
+    pub data: Vec<u8>,
+    pub flags: u32,
+    pub reserved1: u32,
+    pub reserved2: u32,
+}
+
+impl Section {
+    pub fn new(name: &str, address: u64, data: impl Into<Vec<u8>>) -> Self {
+        Self {
+            name: name.to_string(),
+            address,
+            data: data.into(),
+            flags: 0,
+            reserved1: 0,
+            reserved2: 0,
+        }
+    }
+}
+
+struct Segment {
+    name: String,
+    vmaddr: u64,
+    sections: Vec<Section>,
+}
+
+struct Symbol {
+    name: String,
+    n_type: u8,
+    n_sect: u8,
+    n_value: u64,
+}
+
+pub struct MachO {
+    filetype: u32,
+    cputype: u32,
+    cpusubtype: u32,
+    segments: Vec<Segment>,
+    symbols: Vec<Symbol>,
+    indirect_symbols: Vec<u32>,
+    uuid: Option<[u8; 16]>,
+}
+
+const HEADER_SIZE: usize = 32;
+const SEGMENT_SIZE: usize = 72;
+const SECTION_SIZE: usize = 80;
+const SYMTAB_SIZE: usize = 24;
+const DYSYMTAB_SIZE: usize = 80;
+const UUID_SIZE: usize = 24;
+
+impl MachO {
+    pub fn new(filetype: u32, cputype: u32, cpusubtype: u32) -> Self {
+        Self {
+            filetype,
+            cputype,
+            cpusubtype,
+            segments: Vec::new(),
+            symbols: Vec::new(),
+            indirect_symbols: Vec::new(),
+            uuid: None,
+        }
+    }
+
+    /// Adds a segment. Sections are numbered from 1 in the order they are
+    /// added, across segments, which is what `n_sect` refers to.
+    pub fn segment(&mut self, name: &str, vmaddr: u64, sections: Vec<Section>) -> &mut Self {
+        self.segments.push(Segment {
+            name: name.to_string(),
+            vmaddr,
+            sections,
+        });
+        self
+    }
+
+    pub fn symbol(&mut self, name: &str, n_type: u8, n_sect: u8, n_value: u64) -> &mut Self {
+        self.symbols.push(Symbol {
+            name: name.to_string(),
+            n_type,
+            n_sect,
+            n_value,
+        });
+        self
+    }
+
+    /// Adds the next entry of the indirect symbol table, an index into the
+    /// symbol table.
+    pub fn indirect_symbol(&mut self, index: u32) -> &mut Self {
+        self.indirect_symbols.push(index);
+        self
+    }
+
+    pub fn uuid(&mut self, uuid: [u8; 16]) -> &mut Self {
+        self.uuid = Some(uuid);
+        self
+    }
+
+    /// The image as it is laid out at file offset `base` of the file that
+    /// holds it: 0 for a file of its own, more inside a dyld shared cache.
+    pub fn build(&self, base: u64) -> Vec<u8> {
+        let section_count = self
+            .segments
+            .iter()
+            .map(|segment| segment.sections.len())
+            .sum::<usize>();
+        let ncmds = self.segments.len() + 3 + usize::from(self.uuid.is_some());
+        let sizeofcmds = (self.segments.len() + 1) * SEGMENT_SIZE
+            + section_count * SECTION_SIZE
+            + SYMTAB_SIZE
+            + DYSYMTAB_SIZE
+            + self.uuid.map_or(0, |_| UUID_SIZE);
+
+        // Section contents follow the load commands, then the link-edit data.
+        let mut offset = align(HEADER_SIZE + sizeofcmds);
+        let mut section_offsets = Vec::new();
+        for section in self.segments.iter().flat_map(|segment| &segment.sections) {
+            section_offsets.push(offset);
+            offset = align(offset + section.data.len());
+        }
+        let mut strings = vec![0u8];
+        let mut nlists = Vec::new();
+        for symbol in &self.symbols {
+            let n_strx = match symbol.name.is_empty() {
+                true => 0,
+                false => {
+                    strings.extend_from_slice(symbol.name.as_bytes());
+                    strings.push(0);
+                    strings.len() - symbol.name.len() - 1
+                }
+            };
+            put_u32(&mut nlists, n_strx as u32);
+            nlists.extend_from_slice(&[symbol.n_type, symbol.n_sect, 0, 0]);
+            put_u64(&mut nlists, symbol.n_value);
+        }
+        let symoff = offset;
+        let stroff = symoff + nlists.len();
+        let indirectsymoff = stroff + strings.len();
+        let end = indirectsymoff + 4 * self.indirect_symbols.len();
+        let file = |offset: usize| base + offset as u64;
+
+        let mut out = Vec::new();
+        for value in [
+            macho::MH_MAGIC_64,
+            self.cputype,
+            self.cpusubtype,
+            self.filetype,
+            ncmds as u32,
+            sizeofcmds as u32,
+            0,
+            0,
+        ] {
+            put_u32(&mut out, value);
+        }
+
+        let mut ordinal = 0;
+        for segment in &self.segments {
+            let offsets = &section_offsets[ordinal..ordinal + segment.sections.len()];
+            let vmend = segment
+                .sections
+                .iter()
+                .map(|section| section.address + section.data.len() as u64)
+                .max()
+                .unwrap_or(segment.vmaddr);
+            let fileoff = offsets.first().copied().unwrap_or(0);
+            let fileend = segment
+                .sections
+                .iter()
+                .zip(offsets)
+                .map(|(section, offset)| offset + section.data.len())
+                .max()
+                .unwrap_or(0);
+            put_segment(
+                &mut out,
+                &segment.name,
+                segment.vmaddr,
+                vmend - segment.vmaddr,
+                file(fileoff),
+                (fileend - fileoff) as u64,
+                segment.sections.len(),
+            );
+            for (section, &offset) in segment.sections.iter().zip(offsets) {
+                put_name(&mut out, &section.name);
+                put_name(&mut out, &segment.name);
+                put_u64(&mut out, section.address);
+                put_u64(&mut out, section.data.len() as u64);
+                for value in [
+                    file(offset) as u32,
+                    0,
+                    0,
+                    0,
+                    section.flags,
+                    section.reserved1,
+                    section.reserved2,
+                    0,
+                ] {
+                    put_u32(&mut out, value);
+                }
+            }
+            ordinal += segment.sections.len();
+        }
+
+        // __LINKEDIT sits above every other segment.
+        let linkedit = self
+            .segments
+            .iter()
+            .flat_map(|segment| &segment.sections)
+            .map(|section| section.address + section.data.len() as u64)
+            .max()
+            .unwrap_or(0);
+        let linkedit = (linkedit + 0xfff) & !0xfff;
+        put_segment(
+            &mut out,
+            "__LINKEDIT",
+            linkedit,
+            (end - symoff) as u64,
+            file(symoff),
+            (end - symoff) as u64,
+            0,
+        );
+
+        for value in [
+            macho::LC_SYMTAB,
+            SYMTAB_SIZE as u32,
+            file(symoff) as u32,
+            self.symbols.len() as u32,
+            file(stroff) as u32,
+            strings.len() as u32,
+        ] {
+            put_u32(&mut out, value);
+        }
+        put_u32(&mut out, macho::LC_DYSYMTAB);
+        put_u32(&mut out, DYSYMTAB_SIZE as u32);
+        for index in 0..18 {
+            put_u32(
+                &mut out,
+                match index {
+                    1 => self.symbols.len() as u32,
+                    12 if !self.indirect_symbols.is_empty() => file(indirectsymoff) as u32,
+                    13 => self.indirect_symbols.len() as u32,
+                    _ => 0,
+                },
+            );
+        }
+        if let Some(uuid) = self.uuid {
+            put_u32(&mut out, macho::LC_UUID);
+            put_u32(&mut out, UUID_SIZE as u32);
+            out.extend_from_slice(&uuid);
+        }
+        assert_eq!(out.len(), HEADER_SIZE + sizeofcmds);
+
+        for (section, &offset) in self
+            .segments
+            .iter()
+            .flat_map(|segment| &segment.sections)
+            .zip(&section_offsets)
+        {
+            out.resize(offset, 0);
+            out.extend_from_slice(&section.data);
+        }
+        out.resize(symoff, 0);
+        out.extend_from_slice(&nlists);
+        out.extend_from_slice(&strings);
+        for index in &self.indirect_symbols {
+            put_u32(&mut out, *index);
+        }
+        out
+    }
+
+    pub fn write(&self, path: &Path) {
+        fs::write(path, self.build(0)).unwrap();
+    }
+}
+
+/// The DWARF sections of the ELF file at `path`, renamed the way a dSYM or a
+/// Mach-O object names them (`.debug_info` becomes `__debug_info`). A linked
+/// ELF file's DWARF needs no relocation, so it can stand in for either.
+pub fn dwarf_sections(path: &Path) -> Vec<Section> {
+    let bytes = fs::read(path).unwrap();
+    let object = object::File::parse(bytes.as_slice()).unwrap();
+    object
+        .sections()
+        .filter_map(|section| {
+            let name = section.name().ok()?.strip_prefix(".debug_")?;
+            let mut name = format!("__debug_{name}");
+            name.truncate(16);
+            Some(Section::new(&name, 0, section.data().ok()?))
+        })
+        .collect()
+}
+
+/// The address and contents of the ELF section `name` of the file at `path`.
+pub fn elf_section(path: &Path, name: &str) -> (u64, Vec<u8>) {
+    let bytes = fs::read(path).unwrap();
+    let object = object::File::parse(bytes.as_slice()).unwrap();
+    let section = object.section_by_name(name).unwrap();
+    (section.address(), section.data().unwrap().to_vec())
+}
+
+/// Writes a dyld shared cache at `path` split the way device caches are: the
+/// main file lists the one image, `path.01` holds `image` (whose `__TEXT`
+/// must start at `address`) and `path.symbols` holds `local_symbols`, which
+/// the image's own symbol table no longer carries.
+pub fn write_dyld_cache(
+    path: &Path,
+    image: &MachO,
+    address: u64,
+    install_name: &str,
+    local_symbols: &[(&str, u64)],
+) {
+    let main_uuid = [1; 16];
+    let subcache_uuid = [2; 16];
+    let symbols_uuid = [3; 16];
+    // The main file maps the page below the subcache, which maps the page
+    // below the image and the image itself.
+    let subcache_base = address - 0x1000;
+    let cache_base = subcache_base - 0x1000;
+
+    let mut main = cache_header(main_uuid, 1);
+    put_mapping(&mut main, cache_base, 0x1000);
+    let subcaches = main.len() as u32;
+    main[0x188..0x18c].copy_from_slice(&subcaches.to_le_bytes());
+    main[0x18c..0x190].copy_from_slice(&1u32.to_le_bytes());
+    main[0x190..0x1a0].copy_from_slice(&symbols_uuid);
+    main.extend_from_slice(&subcache_uuid);
+    put_u64(&mut main, 0x1000);
+    let images = main.len() as u32;
+    main[0x1c0..0x1c4].copy_from_slice(&images.to_le_bytes());
+    main[0x1c4..0x1c8].copy_from_slice(&1u32.to_le_bytes());
+    let path_offset = main.len() + 32;
+    for value in [address, 0, 0, path_offset as u64] {
+        put_u64(&mut main, value);
+    }
+    main.extend_from_slice(install_name.as_bytes());
+    main.push(0);
+
+    let mut subcache = cache_header(subcache_uuid, 1);
+    put_mapping(&mut subcache, subcache_base, 0x10_0000);
+    subcache.resize(0x1000, 0);
+    subcache.extend_from_slice(&image.build(0x1000));
+
+    // dyld_cache_local_symbols_info, one entry keyed by the image's offset
+    // from the cache base, the nlists and their strings.
+    let mut symbols = cache_header(symbols_uuid, 0);
+    let info = symbols.len();
+    let entries = 24;
+    let nlists = entries + 16;
+    let strings_offset = nlists + 16 * local_symbols.len();
+    let mut strings = vec![0u8];
+    let mut table = Vec::new();
+    for (name, value) in local_symbols {
+        put_u32(&mut table, strings.len() as u32);
+        table.extend_from_slice(&[macho::N_SECT, 1, 0, 0]);
+        put_u64(&mut table, *value);
+        strings.extend_from_slice(name.as_bytes());
+        strings.push(0);
+    }
+    for value in [
+        nlists,
+        local_symbols.len(),
+        strings_offset,
+        strings.len(),
+        entries,
+        1,
+    ] {
+        put_u32(&mut symbols, value as u32);
+    }
+    put_u64(&mut symbols, address - cache_base);
+    put_u32(&mut symbols, 0);
+    put_u32(&mut symbols, local_symbols.len() as u32);
+    symbols.extend_from_slice(&table);
+    symbols.extend_from_slice(&strings);
+    let size = (symbols.len() - info) as u64;
+    symbols[0x48..0x50].copy_from_slice(&(info as u64).to_le_bytes());
+    symbols[0x50..0x58].copy_from_slice(&size.to_le_bytes());
+
+    let with_suffix = |suffix: &str| {
+        let mut name = path.as_os_str().to_owned();
+        name.push(suffix);
+        name
+    };
+    fs::write(path, main).unwrap();
+    fs::write(with_suffix(".01"), subcache).unwrap();
+    fs::write(with_suffix(".symbols"), symbols).unwrap();
+}
+
+/// An arm64e cache header whose mapping table, of `mappings` entries, follows
+/// it directly.
+fn cache_header(uuid: [u8; 16], mappings: u32) -> Vec<u8> {
+    let mut header = vec![0u8; 0x1c8];
+    header[..16].copy_from_slice(b"dyld_v1  arm64e\0");
+    header[0x10..0x14].copy_from_slice(&0x1c8u32.to_le_bytes());
+    header[0x14..0x18].copy_from_slice(&mappings.to_le_bytes());
+    header[0x58..0x68].copy_from_slice(&uuid);
+    header
+}
+
+/// A mapping of `size` bytes at `address` from the start of the file.
+fn put_mapping(out: &mut Vec<u8>, address: u64, size: u64) {
+    for value in [address, size, 0] {
+        put_u64(out, value);
+    }
+    put_u32(out, 5);
+    put_u32(out, 5);
+}
+
+fn align(offset: usize) -> usize {
+    (offset + 15) & !15
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_name(out: &mut Vec<u8>, name: &str) {
+    let mut field = [0u8; 16];
+    field[..name.len()].copy_from_slice(name.as_bytes());
+    out.extend_from_slice(&field);
+}
+
+fn put_segment(
+    out: &mut Vec<u8>,
+    name: &str,
+    vmaddr: u64,
+    vmsize: u64,
+    fileoff: u64,
+    filesize: u64,
+    nsects: usize,
+) {
+    put_u32(out, macho::LC_SEGMENT_64);
+    put_u32(out, (SEGMENT_SIZE + nsects * SECTION_SIZE) as u32);
+    put_name(out, name);
+    for value in [vmaddr, vmsize, fileoff, filesize] {
+        put_u64(out, value);
+    }
+    for value in [7, 5, nsects as u32, 0] {
+        put_u32(out, value);
+    }
+}
@@ -0,0 +1,234 @@
+//! Setup shared by the CLI tests: C programs built in a scratch directory,
+//! addresses read back out of what was built, and small stand-in servers.
+
+pub mod macho;
+
+use assert_cmd::Command;
+use object::{Object, ObjectSection, ObjectSymbol};
+use serde_json::Value;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command as ProcessCommand;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tempfile::TempDir;
+
+/// The program most tests symbolize: `fixture_target` on line 1 of `f.c`.
+pub const TARGET_SOURCE: &str =
+    "int fixture_target(void){return 7;}\nint main(void){return fixture_target();}\n";
+
+/// The `atosl` binary of this build.
+pub fn atosl() -> Command {
+    Command::cargo_bin("atosl").unwrap()
+}
+
+pub fn hex(value: u64) -> String {
+    format!("0x{value:x}")
+}
+
+/// A scratch directory that sources are written to and built in. Relative
+/// paths passed to its methods are relative to the directory.
+pub struct Workspace {
+    dir: TempDir,
+}
+
+impl Workspace {
+    pub fn new() -> Self {
+        Self {
+            dir: TempDir::new().unwrap(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.dir.path().join(path)
+    }
+
+    /// Writes `contents` to `name`, creating its parent directories.
+    pub fn write(&self, name: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    /// Runs `cc` with `args` in the workspace.
+    pub fn cc(&self, args: &[&str]) {
+        run_in(self.path(), "cc", args);
+    }
+
+    /// Writes `source` to `name` and builds it into `output` with
+    /// `cc -g -O0` and `args`.
+    pub fn build(&self, name: &str, source: &str, output: &str, args: &[&str]) -> PathBuf {
+        self.write(name, source);
+        let path = self.join(output);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        self.cc(&[&["-g", "-O0", name, "-o", output][..], args].concat());
+        path
+    }
+
+    /// Builds [`TARGET_SOURCE`] into `output`.
+    pub fn build_target(&self, output: &str) -> PathBuf {
+        self.build("f.c", TARGET_SOURCE, output, &[])
+    }
+
+    /// Builds [`TARGET_SOURCE`] into `output`, with a GNU build-id note.
+    pub fn build_target_with_id(&self, output: &str, build_id: &str) -> PathBuf {
+        let build_id = format!("-Wl,--build-id=0x{build_id}");
+        self.build("f.c", TARGET_SOURCE, output, &[&build_id])
+    }
+
+    /// Runs `objcopy` with `args` in the workspace.
+    pub fn objcopy(&self, args: &[&str]) {
+        run_in(self.path(), "objcopy", args);
+    }
+
+    /// Moves the DWARF of `binary` into `binary.debug`, as distributions
+    /// ship it, and returns the debug file.
+    pub fn split_debug(&self, binary: &str) -> PathBuf {
+        let debug = format!("{binary}.debug");
+        self.objcopy(&["--only-keep-debug", binary, &debug]);
+        self.objcopy(&["--strip-debug", binary]);
+        self.join(debug)
+    }
+}
+
+fn run_in(dir: &Path, program: &str, args: &[&str]) {
+    let status = ProcessCommand::new(program)
+        .args(args)
+        .current_dir(dir)
+        .status()
+        .unwrap();
+    assert!(status.success(), "{program} {args:?} failed");
+}
+
+fn with_object<T>(path: &Path, read: impl FnOnce(&object::File<'_>) -> T) -> T {
+    let bytes = fs::read(path).unwrap();
+    read(&object::File::parse(bytes.as_slice()).unwrap())
+}
+
+/// The address of the first symbol whose name contains `name`.
+pub fn symbol_addr(path: &Path, name: &str) -> u64 {
+    with_object(path, |object| {
+        object
+            .symbols()
+            .chain(object.dynamic_symbols())
+            .find(|symbol| symbol.name().map(|n| n.contains(name)).unwrap_or(false))
+            .map(|symbol| symbol.address())
+            .unwrap()
+    })
+}
+
+pub fn symbol_size(path: &Path, name: &str) -> u64 {
+    with_object(path, |object| {
+        object
+            .symbols()
+            .find(|symbol| symbol.name() == Ok(name))
+            .map(|symbol| symbol.size())
+            .unwrap()
+    })
+}
+
+/// The address of the text section, which tests pass as the load address so
+/// that addresses are looked up unchanged.
+pub fn text_addr(path: &Path) -> u64 {
+    with_object(path, |object| {
+        object
+            .section_by_name(".text")
+            .or_else(|| object.section_by_name("__text"))
+            .map(|section| section.address())
+            .unwrap()
+    })
+}
+
+/// A child process that is killed when the test ends, however it ends.
+pub struct ServerProcess(pub std::process::Child);
+
+impl ServerProcess {
+    /// Starts `atosl` with `args` and waits for the line it prints once it is
+    /// listening.
+    pub fn start(args: &[&str]) -> (Self, String) {
+        let mut server = ServerProcess(
+            ProcessCommand::new(assert_cmd::cargo::cargo_bin("atosl"))
+                .args(args)
+                .stdout(std::process::Stdio::piped())
+                .spawn()
+                .unwrap(),
+        );
+        let mut banner = String::new();
+        BufReader::new(server.0.stdout.take().unwrap())
+            .read_line(&mut banner)
+            .unwrap();
+        (server, banner)
+    }
+}
+
+impl Drop for ServerProcess {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// A minimal HTTP/1.1 server on localhost that answers GETs for `routes` and
+/// 404s everything else, counting requests.
+pub fn serve_http(routes: Vec<(String, Vec<u8>)>) -> (String, Arc<AtomicUsize>) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&requests);
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else {
+                continue;
+            };
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            reader.read_line(&mut request_line).unwrap();
+            let mut header = String::new();
+            while reader.read_line(&mut header).unwrap() > 2 {
+                header.clear();
+            }
+            let path = request_line.split_whitespace().nth(1).unwrap_or_default();
+            let (status, body) = match routes.iter().find(|(route, _)| route == path) {
+                Some((_, body)) => ("200 OK", body.as_slice()),
+                None => ("404 Not Found", &b""[..]),
+            };
+            let _ = write!(
+                stream,
+                "HTTP/1.1 {status}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            let _ = stream.write_all(body);
+        }
+    });
+    (url, requests)
+}
+
+/// `atosl -o <binary> -l <text address>`, which looks addresses read from
+/// `binary` up unchanged.
+pub fn atosl_for(binary: &Path) -> Command {
+    let mut command = atosl();
+    command.args([
+        "-o",
+        binary.to_str().unwrap(),
+        "-l",
+        &hex(text_addr(binary)),
+    ]);
+    command
+}
+
+/// Runs `command`, which must succeed, and parses its output as JSON.
+pub fn json_output(command: &mut Command) -> Value {
+    serde_json::from_slice(&command.assert().success().get_output().stdout).unwrap()
+}
+
+/// Runs `command`, which must succeed, and returns its output.
+pub fn text_output(command: &mut Command) -> String {
+    String::from_utf8(command.assert().success().get_output().stdout.clone()).unwrap()
+}